@@ -0,0 +1,363 @@
+//! Derive macros for hag_website.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{meta::ParseNestedMeta, parse_macro_input, Data, DeriveInput, Fields, GenericArgument, LitStr, Path, PathArguments, Token, Type};
+
+/// Implement `DatabaseModel`, with `fields()` generated from the fields of the struct.
+///
+/// The type of each field is mapped to a `ModelValueType`:
+/// strings to `String`, integers and floats to `Number`, `bool` to `Boolean`, `Vec`s, slices and sets to `Array`,
+/// and anything else, such as maps or nested structs, to `Object`.
+/// `Option` fields use the type they wrap, and are optional.
+///
+/// Fields are named as serde serializes them, following `#[serde(rename = "...")]` and `#[serde(rename_all = "...")]`.
+/// Fields with `#[serde(skip)]` or `#[serde(skip_serializing)]` are never stored, so are left out,
+/// and fields with `#[serde(skip_serializing_if = "...")]` may be missing, so are optional.
+///
+/// Attributes on the struct:
+/// - `#[model(table = "name")]`, required, the table entries are stored in.
+/// - `#[model(timestamps)]`, `#[model(soft_delete)]` and `#[model(revisions)]`, opt into the fields maintained by `Repository`.
+/// - `#[model(expiry = path)]` and `#[model(cache = path)]`, functions returning the `Option<Expiry>` and `Option<CachePolicy>` of the model.
+///
+/// Attributes on fields:
+/// - `#[model(unique)]`, no two entries may share a value for the field.
+/// - `#[model(indexed)]`, the field is frequently looked up.
+/// - `#[model(searchable)]`, the field is indexed for full-text search.
+/// - `#[model(sensitive)]`, the field holds a secret, such as a password hash, so its values are redacted from the audit log.
+/// - `#[model(optional)]`, the field may be missing or null.
+/// - `#[model(skip)]`, the field isn't stored, it must also have `#[serde(skip)]` so it isn't serialized either.
+/// - `#[model(kind = Variant)]`, store the field as the given `ModelValueType`, such as enums serialized as strings.
+///
+/// ```ignore
+/// #[derive(DatabaseModel, Deserialize, Serialize)]
+/// #[model(table = "sessions", timestamps)]
+/// pub struct Session {
+///     #[model(unique)]
+///     pub session_id: String,
+///     #[model(indexed)]
+///     pub uid: String
+/// }
+/// ```
+#[proc_macro_derive(DatabaseModel, attributes(model))]
+pub fn derive_database_model(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match database_model(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into()
+    }
+}
+
+#[derive(Default)]
+struct ModelAttributes {
+    table: Option<LitStr>,
+    timestamps: bool,
+    soft_delete: bool,
+    revisions: bool,
+    expiry: Option<Path>,
+    cache: Option<Path>,
+    /// From `#[serde(rename_all = "...")]`.
+    rename_all: Option<LitStr>
+}
+
+#[derive(Default)]
+struct FieldAttributes {
+    unique: bool,
+    indexed: bool,
+    searchable: bool,
+    sensitive: bool,
+    optional: bool,
+    skip: bool,
+    kind: Option<proc_macro2::Ident>,
+    /// From `#[serde(...)]`.
+    rename: Option<LitStr>,
+    serde_skip: bool,
+    serde_optional: bool
+}
+
+fn database_model(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let mut model = ModelAttributes::default();
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("model")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("table") {
+                model.table = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("timestamps") {
+                model.timestamps = true;
+            } else if meta.path.is_ident("soft_delete") {
+                model.soft_delete = true;
+            } else if meta.path.is_ident("revisions") {
+                model.revisions = true;
+            } else if meta.path.is_ident("expiry") {
+                model.expiry = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("cache") {
+                model.cache = Some(meta.value()?.parse()?);
+            } else {
+                return Err(meta.error("unknown model attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename_all") {
+                model.rename_all = Some(serde_name(&meta)?);
+            } else {
+                skip_serde_value(&meta)?;
+            }
+            Ok(())
+        })?;
+    }
+    let Some(table) = model.table else {
+        return Err(syn::Error::new(Span::call_site(), "missing #[model(table = \"...\")]"));
+    };
+
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new(Span::call_site(), "DatabaseModel can only be derived for structs"));
+    };
+    let Fields::Named(named) = &data.fields else {
+        return Err(syn::Error::new(Span::call_site(), "DatabaseModel can only be derived for structs with named fields"));
+    };
+
+    let (mut fields, mut optional, mut unique, mut indexed, mut searchable, mut sensitive) = (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for field in &named.named {
+        let mut attrs = FieldAttributes::default();
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("model")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("unique") {
+                    attrs.unique = true;
+                } else if meta.path.is_ident("indexed") {
+                    attrs.indexed = true;
+                } else if meta.path.is_ident("searchable") {
+                    attrs.searchable = true;
+                } else if meta.path.is_ident("sensitive") {
+                    attrs.sensitive = true;
+                } else if meta.path.is_ident("optional") {
+                    attrs.optional = true;
+                } else if meta.path.is_ident("skip") {
+                    attrs.skip = true;
+                } else if meta.path.is_ident("kind") {
+                    attrs.kind = Some(meta.value()?.parse()?);
+                } else {
+                    return Err(meta.error("unknown model field attribute"));
+                }
+                Ok(())
+            })?;
+        }
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    attrs.rename = Some(serde_name(&meta)?);
+                } else if meta.path.is_ident("skip") || meta.path.is_ident("skip_serializing") {
+                    attrs.serde_skip = true;
+                } else if meta.path.is_ident("skip_serializing_if") {
+                    attrs.serde_optional = true;
+                    skip_serde_value(&meta)?;
+                } else if meta.path.is_ident("flatten") {
+                    return Err(meta.error("flattened fields aren't supported by DatabaseModel"));
+                } else {
+                    skip_serde_value(&meta)?;
+                }
+                Ok(())
+            })?;
+        }
+        if attrs.skip && !attrs.serde_skip {
+            return Err(syn::Error::new_spanned(field, "#[model(skip)] fields must also have #[serde(skip)], otherwise they are still stored"));
+        }
+        if attrs.serde_skip {
+            continue;
+        }
+
+        let name = match (&attrs.rename, &model.rename_all) {
+            (Some(rename), _) => rename.value(),
+            (None, rename_all) => {
+                let name = field.ident.as_ref().unwrap().to_string();
+                let name = name.strip_prefix("r#").unwrap_or(&name).to_string();
+                match rename_all {
+                    Some(rule) => rename_field(&name, rule)?,
+                    None => name
+                }
+            }
+        };
+        let (ty, is_option) = match option_inner(&field.ty) {
+            Some(inner) => (inner, true),
+            None => (&field.ty, false)
+        };
+        let kind = attrs.kind.unwrap_or_else(|| value_type(ty));
+        fields.push(quote!(crate::core::models::ModelValueType::#kind { field: #name }));
+
+        if attrs.optional || attrs.serde_optional || is_option {
+            optional.push(name.clone());
+        }
+        if attrs.unique {
+            unique.push(name.clone());
+        }
+        if attrs.searchable {
+            searchable.push(name.clone());
+        }
+        if attrs.sensitive {
+            sensitive.push(name.clone());
+        }
+        if attrs.indexed {
+            indexed.push(name);
+        }
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let timestamps = model.timestamps.then(|| quote! {
+        fn timestamps() -> bool {
+            true
+        }
+    });
+    let soft_delete = model.soft_delete.then(|| quote! {
+        fn soft_delete() -> bool {
+            true
+        }
+    });
+    let revisions = model.revisions.then(|| quote! {
+        fn revisions() -> bool {
+            true
+        }
+    });
+    let expiry = model.expiry.map(|path| quote! {
+        fn expiry() -> Option<crate::core::database::expiry::Expiry> {
+            #path()
+        }
+    });
+    let cache = model.cache.map(|path| quote! {
+        fn cache() -> Option<crate::core::database::cache::CachePolicy> {
+            #path()
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics crate::core::database::DatabaseModel for #ident #ty_generics #where_clause {
+            fn table() -> &'static str {
+                #table
+            }
+
+            fn fields() -> Vec<crate::core::models::ModelValueType> {
+                vec![#(#fields),*]
+            }
+
+            fn optional() -> Vec<&'static str> {
+                vec![#(#optional),*]
+            }
+
+            fn unique() -> Vec<&'static str> {
+                vec![#(#unique),*]
+            }
+
+            fn indexed() -> Vec<&'static str> {
+                vec![#(#indexed),*]
+            }
+
+            fn searchable() -> Vec<&'static str> {
+                vec![#(#searchable),*]
+            }
+
+            fn sensitive() -> Vec<&'static str> {
+                vec![#(#sensitive),*]
+            }
+
+            #timestamps
+            #soft_delete
+            #revisions
+            #expiry
+            #cache
+        }
+    })
+}
+
+/// Name given by a serde `rename` or `rename_all` attribute.
+/// Separate names for serializing and deserializing are only accepted if they're the same, as entries are both written and read.
+fn serde_name(meta: &ParseNestedMeta) -> syn::Result<LitStr> {
+    if meta.input.peek(Token![=]) {
+        return meta.value()?.parse();
+    }
+
+    let (mut serialize, mut deserialize) = (None, None);
+    meta.parse_nested_meta(|inner| {
+        if inner.path.is_ident("serialize") {
+            serialize = Some(inner.value()?.parse::<LitStr>()?);
+        } else if inner.path.is_ident("deserialize") {
+            deserialize = Some(inner.value()?.parse::<LitStr>()?);
+        } else {
+            return Err(inner.error("unknown serde rename attribute"));
+        }
+        Ok(())
+    })?;
+    match (serialize, deserialize) {
+        (Some(serialize), Some(deserialize)) if serialize.value() == deserialize.value() => Ok(serialize),
+        _ => Err(meta.error("DatabaseModel fields must be serialized and deserialized under the same name"))
+    }
+}
+
+/// Consume the value of a serde attribute which doesn't affect the schema, such as `default` or `with = "..."`.
+fn skip_serde_value(meta: &ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(Token![=]) {
+        meta.value()?.parse::<syn::Expr>()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        let _content;
+        syn::parenthesized!(_content in meta.input);
+    }
+    Ok(())
+}
+
+/// Apply a serde `rename_all` rule to the snake case `field`, as serde does for struct fields.
+fn rename_field(field: &str, rule: &LitStr) -> syn::Result<String> {
+    let pascal = || field.split('_').map(|word| {
+        let mut chars = word.chars();
+        chars.next().map(|first| first.to_uppercase().chain(chars).collect::<String>()).unwrap_or_default()
+    }).collect::<String>();
+
+    Ok(match rule.value().as_str() {
+        "lowercase" | "snake_case" => field.to_string(),
+        "UPPERCASE" | "SCREAMING_SNAKE_CASE" => field.to_ascii_uppercase(),
+        "PascalCase" => pascal(),
+        "camelCase" => {
+            let pascal = pascal();
+            let mut chars = pascal.chars();
+            chars.next().map(|first| first.to_lowercase().chain(chars).collect()).unwrap_or_default()
+        },
+        "kebab-case" => field.replace('_', "-"),
+        "SCREAMING-KEBAB-CASE" => field.to_ascii_uppercase().replace('_', "-"),
+        _ => return Err(syn::Error::new_spanned(rule, "unknown serde rename_all rule"))
+    })
+}
+
+/// Type wrapped by `ty`, if it is an `Option`.
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) => args.args.iter().find_map(|arg| match arg {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None
+        }),
+        _ => None
+    }
+}
+
+/// Variant of `ModelValueType` for values of `ty`.
+fn value_type(ty: &Type) -> proc_macro2::Ident {
+    let kind = match ty {
+        Type::Reference(reference) => return value_type(&reference.elem),
+        Type::Array(_) | Type::Slice(_) | Type::Tuple(_) => "Array",
+        Type::Path(path) => match path.path.segments.last().map(|segment| segment.ident.to_string()).as_deref() {
+            Some("String" | "str" | "char") => "String",
+            Some("u8" | "u16" | "u32" | "u64" | "u128" | "usize" | "i8" | "i16" | "i32" | "i64" | "i128" | "isize" | "f32" | "f64") => "Number",
+            Some("bool") => "Boolean",
+            Some("Vec" | "VecDeque" | "HashSet" | "BTreeSet") => "Array",
+            _ => "Object"
+        },
+        _ => "Object"
+    };
+    proc_macro2::Ident::new(kind, Span::call_site())
+}
@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 #[derive(clap::Parser)]
 pub struct CLI {
     #[arg(value_enum, long)]
@@ -5,17 +7,59 @@ pub struct CLI {
     pub db: ArgDb,
 
     #[arg(long)]
+    /// Location of the database, for persistent this is the directory tables are stored in,
+    /// for production it is the path of the SQLite database file.
     /// Not required for volatile
     pub db_location: Option<String>,
 
-    /// If present, will create the required tables for your database.
-    /// Will default to true if Volatile is used.
+    /// Seed the database with dummy data, loaded from fixtures and optionally generated.
     #[arg(long, default_value_t = false)]
-    pub setup_db: bool,
+    pub dummy_db: bool,
+
+    /// Directory holding seed fixtures, each file is named after the table it is loaded into.
+    #[arg(long, default_value = "fixtures")]
+    pub fixtures: PathBuf,
+
+    /// Number of random accounts to generate when seeding.
+    #[arg(long, default_value_t = 0)]
+    pub dummy_accounts: usize,
+
+    /// Seed for generated data, the same seed always generates the same data.
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
 
-    /// Generate dummy data for the database.
+    /// Cache frequent reads, such as session lookups, in memory.
     #[arg(long, default_value_t = false)]
-    pub dummy_db: bool
+    pub cache: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>
+}
+
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// Apply pending database migrations, then exit.
+    Migrate {
+        /// Only apply migrations up to and including this version.
+        #[arg(long)]
+        to: Option<u32>
+    },
+    /// Roll back applied database migrations, then exit.
+    Rollback {
+        /// Roll back every migration after this version, by default only the latest migration is rolled back.
+        #[arg(long)]
+        to: Option<u32>
+    },
+    /// Export every table to a json file, then exit.
+    Dump {
+        /// Path of the file to write.
+        file: PathBuf
+    },
+    /// Replace every table with those from a file created by `dump`, then exit.
+    Restore {
+        /// Path of the file to read.
+        file: PathBuf
+    }
 }
 
 #[derive(clap::ValueEnum, Clone, PartialEq)]
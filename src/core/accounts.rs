@@ -1,13 +1,16 @@
-use super::{models::account::Account, database, validation::{validate_name, validate_email, validate_password}};
+use super::{models::account::Account, database::{Database, error::DatabaseError, repository::Repository}, validation::{validate_name, validate_email, validate_password}};
 use base64ct::{Base64, Encoding};
 use sha2::{Sha256, Digest};
 
+const ACCOUNT_EXISTS: &str = "Account already exists!";
+
 /// Attempts to find an account.
 /// 
 /// Returns the `uid` if successful.
 /// 
 /// # Arguments
 /// 
+/// * `db` - [`Database`] to query, may be the database given to a transaction.
 /// * `email` - [`String`] containing a valid email address.
 /// 
 /// # Examples
@@ -18,33 +21,37 @@ use sha2::{Sha256, Digest};
 /// let user = Account {
 ///     // ...
 /// }
-/// let uid = accounts::register(user, true).await?;
-/// let found = accounts::exists(user.email).await;
+/// let uid = accounts::register(db, user, true).await?;
+/// let found = accounts::exists(db, user.email).await;
 /// 
 /// assert_eq!(Some(uid), found)
 /// ```
-pub async fn exists(email: String) -> Option<String> {
-    let db = database::get();
-
-    let filter = db.filter()
+pub async fn exists(db: &dyn Database, email: String) -> Option<String> {
+    let accounts = Repository::<Account>::new(db);
+    let filter = accounts.filter()
         // Account with matching email
         .eq("email", email.into())
         .build();
 
     // Request entry with a matching email and password
-    let result = db.get("accounts", filter).await;
+    let result = accounts.get(filter).await;
 
     // Return Some(uid) if result is Ok, else None
-    if let Ok(account) = result {
-        return Some(account["uid"].as_str().unwrap().to_string());
+    match result {
+        Ok(account) => Some(account.uid),
+        Err(DatabaseError::NotFound) => None,
+        Err(err) => {
+            log::error!("Failed to query accounts. {err}");
+            None
+        }
     }
-    None
 }
 
 /// Checks that the provided `pass_hash` matches that of the [`Account`] corresponding to `uid`
 /// 
 /// # Arguments
 /// 
+/// * `db` - [`Database`] to query.
 /// * `uid` - [`String`] containing the users unique identifier.
 /// * `pass_hash` - [`String`] containing the hash to compare against.
 /// 
@@ -56,19 +63,23 @@ pub async fn exists(email: String) -> Option<String> {
 /// 
 /// 
 /// ```
-pub async fn verify_pass_hash(uid: String, pass_hash: String) -> bool {
-    let db = database::get();
+pub async fn verify_pass_hash(db: &dyn Database, uid: String, pass_hash: String) -> bool {
+    let accounts = Repository::<Account>::new(db);
 
-    let filter = db.filter()
-        .eq("uid", uid.into())
+    let filter = accounts.filter()
+        .eq("uid", uid.clone().into())
         .eq("pass_hash", pass_hash.into())
         .build();
 
-    let result = db.find("accounts", filter).await;
+    let result = accounts.find(filter).await;
 
     match result {
         Ok(_) => true,
-        Err(_) => false
+        Err(DatabaseError::NotFound) => false,
+        Err(err) => {
+            log::error!("Failed to verify password for UID {uid}. {err}");
+            false
+        }
     }
 }
 
@@ -78,6 +89,7 @@ pub async fn verify_pass_hash(uid: String, pass_hash: String) -> bool {
 /// 
 /// # Arguments
 /// 
+/// * `db` - [`Database`] to query.
 /// * `email` - [`String`] containing a valid email address.
 /// * `pass_hash` - [`String`] containg the [`Base64`] encoded [`Sha256`] hash of the users password
 /// 
@@ -92,7 +104,7 @@ pub async fn verify_pass_hash(uid: String, pass_hash: String) -> bool {
 /// async fn api_login(req: Request) -> ApiResponse {
 ///     let { email, pass_hash } = req.args;
 ///     
-///     match accounts::login(email, pass_hash).await {
+///     match accounts::login(&*req.state().db, email, pass_hash).await {
 ///         Some(session) => {
 ///             return ApiResponse {
 ///                 status: 200,
@@ -113,8 +125,8 @@ pub async fn verify_pass_hash(uid: String, pass_hash: String) -> bool {
 /// }
 /// 
 /// ```
-pub async fn login(email: String, pass_hash: String) -> Result<String, String> {
-    let account_id = exists(email).await;
+pub async fn login(db: &dyn Database, email: String, pass_hash: String) -> Result<String, String> {
+    let account_id = exists(db, email).await;
     if let None = account_id {
         return Err("No account found with that email.".into())
     }
@@ -123,7 +135,7 @@ pub async fn login(email: String, pass_hash: String) -> Result<String, String> {
 
     log::debug!("Login | UID: {uid} | Pass: {pass_hash}");
 
-    if !verify_pass_hash(uid.clone(), pass_hash).await {
+    if !verify_pass_hash(db, uid.clone(), pass_hash).await {
         return Err("Incorrect password.".into())
     }
 
@@ -137,6 +149,7 @@ pub async fn login(email: String, pass_hash: String) -> Result<String, String> {
 /// 
 /// # Arguments
 /// 
+/// * `db` - [`Database`] to create the account in.
 /// * `user` - [`Account`] containing the users provided information.
 /// * `generate_id` - [`bool`], if true the `uid` in `user` will be overwritten with one provided by [accounts::uuid](super::accounts::uuid)
 /// * `should_hash` - [`bool`], if true `user.pass_hash` will be run through [accounts::hash_password][super::accounts::hash_password]. Should only be true if `user.pass_hash` is plaintext.
@@ -149,7 +162,7 @@ pub async fn login(email: String, pass_hash: String) -> Result<String, String> {
 /// async fn api_register(req: Request) -> ApiResponse {
 ///     let account = req.body::<Account>()?;
 ///     
-///     match accounts.register(account, true, true).await {
+///     match accounts.register(&*req.state().db, account, true, true).await {
 ///         Some(user_id) => {
 ///             return ApiResponse {
 ///                 status: 200,
@@ -170,7 +183,7 @@ pub async fn login(email: String, pass_hash: String) -> Result<String, String> {
 /// }
 /// 
 /// ```
-pub async fn register(mut user: Account, generate_id: bool, should_hash: bool) -> Result<String, String> {   
+pub async fn register(db: &dyn Database, mut user: Account, generate_id: bool, should_hash: bool) -> Result<String, String> {   
     
     if !validate_name(user.firstname.clone()) 
     || !validate_name(user.surname.clone())
@@ -179,13 +192,6 @@ pub async fn register(mut user: Account, generate_id: bool, should_hash: bool) -
         return Err("Invalid registration details. Please try again.".into())
     }
 
-    let db = database::get();
-
-    // Ensure account does not already exist
-    if let Some(_) = exists(user.email.clone()).await {
-        return Err("Account already exists!".into())
-    }
-
     if generate_id {
         user.uid = uuid();
     }
@@ -196,14 +202,27 @@ pub async fn register(mut user: Account, generate_id: bool, should_hash: bool) -
 
     // TODO: Validate account info
 
-    // Create a database entry for the new account.
-    let result = db.insert(
-        "accounts",
-        &serde_json::to_value(user.clone()).unwrap()
-    ).await;
-    
-    if let Err(_) = result {
-        return Err("Failed to register account, try again later.".into())
+    let email = user.email.clone();
+    let account = user.clone();
+
+    // Ensure account does not already exist, and create it within a single transaction
+    // so concurrent registrations with the same email can't both succeed.
+    let result = db.transaction(Box::new(move |db| Box::pin(async move {
+        if exists(db, email).await.is_some() {
+            return Err(DatabaseError::Conflict { field: "email".into() });
+        }
+        Repository::<Account>::new(db).insert(&account).await?;
+        Ok(())
+    }))).await;
+
+    match result {
+        Ok(_) => {},
+        // Also returned by the insert itself, as emails are unique.
+        Err(DatabaseError::Conflict { .. }) => return Err(ACCOUNT_EXISTS.into()),
+        Err(err) => {
+            log::error!("Failed to register account: {err}");
+            return Err("Failed to register account, try again later.".into())
+        }
     }
 
     Ok(user.uid)
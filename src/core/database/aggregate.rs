@@ -0,0 +1,166 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use super::{sort_compare, DatabaseFilter, FilterValue};
+
+/// How entries are grouped by an [`Aggregation`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Grouping {
+    /// Entries sharing a value for `key`.
+    Field(&'static str),
+    /// Entries whose integer `key` falls in the same `width` wide interval, keyed by its start.
+    /// Such as timestamps in unix milliseconds by day, with a width of `86_400_000`.
+    Interval { key: &'static str, width: i64 },
+    /// Entries sharing the part of the string `key` after the first `separator`, or all of it if there is none.
+    /// Such as emails by domain, with a separator of `'@'`.
+    After { key: &'static str, separator: char }
+}
+
+impl Grouping {
+    /// Key `entry` is grouped by, null if it doesn't have one.
+    pub fn key_of(&self, entry: &serde_json::Value) -> serde_json::Value {
+        let field = |key: &str| entry.get(key).cloned().unwrap_or_default();
+        match self {
+            Grouping::Field(key) => field(key),
+            Grouping::Interval { key, width } => match field(key).as_i64() {
+                Some(val) if *width > 0 => (val - val.rem_euclid(*width)).into(),
+                _ => serde_json::Value::Null
+            },
+            Grouping::After { key, separator } => match field(key).as_str() {
+                Some(val) => val.split_once(*separator).map_or(val, |(_, after)| after).into(),
+                None => serde_json::Value::Null
+            }
+        }
+    }
+}
+
+/// A value computed over each group of an [`Aggregation`].
+/// Entries missing the field are ignored, other than by [`Metric::Count`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    /// Number of entries in the group.
+    Count,
+    /// Total of a numeric field, null if no entry has it.
+    Sum(&'static str),
+    /// Smallest value of a field, null if no entry has it.
+    Min(&'static str),
+    /// Largest value of a field, null if no entry has it.
+    Max(&'static str)
+}
+
+/// Entries matching a filter split into groups, with metrics computed over each, used with [`Database::aggregate`](super::Database::aggregate).
+///
+/// Without any groupings every matching entry is in a single group, which is returned even if there are none.
+///
+/// # Examples
+/// ```
+/// let filter = db.filter().gte("created_at", since.into()).build();
+/// let signups = Aggregation::new(filter)
+///     .group_by(Grouping::Interval { key: "created_at", width: 86_400_000 })
+///     .count();
+///
+/// for group in db.aggregate("accounts", signups).await? {
+///     log::debug!("{} signups on {}", group.values[0], group.key[0]);
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Aggregation<T> {
+    pub filter: DatabaseFilter<T>,
+    pub group_by: Vec<Grouping>,
+    pub metrics: Vec<Metric>
+}
+
+impl<T> Aggregation<T> {
+    pub fn new(filter: DatabaseFilter<T>) -> Self {
+        Aggregation { filter, group_by: Vec::new(), metrics: Vec::new() }
+    }
+
+    /// Split entries by `grouping`, within the groups of earlier calls.
+    pub fn group_by(mut self, grouping: Grouping) -> Self {
+        self.group_by.push(grouping);
+        self
+    }
+
+    pub fn count(mut self) -> Self {
+        self.metrics.push(Metric::Count);
+        self
+    }
+
+    pub fn sum(mut self, key: &'static str) -> Self {
+        self.metrics.push(Metric::Sum(key));
+        self
+    }
+
+    pub fn min(mut self, key: &'static str) -> Self {
+        self.metrics.push(Metric::Min(key));
+        self
+    }
+
+    pub fn max(mut self, key: &'static str) -> Self {
+        self.metrics.push(Metric::Max(key));
+        self
+    }
+}
+
+impl Aggregation<FilterValue> {
+    /// Run the aggregation over in-memory entries.
+    pub fn apply<'a>(&self, entries: impl Iterator<Item = &'a serde_json::Value>) -> Vec<AggregateGroup> {
+        let mut groups: Vec<(Vec<serde_json::Value>, Vec<&serde_json::Value>)> = Vec::new();
+        // Json values can't be hashed, so groups are found by their serialized key.
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        for entry in entries.filter(|entry| self.filter.matches(entry)) {
+            let key: Vec<_> = self.group_by.iter().map(|grouping| grouping.key_of(entry)).collect();
+            let pos = *positions.entry(serde_json::Value::from(key.clone()).to_string()).or_insert_with(|| {
+                groups.push((key, Vec::new()));
+                groups.len() - 1
+            });
+            groups[pos].1.push(entry);
+        }
+        if groups.is_empty() && self.group_by.is_empty() {
+            groups.push((Vec::new(), Vec::new()));
+        }
+
+        groups.sort_by(|(a, _), (b, _)| {
+            a.iter().zip(b)
+                .map(|(a, b)| sort_compare(Some(a), Some(b)))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(Ordering::Equal)
+        });
+
+        groups.into_iter()
+            .map(|(key, members)| AggregateGroup {
+                key,
+                values: self.metrics.iter().map(|metric| compute(metric, &members)).collect()
+            })
+            .collect()
+    }
+}
+
+/// Compute `metric` over the entries of a group.
+fn compute(metric: &Metric, members: &[&serde_json::Value]) -> serde_json::Value {
+    let values = |key: &'static str| members.iter().filter_map(move |entry| entry.get(key).filter(|val| !val.is_null()));
+    match metric {
+        Metric::Count => members.len().into(),
+        // Totals stay integers unless a value isn't one, like SQL.
+        Metric::Sum(key) => {
+            let numbers: Vec<_> = values(key).filter(|val| val.is_number()).collect();
+            if numbers.is_empty() {
+                serde_json::Value::Null
+            } else if let Some(total) = numbers.iter().map(|val| val.as_i64()).sum::<Option<i64>>() {
+                total.into()
+            } else {
+                numbers.iter().filter_map(|val| val.as_f64()).sum::<f64>().into()
+            }
+        },
+        Metric::Min(key) => values(key).min_by(|a, b| sort_compare(Some(a), Some(b))).cloned().unwrap_or_default(),
+        Metric::Max(key) => values(key).max_by(|a, b| sort_compare(Some(a), Some(b))).cloned().unwrap_or_default()
+    }
+}
+
+/// A group of entries from [`Database::aggregate`](super::Database::aggregate).
+#[derive(Clone, Debug, PartialEq)]
+pub struct AggregateGroup {
+    /// Key of the group, one value for each grouping, in the order they were added.
+    pub key: Vec<serde_json::Value>,
+    /// Value of each metric for the group, in the order they were added.
+    pub values: Vec<serde_json::Value>
+}
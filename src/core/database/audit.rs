@@ -0,0 +1,356 @@
+use std::{ops::Deref, sync::{Arc, Mutex}};
+
+use async_std::channel::Receiver;
+use async_trait::async_trait;
+use chrono::Utc;
+
+use crate::core::models::audit::{AuditAction, AuditEntry};
+
+use super::{aggregate::{AggregateGroup, Aggregation}, changes::{Change, ChangeEvent}, error::DatabaseError, repository::Repository, sensitive, transaction::Operation, Database, DatabaseFilter, DatabaseModel, DatabaseQuery, EntryId, FilterValue, QueryResult, SortOrder, TransactionFn, REVISION};
+
+/// Records every insert, update and delete made through it in [`AuditEntry::table`], along with who made it.
+///
+/// The shared database is wrapped once when the application starts, so every write made through
+/// [`ApplicationState::db`](crate::core::state::ApplicationState::db) is recorded, as made by the application itself.
+/// Writes made on behalf of a user go through [`Audited::as_user`]:
+///
+/// ```
+/// let db = req.state().db.as_user(req.uid());
+/// sessions::create(&db, session_id, uid).await?;
+/// ```
+///
+/// Each write is recorded from the changes it made, as returned by [`Database::write`], so exactly the entries it changed are recorded.
+/// Each write is made within a transaction along with recording it, so a change is never kept without being recorded.
+/// Changes made within [`Database::transaction`] are recorded within that transaction instead.
+/// Changes to the shape of tables, and to the audit log itself, aren't recorded.
+/// Values of [`DatabaseModel::sensitive`] fields are redacted, so only the fact that they changed is recorded.
+pub struct Audited<D = Arc<dyn Database>> {
+    db: D,
+    uid: Option<String>,
+    /// Whether `db` is a transaction, which writes are recorded within rather than starting one of their own.
+    in_transaction: bool
+}
+
+impl<'d, D: Deref<Target = dyn Database + 'd> + Send + Sync> Audited<D> {
+    /// Wrap `db`, recording changes as made by `uid`, or by the application itself if there is none.
+    pub fn new(db: D, uid: Option<String>) -> Audited<D> {
+        Audited { db, uid, in_transaction: false }
+    }
+
+    /// The same database, recording changes as made by `uid` instead.
+    pub fn as_user(&self, uid: Option<String>) -> Audited<&(dyn Database + 'd)> {
+        Audited { db: &*self.db, uid, in_transaction: self.in_transaction }
+    }
+
+    /// Record every change in `changes`, made to entries of `table_id`.
+    async fn record(&self, table_id: &str, changes: &[Change]) -> Result<(), DatabaseError> {
+        if table_id == AuditEntry::table() {
+            return Ok(());
+        }
+
+        let redacted = sensitive().into_iter()
+            .find(|(id, _)| *id == table_id)
+            .map(|(_, fields)| fields)
+            .unwrap_or_default();
+        let timestamp = Utc::now().timestamp_millis();
+        let null = serde_json::Value::Null;
+        let entries = changes.iter()
+            .map(|change| {
+                let (action, old, new) = match &change.event {
+                    ChangeEvent::Insert { new } => (AuditAction::Insert, &null, new),
+                    ChangeEvent::Update { old, new } => (AuditAction::Update, old, new),
+                    ChangeEvent::Delete { old } => (AuditAction::Delete, old, &null)
+                };
+                // Every update moves the revision on, so only the fields it wrote are recorded.
+                let mut changed = diff(old, new);
+                if let Some(fields) = changed.as_object_mut() {
+                    fields.remove(REVISION);
+                }
+                AuditEntry {
+                    uid: self.uid.clone(),
+                    timestamp,
+                    table_id: table_id.into(),
+                    entry_id: change.id,
+                    action,
+                    diff: redact(changed, &redacted)
+                }
+            })
+            // Updates which didn't change anything are left out.
+            .filter(|entry| entry.diff.as_object().is_some_and(|diff| !diff.is_empty()))
+            .collect::<Vec<_>>();
+
+        if !entries.is_empty() {
+            Repository::<AuditEntry>::new(&*self.db).insert_many(&entries).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<'d, D: Deref<Target = dyn Database + 'd> + Send + Sync> Database for Audited<D> {
+
+    /// Audited databases can't be initialized, they wrap an existing database, see [`Audited::new`].
+    async fn init(_location: &str) -> Result<Arc<Self>, DatabaseError> {
+        Err(DatabaseError::InvalidArgument("An audited database must wrap an existing database.".into()))
+    }
+
+    async fn write(&self, table_id: &str, operation: Operation<'_>) -> Result<Vec<Change>, DatabaseError> {
+        // Changes to the shape of tables and to the audit log aren't recorded, so needn't be made within a transaction.
+        let reshapes = matches!(operation, Operation::CreateTable(_) | Operation::AlterTable(_) | Operation::DropTable);
+        if reshapes || self.in_transaction || table_id == AuditEntry::table() {
+            let changes = self.db.write(table_id, operation).await?;
+            self.record(table_id, &changes).await?;
+            return Ok(changes);
+        }
+
+        // Transactions can't borrow from outside of them, so the write is moved in and its changes are passed back out.
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let (table_id, write, uid, out) = (table_id.to_string(), operation.into_owned(), self.uid.clone(), written.clone());
+        self.db.transaction(Box::new(move |db| Box::pin(async move {
+            let db = Audited { db, uid, in_transaction: true };
+            *out.lock().unwrap() = db.write(&table_id, write.as_operation()).await?;
+            Ok(())
+        }))).await?;
+
+        let changes = std::mem::take(&mut *written.lock().unwrap());
+        Ok(changes)
+    }
+
+    async fn get(&self, table_id: &str,  filter: DatabaseFilter<FilterValue>) -> Result<serde_json::Value, DatabaseError> {
+        self.db.get(table_id, filter).await
+    }
+
+    async fn find(&self, table_id: &str, filter: DatabaseFilter<FilterValue>) -> Result<EntryId, DatabaseError> {
+        self.db.find(table_id, filter).await
+    }
+
+    async fn find_entries(&self, table_id: &str, filter: DatabaseFilter<FilterValue>) -> Result<Vec<(EntryId, serde_json::Value)>, DatabaseError> {
+        self.db.find_entries(table_id, filter).await
+    }
+
+    async fn get_by_id(&self, table_id: &str, id: EntryId) -> Result<serde_json::Value, DatabaseError> {
+        self.db.get_by_id(table_id, id).await
+    }
+
+    async fn query(&self, table_id: &str, query: DatabaseQuery<FilterValue>) -> Result<QueryResult, DatabaseError> {
+        self.db.query(table_id, query).await
+    }
+
+    async fn aggregate(&self, table_id: &str, aggregation: Aggregation<FilterValue>) -> Result<Vec<AggregateGroup>, DatabaseError> {
+        self.db.aggregate(table_id, aggregation).await
+    }
+
+    fn subscribe(&self, table_id: &str) -> Receiver<Change> {
+        self.db.subscribe(table_id)
+    }
+
+    async fn transaction(&self, operation: TransactionFn) -> Result<(), DatabaseError> {
+        let uid = self.uid.clone();
+        self.db.transaction(Box::new(move |db| Box::pin(async move {
+            operation(&Audited { db, uid, in_transaction: true }).await
+        }))).await
+    }
+}
+
+/// Fields which differ between `old` and `new`, mapped to an object holding their `old` and `new` values.
+/// Missing fields, and entries which aren't objects, are treated as null.
+pub fn diff(old: &serde_json::Value, new: &serde_json::Value) -> serde_json::Value {
+    let empty = serde_json::Map::new();
+    let old = old.as_object().unwrap_or(&empty);
+    let new = new.as_object().unwrap_or(&empty);
+
+    let diff = old.keys().chain(new.keys().filter(|key| !old.contains_key(*key)))
+        .filter_map(|key| {
+            let old = old.get(key).unwrap_or(&serde_json::Value::Null);
+            let new = new.get(key).unwrap_or(&serde_json::Value::Null);
+            (old != new).then(|| (key.clone(), serde_json::json!({ "old": old, "new": new })))
+        })
+        .collect();
+    serde_json::Value::Object(diff)
+}
+
+/// Recorded in place of the values of sensitive fields, see [`DatabaseModel::sensitive`].
+pub const REDACTED: &str = "[redacted]";
+
+/// Replace the values of `fields` within `diff` with [`REDACTED`].
+/// Null values are kept, so whether the field was set or cleared is still recorded.
+pub fn redact(mut diff: serde_json::Value, fields: &[&str]) -> serde_json::Value {
+    for field in fields {
+        if let Some(change) = diff.get_mut(*field).and_then(|change| change.as_object_mut()) {
+            change.values_mut().filter(|value| !value.is_null()).for_each(|value| *value = REDACTED.into());
+        }
+    }
+    diff
+}
+
+/// Which audit entries [`trail`] returns, every entry is returned by default.
+#[derive(Clone, Debug, Default)]
+pub struct AuditFilter {
+    /// Only changes made by this account.
+    pub uid: Option<String>,
+    /// Only changes made to this table.
+    pub table_id: Option<String>,
+    /// Only changes made to the entry with this id, should be used along with `table_id`.
+    pub entry_id: Option<EntryId>,
+    /// Only changes made at or after this unix timestamp, in milliseconds.
+    pub since: Option<i64>,
+    /// Only changes made before this unix timestamp, in milliseconds.
+    pub until: Option<i64>
+}
+
+/// Read back the audit entries matching `filter`, oldest first.
+pub async fn trail(db: &dyn Database, filter: &AuditFilter, limit: Option<usize>, offset: usize) -> Result<QueryResult<AuditEntry>, DatabaseError> {
+    let mut builder = db.filter();
+    if let Some(uid) = &filter.uid {
+        builder.eq("uid", uid.as_str().into());
+    }
+    if let Some(table_id) = &filter.table_id {
+        builder.eq("table_id", table_id.as_str().into());
+    }
+    if let Some(entry_id) = filter.entry_id {
+        builder.eq("entry_id", entry_id.into());
+    }
+    if let Some(since) = filter.since {
+        builder.gte("timestamp", since.into());
+    }
+    if let Some(until) = filter.until {
+        builder.lt("timestamp", until.into());
+    }
+
+    let mut query = DatabaseQuery::new(builder.build())
+        .order_by("timestamp", SortOrder::Ascending)
+        .offset(offset);
+    if let Some(limit) = limit {
+        query = query.limit(limit);
+    }
+    Repository::<AuditEntry>::new(db).query(query).await
+}
+
+/// Every change made to the entry of `table_id` with the given id, oldest first.
+pub async fn history(db: &dyn Database, table_id: &str, entry_id: EntryId) -> Result<Vec<AuditEntry>, DatabaseError> {
+    let filter = AuditFilter { table_id: Some(table_id.into()), entry_id: Some(entry_id), ..Default::default() };
+    Ok(trail(db, &filter, None, 0).await?.entries)
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use crate::core::{database::{self, migrations, volatile::VolatileDb, Database, DatabaseModel, DatabaseQuery}, models::audit::{AuditAction, AuditEntry}, sessions};
+
+    use super::{history, trail, AuditFilter, Audited, REDACTED};
+
+    async fn db() -> Arc<dyn Database> {
+        let db: Arc<dyn Database> = Arc::new(VolatileDb::default());
+        migrations::migrate(&*db, None).await.unwrap();
+        database::setup(&*db).await;
+        db
+    }
+
+    fn session(session_id: &str, uid: &str) -> serde_json::Value {
+        json!({ "session_id": session_id, "uid": uid, "created": 0, "valid": true })
+    }
+
+    #[tokio::test]
+    async fn records() {
+        let inner = db().await;
+        let db = Audited::new(inner.clone(), Some("admin".into()));
+
+        let id = db.insert("sessions", &session("a", "1")).await.unwrap();
+        db.insert_many("sessions", &[session("b", "1"), session("c", "2")]).await.unwrap();
+        db.update("sessions", db.filter().eq("session_id", "a".into()).build(), &json!({ "valid": false })).await.unwrap();
+        // Updates which don't change anything aren't recorded.
+        db.update("sessions", db.filter().eq("session_id", "a".into()).build(), &json!({ "valid": false })).await.unwrap();
+        assert_eq!(db.update_many("sessions", db.filter().eq("uid", "1".into()).build(), &json!({ "valid": false })).await, Ok(2));
+        db.delete("sessions", db.filter().eq("session_id", "a".into()).build()).await.unwrap();
+        assert_eq!(db.delete_many("sessions", db.filter().build()).await, Ok(2));
+
+        let entries = history(&*inner, "sessions", id).await.unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.action).collect::<Vec<_>>(), vec![AuditAction::Insert, AuditAction::Update, AuditAction::Delete]);
+        assert!(entries.iter().all(|entry| entry.uid.as_deref() == Some("admin")));
+        assert_eq!(entries[0].diff["session_id"], json!({ "old": null, "new": "a" }));
+        assert_eq!(entries[1].diff, json!({ "valid": { "old": true, "new": false } }));
+        assert_eq!(entries[2].diff["valid"], json!({ "old": false, "new": null }));
+
+        let all = trail(&*inner, &AuditFilter { table_id: Some("sessions".into()), ..Default::default() }, None, 0).await.unwrap();
+        assert_eq!(all.total, 8);
+        let page = trail(&*inner, &AuditFilter { uid: Some("admin".into()), ..Default::default() }, Some(2), 3).await.unwrap();
+        assert_eq!(page.entries, all.entries[3..5]);
+        assert_eq!(trail(&*inner, &AuditFilter { uid: Some("other".into()), ..Default::default() }, None, 0).await.unwrap().total, 0);
+
+        // Changes made without the wrapper aren't recorded.
+        inner.insert("sessions", &session("d", "3")).await.unwrap();
+        assert_eq!(trail(&*inner, &AuditFilter::default(), None, 0).await.unwrap().total, 8);
+    }
+
+    #[tokio::test]
+    async fn shared() {
+        let inner = db().await;
+        let db = Arc::new(Audited::new(inner.clone(), None));
+
+        // Writes made through the shared database are recorded as made by the application, unless made as a user.
+        db.as_user(Some("1".into())).insert("sessions", &session("a", "1")).await.unwrap();
+        db.insert("sessions", &json!({ "session_id": "b", "uid": "2", "created": 0, "valid": false })).await.unwrap();
+        // Reading an invalid session deletes it.
+        assert!(sessions::get(&*db, "b".into()).await.is_none());
+
+        let all = trail(&*inner, &AuditFilter::default(), None, 0).await.unwrap();
+        assert_eq!(all.entries.iter().map(|entry| (entry.uid.as_deref(), entry.action)).collect::<Vec<_>>(), vec![
+            (Some("1"), AuditAction::Insert),
+            (None, AuditAction::Insert),
+            (None, AuditAction::Delete)
+        ]);
+    }
+
+    #[tokio::test]
+    async fn redacts() {
+        let inner = db().await;
+        let db = Audited::new(inner.clone(), None);
+
+        let account = json!({ "uid": "1", "firstname": "A", "surname": "B", "email": "a@b.c", "pass_hash": "secret" });
+        let id = db.insert("accounts", &account).await.unwrap();
+        db.update("accounts", db.filter().eq("uid", "1".into()).build(), &json!({ "pass_hash": "changed", "firstname": "C" })).await.unwrap();
+
+        let entries = history(&*inner, "accounts", id).await.unwrap();
+        assert_eq!(entries[0].diff["pass_hash"], json!({ "old": null, "new": REDACTED }));
+        assert_eq!(entries[1].diff, json!({
+            "pass_hash": { "old": REDACTED, "new": REDACTED },
+            "firstname": { "old": "A", "new": "C" }
+        }));
+    }
+
+    #[tokio::test]
+    async fn transaction() {
+        let inner = db().await;
+        let db = Audited::new(inner.clone(), None);
+
+        let result = db.transaction(Box::new(|db| Box::pin(async move {
+            db.insert("sessions", &session("a", "1")).await?;
+            db.insert("sessions", &session("a", "1")).await?;
+            Ok(())
+        }))).await;
+        assert!(result.is_err());
+        db.transaction(Box::new(|db| Box::pin(async move {
+            db.insert("sessions", &session("b", "1")).await?;
+            Ok(())
+        }))).await.unwrap();
+
+        let all = trail(&*inner, &AuditFilter::default(), None, 0).await.unwrap();
+        assert_eq!(all.total, 1);
+        assert_eq!(all.entries[0].uid, None);
+        assert_eq!(inner.query("sessions", DatabaseQuery::new(inner.filter().build())).await.unwrap().total, 1);
+    }
+
+    #[tokio::test]
+    async fn unrecorded() {
+        let inner = db().await;
+        let db = Audited::new(inner.clone(), None);
+
+        // A change which can't be recorded isn't kept.
+        inner.drop_table(AuditEntry::table()).await.unwrap();
+        assert!(db.insert("sessions", &session("a", "1")).await.is_err());
+        assert!(inner.get("sessions", inner.filter().build()).await.unwrap_err().is_not_found());
+    }
+}
@@ -0,0 +1,324 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}, time::{Duration, Instant}};
+use async_std::channel::Receiver;
+use async_trait::async_trait;
+
+use super::{aggregate::{AggregateGroup, Aggregation}, changes::Change, cached, error::DatabaseError, Database, DatabaseFilter, DatabaseQuery, QueryResult, EntryId, FilterValue, TransactionFn, transaction::Operation};
+
+/// How reads from a table are cached by [`CachedDb`], see [`DatabaseModel::cache`](super::DatabaseModel::cache).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CachePolicy {
+    /// How long a result is kept for, this bounds how stale a result can be if the table is changed by another process.
+    pub ttl: Duration,
+    /// Most results kept at once, the oldest result is dropped to make room for a new one.
+    pub capacity: usize
+}
+
+#[derive(Clone)]
+enum Cached {
+    Entry(serde_json::Value),
+    Id(EntryId),
+    Entries(Vec<(EntryId, serde_json::Value)>),
+    Query(QueryResult),
+    Groups(Vec<AggregateGroup>),
+    NotFound
+}
+
+enum Lookup {
+    /// The table isn't cached.
+    Uncached,
+    Hit(Cached),
+    Miss { generation: u64 }
+}
+
+struct TableCache {
+    policy: CachePolicy,
+    /// Every change to the table, whether or not it was made through the cache.
+    changes: Receiver<Change>,
+    results: HashMap<String, (Instant, Cached)>,
+    /// Incremented whenever the table is changed, so results read before a change aren't cached after it.
+    generation: u64
+}
+
+impl TableCache {
+    /// Drop every result if the table has changed since it was last checked.
+    fn sync(&mut self) {
+        let mut changed = false;
+        while self.changes.try_recv().is_ok() {
+            changed = true;
+        }
+        if changed {
+            self.clear();
+        }
+    }
+
+    fn clear(&mut self) {
+        self.results.clear();
+        self.generation += 1;
+    }
+
+    fn get(&mut self, key: &str) -> Option<Cached> {
+        self.sync();
+        match self.results.get(key) {
+            Some((cached_at, cached)) if cached_at.elapsed() < self.policy.ttl => Some(cached.clone()),
+            Some(_) => {
+                self.results.remove(key);
+                None
+            },
+            None => None
+        }
+    }
+
+    /// Cache `cached`, unless the table has changed since `generation`.
+    fn put(&mut self, key: String, cached: Cached, generation: u64) {
+        self.sync();
+        if generation != self.generation || self.policy.capacity == 0 {
+            return;
+        }
+
+        if self.results.len() >= self.policy.capacity {
+            let ttl = self.policy.ttl;
+            self.results.retain(|_, (cached_at, _)| cached_at.elapsed() < ttl);
+        }
+        if self.results.len() >= self.policy.capacity {
+            let oldest = self.results.iter().min_by_key(|(_, (cached_at, _))| *cached_at).map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.results.remove(&oldest);
+            }
+        }
+        self.results.insert(key, (Instant::now(), cached));
+    }
+}
+
+/// Read-through cache in front of another database.
+///
+/// Results of `get`, `find`, `find_entries`, `get_by_id` and `query` are cached for tables with a [`CachePolicy`],
+/// and every cached result for a table is dropped whenever it changes.
+/// Changes are picked up through [`Database::subscribe`], so writes made directly to the wrapped database are also seen.
+///
+/// Operations run within [`Database::transaction`] go straight to the wrapped database, so aren't cached.
+/// Reads made while a transaction runs can still see its writes, so every cached result is dropped should it be rolled back.
+pub struct CachedDb<D: Database> {
+    inner: Arc<D>,
+    tables: Mutex<HashMap<String, TableCache>>
+}
+
+impl<D: Database> CachedDb<D> {
+    /// Wrap `inner`, without caching any tables.
+    pub fn new(inner: Arc<D>) -> CachedDb<D> {
+        CachedDb { inner, tables: Mutex::new(HashMap::new()) }
+    }
+
+    /// Cache reads from `table_id` according to `policy`.
+    pub fn cache(self, table_id: &str, policy: CachePolicy) -> CachedDb<D> {
+        let table = TableCache { policy, changes: self.inner.subscribe(table_id), results: HashMap::new(), generation: 0 };
+        self.tables.lock().unwrap().insert(table_id.to_string(), table);
+        self
+    }
+
+    fn lookup(&self, table_id: &str, key: &str) -> Lookup {
+        let mut tables = self.tables.lock().unwrap();
+        let Some(table) = tables.get_mut(table_id) else {
+            return Lookup::Uncached
+        };
+        match table.get(key) {
+            Some(cached) => Lookup::Hit(cached),
+            None => Lookup::Miss { generation: table.generation }
+        }
+    }
+
+    /// Return the cached result for `key`, or read it with `read` and cache it.
+    async fn read_through<R>(&self, table_id: &str, key: String, read: R) -> Result<Cached, DatabaseError>
+    where R: std::future::Future<Output = Result<Cached, DatabaseError>> {
+        let generation = match self.lookup(table_id, &key) {
+            Lookup::Uncached => return read.await,
+            Lookup::Hit(cached) => return Ok(cached),
+            Lookup::Miss { generation } => generation
+        };
+
+        let result = match read.await {
+            Err(DatabaseError::NotFound) => Ok(Cached::NotFound),
+            result => result
+        }?;
+        if let Some(table) = self.tables.lock().unwrap().get_mut(table_id) {
+            table.put(key, result.clone(), generation);
+        }
+        Ok(result)
+    }
+
+    /// Drop every cached result for `table_id`, for changes which aren't sent to subscribers.
+    fn invalidate(&self, table_id: &str) {
+        if let Some(table) = self.tables.lock().unwrap().get_mut(table_id) {
+            table.clear();
+        }
+    }
+}
+
+#[async_trait]
+impl<D: Database + 'static> Database for CachedDb<D> {
+
+    /// Initialize the wrapped database, caching every table with a [`CachePolicy`].
+    async fn init(location: &str) -> Result<Arc<CachedDb<D>>, DatabaseError> {
+        let db = cached().into_iter()
+            .fold(CachedDb::new(D::init(location).await?), |db, (table_id, policy)| db.cache(table_id, policy));
+        Ok(Arc::new(db))
+    }
+
+    async fn write(&self, table_id: &str, operation: Operation<'_>) -> Result<Vec<Change>, DatabaseError> {
+        let result = self.inner.write(table_id, operation).await;
+        if let Operation::CreateTable(_) | Operation::AlterTable(_) | Operation::DropTable = operation {
+            self.invalidate(table_id);
+        }
+        result
+    }
+
+    async fn get(&self, table_id: &str,  filter: DatabaseFilter<FilterValue>) -> Result<serde_json::Value, DatabaseError> {
+        let key = format!("get {filter:?}");
+        match self.read_through(table_id, key, async { self.inner.get(table_id, filter).await.map(Cached::Entry) }).await? {
+            Cached::Entry(entry) => Ok(entry),
+            _ => Err(DatabaseError::NotFound)
+        }
+    }
+
+    async fn find(&self, table_id: &str, filter: DatabaseFilter<FilterValue>) -> Result<EntryId, DatabaseError> {
+        let key = format!("find {filter:?}");
+        match self.read_through(table_id, key, async { self.inner.find(table_id, filter).await.map(Cached::Id) }).await? {
+            Cached::Id(id) => Ok(id),
+            _ => Err(DatabaseError::NotFound)
+        }
+    }
+
+    async fn find_entries(&self, table_id: &str, filter: DatabaseFilter<FilterValue>) -> Result<Vec<(EntryId, serde_json::Value)>, DatabaseError> {
+        let key = format!("find_entries {filter:?}");
+        match self.read_through(table_id, key, async { self.inner.find_entries(table_id, filter).await.map(Cached::Entries) }).await? {
+            Cached::Entries(entries) => Ok(entries),
+            _ => Err(DatabaseError::NotFound)
+        }
+    }
+
+    async fn get_by_id(&self, table_id: &str, id: EntryId) -> Result<serde_json::Value, DatabaseError> {
+        let key = format!("get_by_id {id}");
+        match self.read_through(table_id, key, async { self.inner.get_by_id(table_id, id).await.map(Cached::Entry) }).await? {
+            Cached::Entry(entry) => Ok(entry),
+            _ => Err(DatabaseError::NotFound)
+        }
+    }
+
+    async fn query(&self, table_id: &str, query: DatabaseQuery<FilterValue>) -> Result<QueryResult, DatabaseError> {
+        let key = format!("query {query:?}");
+        match self.read_through(table_id, key, async { self.inner.query(table_id, query).await.map(Cached::Query) }).await? {
+            Cached::Query(result) => Ok(result),
+            _ => Err(DatabaseError::NotFound)
+        }
+    }
+
+    async fn aggregate(&self, table_id: &str, aggregation: Aggregation<FilterValue>) -> Result<Vec<AggregateGroup>, DatabaseError> {
+        let key = format!("aggregate {aggregation:?}");
+        match self.read_through(table_id, key, async { self.inner.aggregate(table_id, aggregation).await.map(Cached::Groups) }).await? {
+            Cached::Groups(groups) => Ok(groups),
+            _ => Err(DatabaseError::NotFound)
+        }
+    }
+
+    fn subscribe(&self, table_id: &str) -> Receiver<Change> {
+        self.inner.subscribe(table_id)
+    }
+
+    async fn transaction(&self, operation: TransactionFn) -> Result<(), DatabaseError> {
+        // Changes made within the transaction are only sent once it's committed, which is when cached results are dropped.
+        // None are sent when it's rolled back, and which tables it wrote to isn't known, so every cached result is dropped instead.
+        let result = self.inner.transaction(operation).await;
+        if result.is_err() {
+            self.tables.lock().unwrap().values_mut().for_each(TableCache::clear);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use serde_json::json;
+
+    use crate::core::{database::{error::DatabaseError, volatile::VolatileDb, Database}, models::{ModelSchema, ModelValueType}};
+
+    use super::{CachePolicy, CachedDb, Lookup};
+
+    crate::core::database::conformance::conformance!(Arc::new(
+        CachedDb::new(VolatileDb::init("").await.unwrap())
+            .cache(crate::core::database::conformance::TABLE, CachePolicy { ttl: Duration::from_secs(60), capacity: 10 })
+    ));
+
+    async fn numbers(policy: CachePolicy) -> (Arc<VolatileDb>, CachedDb<VolatileDb>) {
+        let schema = ModelSchema { fields: vec![ModelValueType::Number { field: "n" }], optional: Vec::new(), unique: vec!["n"], indexed: Vec::new() };
+        let inner = Arc::new(VolatileDb::default());
+        inner.create_table("numbers", &schema).await.unwrap();
+        for n in 1..=3 {
+            inner.insert("numbers", &json!({ "n": n })).await.unwrap();
+        }
+        (inner.clone(), CachedDb::new(inner).cache("numbers", policy))
+    }
+
+    fn cached(db: &CachedDb<VolatileDb>) -> usize {
+        let mut tables = db.tables.lock().unwrap();
+        let table = tables.get_mut("numbers").unwrap();
+        table.sync();
+        table.results.len()
+    }
+
+    #[tokio::test]
+    async fn invalidate() {
+        let (inner, db) = numbers(CachePolicy { ttl: Duration::from_secs(60), capacity: 10 }).await;
+
+        let id = db.find("numbers", db.filter().eq("n", 1.into()).build()).await.unwrap();
+        assert_eq!(db.get_by_id("numbers", id).await.unwrap(), json!({ "n": 1 }));
+        assert!(db.get("numbers", db.filter().eq("n", 4.into()).build()).await.unwrap_err().is_not_found());
+        assert_eq!(cached(&db), 3);
+        assert_eq!(db.get_by_id("numbers", id).await.unwrap(), json!({ "n": 1 }));
+        assert_eq!(cached(&db), 3);
+
+        // Writes through the cache and directly to the wrapped database are both seen.
+        db.update("numbers", db.filter().eq("n", 1.into()).build(), &json!({ "n": 4 })).await.unwrap();
+        assert_eq!(cached(&db), 0);
+        assert_eq!(db.get("numbers", db.filter().eq("n", 4.into()).build()).await.unwrap(), json!({ "n": 4 }));
+        inner.delete("numbers", inner.filter().eq("n", 4.into()).build()).await.unwrap();
+        assert!(db.get_by_id("numbers", id).await.unwrap_err().is_not_found());
+        assert_eq!(cached(&db), 1);
+
+        // Tables without a policy aren't cached.
+        let uncached = CachedDb::new(inner);
+        uncached.get_by_id("numbers", id + 1).await.unwrap();
+        assert!(uncached.tables.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rollback() {
+        let (_, db) = numbers(CachePolicy { ttl: Duration::from_secs(60), capacity: 10 }).await;
+        let db = Arc::new(db);
+
+        // Results read while the transaction runs aren't kept once it's rolled back.
+        let reader = db.clone();
+        let result = db.transaction(Box::new(move |db| Box::pin(async move {
+            db.insert("numbers", &json!({ "n": 4 })).await?;
+            reader.get("numbers", reader.filter().eq("n", 4.into()).build()).await?;
+            Err(DatabaseError::InvalidArgument("Cancelled".into()))
+        }))).await;
+        assert!(result.is_err());
+        assert_eq!(cached(&db), 0);
+        assert!(db.get("numbers", db.filter().eq("n", 4.into()).build()).await.unwrap_err().is_not_found());
+    }
+
+    #[tokio::test]
+    async fn limits() {
+        let (_, db) = numbers(CachePolicy { ttl: Duration::from_millis(50), capacity: 2 }).await;
+
+        for id in 1..=3 {
+            db.get_by_id("numbers", id).await.unwrap();
+        }
+        assert_eq!(cached(&db), 2);
+        assert!(!db.tables.lock().unwrap()["numbers"].results.contains_key("get_by_id 1"));
+
+        async_std::task::sleep(Duration::from_millis(60)).await;
+        assert!(matches!(db.lookup("numbers", "get_by_id 3"), Lookup::Miss { .. }));
+    }
+}
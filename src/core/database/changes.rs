@@ -0,0 +1,48 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use async_std::channel::{self, Receiver, Sender};
+
+use super::EntryId;
+
+/// A change made to a single entry, sent to every subscriber of its table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    pub table_id: String,
+    pub id: EntryId,
+    pub event: ChangeEvent
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChangeEvent {
+    Insert { new: serde_json::Value },
+    Update { old: serde_json::Value, new: serde_json::Value },
+    Delete { old: serde_json::Value }
+}
+
+/// Subscribers to changes in each table of a database, see [`Database::subscribe`](super::Database::subscribe).
+#[derive(Default)]
+pub struct Subscriptions {
+    senders: Mutex<HashMap<String, Vec<Sender<Change>>>>
+}
+
+impl Subscriptions {
+    /// Subscribe to changes in `table_id`, the subscription ends once the receiver is dropped.
+    pub fn subscribe(&self, table_id: &str) -> Receiver<Change> {
+        let (sender, receiver) = channel::unbounded();
+        self.senders.lock().unwrap().entry(table_id.to_string()).or_default().push(sender);
+        receiver
+    }
+
+    pub fn notify(&self, change: Change) {
+        let mut senders = self.senders.lock().unwrap();
+        let Some(table) = senders.get_mut(&change.table_id) else {
+            return
+        };
+
+        // Channels are unbounded, so sending only fails once the receiver has been dropped.
+        table.retain(|sender| sender.try_send(change.clone()).is_ok());
+        if table.is_empty() {
+            senders.remove(&change.table_id);
+        }
+    }
+}
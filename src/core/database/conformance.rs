@@ -0,0 +1,430 @@
+//! Checks which every [`Database`] implementation is expected to pass.
+//!
+//! Each check is given a freshly initialized database, use [`conformance!`] within a backend's tests to run all of them:
+//!
+//! ```ignore
+//! conformance!(SqliteDb::init(":memory:").await.unwrap());
+//! ```
+
+use std::sync::Arc;
+
+use serde_json::json;
+
+use crate::core::models::{ModelSchema, ModelValueType};
+
+use super::{aggregate::{AggregateGroup, Aggregation, Grouping}, changes::ChangeEvent, error::DatabaseError, Database, DatabaseQuery, SortOrder, REVISION};
+
+/// Table used by every check, it is created by the check itself.
+pub const TABLE: &str = "conformance_users";
+
+/// Generate a test for each check, initializing a new database with `$init` for every test.
+macro_rules! conformance {
+    ($init:expr) => {
+        mod conformance {
+            use super::*;
+
+            #[tokio::test]
+            async fn crud() {
+                $crate::core::database::conformance::crud($init).await;
+            }
+
+            #[tokio::test]
+            async fn filters() {
+                $crate::core::database::conformance::filters($init).await;
+            }
+
+            #[tokio::test]
+            async fn missing_table() {
+                $crate::core::database::conformance::missing_table($init).await;
+            }
+
+            #[tokio::test]
+            async fn deletes() {
+                $crate::core::database::conformance::deletes($init).await;
+            }
+
+            #[tokio::test]
+            async fn concurrency() {
+                $crate::core::database::conformance::concurrency($init).await;
+            }
+
+            #[tokio::test]
+            async fn transactions() {
+                $crate::core::database::conformance::transactions($init).await;
+            }
+
+            #[tokio::test]
+            async fn aggregates() {
+                $crate::core::database::conformance::aggregates($init).await;
+            }
+
+            #[tokio::test]
+            async fn revisions() {
+                $crate::core::database::conformance::revisions($init).await;
+            }
+        }
+    };
+}
+pub(crate) use conformance;
+
+fn users() -> ModelSchema {
+    ModelSchema {
+        fields: vec![
+            ModelValueType::String { field: "email" },
+            ModelValueType::String { field: "name" },
+            ModelValueType::Number { field: "age" },
+        ],
+        optional: vec!["name"],
+        unique: vec!["email"],
+        indexed: vec!["age"]
+    }
+}
+
+fn revisioned_users() -> ModelSchema {
+    let mut schema = users();
+    schema.fields.push(ModelValueType::Number { field: REVISION });
+    schema.optional.push(REVISION);
+    schema
+}
+
+fn user(n: u64) -> serde_json::Value {
+    json!({ "email": format!("user{n}@email.com"), "name": format!("User {n}"), "age": n })
+}
+
+/// Inserting, reading, updating and deleting single entries.
+pub async fn crud(db: Arc<impl Database + 'static>) {
+    db.create_table(TABLE, &users()).await.unwrap();
+
+    let id = db.insert(TABLE, &user(1)).await.unwrap();
+    let other = db.insert(TABLE, &json!({ "email": "user2@email.com", "age": 2 })).await.unwrap();
+    assert_ne!(id, other);
+
+    let filter = db.filter().eq("email", "user1@email.com".into()).build();
+    assert_eq!(db.get(TABLE, filter.clone()).await, Ok(user(1)));
+    assert_eq!(db.find(TABLE, filter.clone()).await, Ok(id));
+    assert_eq!(db.get_by_id(TABLE, id).await, Ok(user(1)));
+    assert_eq!(db.get_by_id(TABLE, other).await, Ok(json!({ "email": "user2@email.com", "age": 2 })));
+
+    // Only the given fields are updated.
+    db.update(TABLE, filter.clone(), &json!({ "age": 30 })).await.unwrap();
+    assert_eq!(db.get_by_id(TABLE, id).await, Ok(json!({ "email": "user1@email.com", "name": "User 1", "age": 30 })));
+
+    // Writes are validated against the schema, and unique fields can't be shared.
+    assert!(matches!(db.insert(TABLE, &json!({ "email": "user3@email.com" })).await, Err(DatabaseError::SchemaViolation(_))));
+    assert!(matches!(db.insert(TABLE, &json!({ "email": "user3@email.com", "age": "3" })).await, Err(DatabaseError::SchemaViolation(_))));
+    assert!(matches!(db.insert(TABLE, &json!({ "email": "user3@email.com", "age": 3, "extra": true })).await, Err(DatabaseError::SchemaViolation(_))));
+    assert!(matches!(db.update(TABLE, filter.clone(), &json!({ "age": "30" })).await, Err(DatabaseError::SchemaViolation(_))));
+    assert_eq!(db.insert(TABLE, &user(1)).await, Err(DatabaseError::Conflict { field: "email".into() }));
+    assert_eq!(db.update(TABLE, filter.clone(), &json!({ "email": "user2@email.com" })).await, Err(DatabaseError::Conflict { field: "email".into() }));
+    assert_eq!(db.get_by_id(TABLE, id).await.unwrap()["email"], json!("user1@email.com"));
+
+    // Missing entries.
+    let missing = db.filter().eq("email", "user4@email.com".into()).build();
+    assert_eq!(db.get(TABLE, missing.clone()).await, Err(DatabaseError::NotFound));
+    assert_eq!(db.find(TABLE, missing.clone()).await, Err(DatabaseError::NotFound));
+    assert_eq!(db.find_entries(TABLE, missing.clone()).await, Ok(Vec::new()));
+    assert_eq!(db.update(TABLE, missing.clone(), &json!({ "age": 4 })).await, Err(DatabaseError::NotFound));
+    assert_eq!(db.delete(TABLE, missing).await, Err(DatabaseError::NotFound));
+
+    db.delete(TABLE, filter.clone()).await.unwrap();
+    assert_eq!(db.get(TABLE, filter).await, Err(DatabaseError::NotFound));
+    assert_eq!(db.get_by_id(TABLE, id).await, Err(DatabaseError::NotFound));
+    assert!(db.get_by_id(TABLE, other).await.is_ok());
+
+    // Restored entries keep their ids, which mustn't already be taken.
+    assert!(matches!(db.restore(TABLE, &[(id, user(1)), (other, user(7))]).await, Err(DatabaseError::Conflict { .. })));
+    assert_eq!(db.get_by_id(TABLE, id).await, Err(DatabaseError::NotFound));
+    db.restore(TABLE, &[(id, user(1))]).await.unwrap();
+    assert_eq!(db.get_by_id(TABLE, id).await, Ok(user(1)));
+    db.delete(TABLE, db.filter().eq("email", "user1@email.com".into()).build()).await.unwrap();
+
+    // Bulk writes apply to every entry or to none of them.
+    assert_eq!(db.insert_many(TABLE, &[user(5), user(6), user(5)]).await, Err(DatabaseError::Conflict { field: "email".into() }));
+    assert_eq!(db.count(TABLE, db.filter().build()).await, Ok(1));
+    db.insert_many(TABLE, &[user(5), user(6)]).await.unwrap();
+    let all = db.filter().build();
+    assert_eq!(db.update_many(TABLE, all.clone(), &json!({ "email": "same@email.com" })).await, Err(DatabaseError::Conflict { field: "email".into() }));
+    assert_eq!(db.find_entries(TABLE, all).await.unwrap().iter().map(|(_, entry)| entry["email"].clone()).collect::<Vec<_>>(), vec![json!("user2@email.com"), json!("user5@email.com"), json!("user6@email.com")]);
+}
+
+/// Every filter operator, along with sorting and paging of queries.
+pub async fn filters(db: Arc<impl Database + 'static>) {
+    db.create_table(TABLE, &users()).await.unwrap();
+    db.insert_many(TABLE, &(1..=5).map(user).collect::<Vec<_>>()).await.unwrap();
+    db.insert(TABLE, &json!({ "email": "anon@email.com", "age": 6 })).await.unwrap();
+
+    let ages = |result: super::QueryResult| result.entries.iter().map(|entry| entry["age"].as_u64().unwrap()).collect::<Vec<_>>();
+    let query = |filter| DatabaseQuery::new(filter).order_by("age", SortOrder::Ascending);
+
+    let cases = [
+        (db.filter().eq("age", 2.into()).build(), vec![2]),
+        (db.filter().neq("age", 2.into()).build(), vec![1, 3, 4, 5, 6]),
+        (db.filter().gt("age", 4.into()).build(), vec![5, 6]),
+        (db.filter().gte("age", 4.into()).build(), vec![4, 5, 6]),
+        (db.filter().lt("age", 2.into()).build(), vec![1]),
+        (db.filter().lte("age", 2.into()).build(), vec![1, 2]),
+        (db.filter().gt("age", 1.into()).lt("age", 4.into()).build(), vec![2, 3]),
+        (db.filter().is_in("age", vec![1.into(), 3.into(), 7.into()]).build(), vec![1, 3]),
+        (db.filter().not_in("age", vec![1.into(), 3.into()]).build(), vec![2, 4, 5, 6]),
+        (db.filter().contains("email", "r5".into()).build(), vec![5]),
+        (db.filter().starts_with("email", "anon".into()).build(), vec![6]),
+        (db.filter().exists("name", true).build(), vec![1, 2, 3, 4, 5]),
+        (db.filter().exists("name", false).build(), vec![6]),
+        (db.filter().eq("age", 2.into()).eq("name", "User 3".into()).build(), vec![]),
+        (db.filter().build(), vec![1, 2, 3, 4, 5, 6]),
+    ];
+    for (filter, expected) in cases {
+        assert_eq!(ages(db.query(TABLE, query(filter.clone())).await.unwrap()), expected, "{filter:?}");
+    }
+
+    let result = db.query(TABLE, DatabaseQuery::new(db.filter().gt("age", 1.into()).build())
+        .order_by("age", SortOrder::Descending)
+        .offset(1)
+        .limit(2)).await.unwrap();
+    assert_eq!(result.total, 5);
+    assert_eq!(ages(result), vec![5, 4]);
+
+    // Entries that are equal by the given order stay in insertion order.
+    let result = db.query(TABLE, DatabaseQuery::new(db.filter().build()).order_by("name", SortOrder::Ascending)).await.unwrap();
+    assert_eq!(ages(result)[1..], [1, 2, 3, 4, 5]);
+
+    // Keys which aren't fields of the table are rejected, rather than matching nothing or everything.
+    let missing = db.filter().or(|filter| filter.neq("missing", 1.into())).build();
+    assert!(matches!(db.find_entries(TABLE, missing.clone()).await, Err(DatabaseError::InvalidArgument(_))));
+    assert!(matches!(db.query(TABLE, DatabaseQuery::new(db.filter().build()).order_by("missing", SortOrder::Ascending)).await, Err(DatabaseError::InvalidArgument(_))));
+    assert!(matches!(db.aggregate(TABLE, Aggregation::new(missing.clone()).count()).await, Err(DatabaseError::InvalidArgument(_))));
+    assert!(matches!(db.delete_many(TABLE, missing).await, Err(DatabaseError::InvalidArgument(_))));
+    assert_eq!(db.count(TABLE, db.filter().build()).await, Ok(6));
+}
+
+/// Every operation on a table that doesn't exist.
+pub async fn missing_table(db: Arc<impl Database + 'static>) {
+    let missing = || Err::<(), _>(DatabaseError::TableNotFound(TABLE.into()));
+    let filter = db.filter().eq("age", 1.into()).build();
+
+    assert_eq!(db.insert(TABLE, &user(1)).await.map(|_| ()), missing());
+    assert_eq!(db.insert_many(TABLE, &[user(1)]).await.map(|_| ()), missing());
+    assert_eq!(db.update(TABLE, filter.clone(), &json!({ "age": 2 })).await, missing());
+    assert_eq!(db.update_many(TABLE, filter.clone(), &json!({ "age": 2 })).await.map(|_| ()), missing());
+    assert_eq!(db.delete(TABLE, filter.clone()).await, missing());
+    assert_eq!(db.delete_many(TABLE, filter.clone()).await.map(|_| ()), missing());
+    assert_eq!(db.get(TABLE, filter.clone()).await.map(|_| ()), missing());
+    assert_eq!(db.find(TABLE, filter.clone()).await.map(|_| ()), missing());
+    assert_eq!(db.find_entries(TABLE, filter.clone()).await.map(|_| ()), missing());
+    assert_eq!(db.get_by_id(TABLE, 1).await.map(|_| ()), missing());
+    assert_eq!(db.query(TABLE, DatabaseQuery::new(filter)).await.map(|_| ()), missing());
+    assert_eq!(db.drop_table(TABLE).await, missing());
+
+    // Dropped tables are missing too.
+    db.create_table(TABLE, &users()).await.unwrap();
+    db.insert(TABLE, &user(1)).await.unwrap();
+    db.drop_table(TABLE).await.unwrap();
+    assert_eq!(db.get_by_id(TABLE, 1).await.map(|_| ()), missing());
+}
+
+/// Removing entries, singly and in bulk.
+pub async fn deletes(db: Arc<impl Database + 'static>) {
+    db.create_table(TABLE, &users()).await.unwrap();
+    let ids = db.insert_many(TABLE, &(1..=6).map(user).collect::<Vec<_>>()).await.unwrap();
+
+    // Only the first matching entry is deleted.
+    db.delete(TABLE, db.filter().lte("age", 2.into()).build()).await.unwrap();
+    assert_eq!(db.get_by_id(TABLE, ids[0]).await, Err(DatabaseError::NotFound));
+    assert_eq!(db.get_by_id(TABLE, ids[1]).await, Ok(user(2)));
+
+    assert_eq!(db.delete_many(TABLE, db.filter().gt("age", 4.into()).build()).await, Ok(2));
+    assert_eq!(db.delete_many(TABLE, db.filter().gt("age", 4.into()).build()).await, Ok(0));
+    let result = db.query(TABLE, DatabaseQuery::new(db.filter().build())).await.unwrap();
+    assert_eq!(result.entries, vec![user(2), user(3), user(4)]);
+
+    // Remaining entries can still be found by their indexed and unique fields.
+    assert_eq!(db.find(TABLE, db.filter().eq("age", 4.into()).build()).await, Ok(ids[3]));
+    assert_eq!(db.find(TABLE, db.filter().eq("email", "user3@email.com".into()).build()).await, Ok(ids[2]));
+    assert_eq!(db.find_entries(TABLE, db.filter().gte("age", 3.into()).build()).await, Ok(vec![(ids[2], user(3)), (ids[3], user(4))]));
+
+    // The values and ids of deleted entries are free, but ids are never reused.
+    let id = db.insert(TABLE, &user(1)).await.unwrap();
+    assert!(ids.iter().all(|existing| *existing < id));
+
+    assert_eq!(db.delete_many(TABLE, db.filter().build()).await, Ok(4));
+    assert_eq!(db.query(TABLE, DatabaseQuery::new(db.filter().build())).await.unwrap().total, 0);
+}
+
+/// Writes made at the same time from many tasks.
+pub async fn concurrency(db: Arc<impl Database + 'static>) {
+    db.create_table(TABLE, &users()).await.unwrap();
+
+    let inserts = (1..=20).map(|n| {
+        let db = db.clone();
+        async_std::task::spawn(async move { db.insert(TABLE, &user(n)).await })
+    }).collect::<Vec<_>>();
+    let mut ids = Vec::new();
+    for insert in inserts {
+        ids.push(insert.await.unwrap());
+    }
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), 20);
+
+    // Only one of the entries sharing a unique value is inserted.
+    let conflicting = (0..10).map(|_| {
+        let db = db.clone();
+        async_std::task::spawn(async move { db.insert(TABLE, &user(21)).await })
+    }).collect::<Vec<_>>();
+    let mut inserted = 0;
+    for insert in conflicting {
+        match insert.await {
+            Ok(_) => inserted += 1,
+            Err(e) => assert_eq!(e, DatabaseError::Conflict { field: "email".into() })
+        }
+    }
+    assert_eq!(inserted, 1);
+
+    // Every update is applied.
+    let updates = (1..=20).map(|n| {
+        let db = db.clone();
+        async_std::task::spawn(async move {
+            db.update(TABLE, db.filter().eq("age", n.into()).build(), &json!({ "age": n + 100 })).await
+        })
+    }).collect::<Vec<_>>();
+    for update in updates {
+        update.await.unwrap();
+    }
+    let result = db.query(TABLE, DatabaseQuery::new(db.filter().gt("age", 100.into()).build())).await.unwrap();
+    assert_eq!(result.total, 20);
+}
+
+/// Writes made outside of a transaction while one is running.
+pub async fn transactions(db: Arc<impl Database + 'static>) {
+    db.create_table(TABLE, &users()).await.unwrap();
+    let changes = db.subscribe(TABLE);
+    let (started, wait) = (async_std::channel::bounded(1), async_std::channel::bounded(1));
+
+    let transaction = {
+        let (db, started, finish) = (db.clone(), started.0, wait.1);
+        async_std::task::spawn(async move {
+            db.transaction(Box::new(move |db| Box::pin(async move {
+                db.insert(TABLE, &user(1)).await?;
+                assert!(matches!(db.transaction(Box::new(|_| Box::pin(async { Ok(()) }))).await, Err(DatabaseError::InvalidArgument(_))));
+                started.send(()).await.unwrap();
+                finish.recv().await.unwrap();
+                Err(DatabaseError::InvalidArgument("Cancelled".into()))
+            }))).await
+        })
+    };
+    started.1.recv().await.unwrap();
+
+    // Other writes wait for the transaction to finish, so aren't rolled back along with it.
+    let write = {
+        let db = db.clone();
+        async_std::task::spawn(async move { db.insert(TABLE, &user(2)).await })
+    };
+    async_std::task::sleep(std::time::Duration::from_millis(50)).await;
+    assert_eq!(db.count(TABLE, db.filter().eq("age", 2.into()).build()).await, Ok(0));
+
+    wait.0.send(()).await.unwrap();
+    assert!(transaction.await.is_err());
+    write.await.unwrap();
+    let entries = db.find_entries(TABLE, db.filter().build()).await.unwrap();
+    assert_eq!(entries.into_iter().map(|(_, entry)| entry).collect::<Vec<_>>(), vec![user(2)]);
+
+    // Rolling back undoes updates and deletes as well, entries are left with the ids they had.
+    db.insert(TABLE, &user(3)).await.unwrap();
+    let before = db.find_entries(TABLE, db.filter().build()).await.unwrap();
+    let result = db.transaction(Box::new(|db| Box::pin(async move {
+        db.update(TABLE, db.filter().eq("age", 2.into()).build(), &json!({ "name": "Updated" })).await?;
+        db.delete(TABLE, db.filter().eq("age", 3.into()).build()).await?;
+        db.insert(TABLE, &user(4)).await?;
+        Err(DatabaseError::InvalidArgument("Cancelled".into()))
+    }))).await;
+    assert!(result.is_err());
+    assert_eq!(db.find_entries(TABLE, db.filter().build()).await.unwrap(), before);
+
+    // Changes made within a transaction that was rolled back are never sent.
+    let inserted = std::iter::from_fn(|| changes.try_recv().ok()).map(|change| change.event).collect::<Vec<_>>();
+    assert_eq!(inserted, vec![ChangeEvent::Insert { new: user(2) }, ChangeEvent::Insert { new: user(3) }]);
+}
+
+/// Counting entries, and grouping them to compute metrics.
+pub async fn aggregates(db: Arc<impl Database + 'static>) {
+    db.create_table(TABLE, &users()).await.unwrap();
+    let group = |key: serde_json::Value, values: serde_json::Value| AggregateGroup {
+        key: key.as_array().unwrap().clone(),
+        values: values.as_array().unwrap().clone()
+    };
+
+    // A single group is returned even without any entries.
+    assert_eq!(db.count(TABLE, db.filter().build()).await, Ok(0));
+    let totals = Aggregation::new(db.filter().build()).count().sum("age").min("age").max("age");
+    assert_eq!(db.aggregate(TABLE, totals.clone()).await, Ok(vec![group(json!([]), json!([0, null, null, null]))]));
+
+    for (email, name, age) in [("a@one.com", Some("A"), 25), ("b@two.com", None, 31), ("c@one.com", Some("C"), 38), ("d@two.com", Some("A"), 12), ("e@one.com", None, 20)] {
+        db.insert(TABLE, &json!({ "email": email, "name": name, "age": age })).await.unwrap();
+    }
+    assert_eq!(db.count(TABLE, db.filter().build()).await, Ok(5));
+    assert_eq!(db.count(TABLE, db.filter().gt("age", 20.into()).build()).await, Ok(3));
+    assert_eq!(db.aggregate(TABLE, totals).await, Ok(vec![group(json!([]), json!([5, 126, 12, 38]))]));
+
+    // Groups are sorted by key, entries missing the field are grouped under null.
+    let by_name = Aggregation::new(db.filter().build()).group_by(Grouping::Field("name")).count().max("email");
+    assert_eq!(db.aggregate(TABLE, by_name).await.unwrap(), vec![
+        group(json!([null]), json!([2, "e@one.com"])),
+        group(json!(["A"]), json!([2, "d@two.com"])),
+        group(json!(["C"]), json!([1, "c@one.com"])),
+    ]);
+
+    let by_domain = Aggregation::new(db.filter().gte("age", 20.into()).build())
+        .group_by(Grouping::After { key: "email", separator: '@' })
+        .group_by(Grouping::Interval { key: "age", width: 10 })
+        .count()
+        .sum("age");
+    assert_eq!(db.aggregate(TABLE, by_domain).await.unwrap(), vec![
+        group(json!(["one.com", 20]), json!([2, 45])),
+        group(json!(["one.com", 30]), json!([1, 38])),
+        group(json!(["two.com", 30]), json!([1, 31])),
+    ]);
+
+    // Fields the table doesn't have are missing from every entry.
+    let missing = Aggregation::new(db.filter().build()).group_by(Grouping::Field("missing")).sum("missing");
+    assert_eq!(db.aggregate(TABLE, missing).await.unwrap(), vec![group(json!([null]), json!([null]))]);
+}
+
+/// Updating entries only if they haven't been written since they were read.
+pub async fn revisions(db: Arc<impl Database + 'static>) {
+    db.create_table(TABLE, &revisioned_users()).await.unwrap();
+    db.insert(TABLE, &user(1)).await.unwrap();
+    let filter = db.filter().eq("email", "user1@email.com".into()).build();
+
+    // Entries without a revision are at revision 0.
+    let first = db.update_if(TABLE, filter.clone(), 0, &json!({ "age": 2 })).await.unwrap();
+    assert_eq!(first, 1);
+    assert_eq!(db.update_if(TABLE, filter.clone(), 0, &json!({ "age": 3 })).await, Err(DatabaseError::Conflict { field: REVISION.into() }));
+
+    let second = db.update_if(TABLE, filter.clone(), first, &json!({ "age": 3 })).await.unwrap();
+    assert_eq!(second, 2);
+    assert_eq!(db.update_if(TABLE, filter.clone(), first, &json!({ "age": 4 })).await, Err(DatabaseError::Conflict { field: REVISION.into() }));
+    let entry = db.get(TABLE, filter.clone()).await.unwrap();
+    assert_eq!((&entry["age"], &entry[REVISION]), (&json!(3), &json!(second)));
+
+    let missing = db.filter().eq("email", "missing@email.com".into()).build();
+    assert_eq!(db.update_if(TABLE, missing, second, &json!({ "age": 4 })).await, Err(DatabaseError::NotFound));
+
+    // Every update moves the entry on from its stored revision, whatever revision it sets.
+    db.update(TABLE, filter.clone(), &json!({ "age": 4, REVISION: first })).await.unwrap();
+    assert_eq!(db.get(TABLE, filter.clone()).await.unwrap()[REVISION], json!(3));
+    assert_eq!(db.update_if(TABLE, filter.clone(), second, &json!({ "age": 5 })).await, Err(DatabaseError::Conflict { field: REVISION.into() }));
+
+    // Only one of many writers at the same revision succeeds.
+    let writers = (5..15).map(|age: u64| {
+        let (db, filter) = (db.clone(), filter.clone());
+        async_std::task::spawn(async move { db.update_if(TABLE, filter, 3, &json!({ "age": age })).await })
+    }).collect::<Vec<_>>();
+    let mut written = 0;
+    for writer in writers {
+        match writer.await {
+            Ok(_) => written += 1,
+            Err(e) => assert_eq!(e, DatabaseError::Conflict { field: REVISION.into() })
+        }
+    }
+    assert_eq!(written, 1);
+}
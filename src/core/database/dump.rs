@@ -0,0 +1,136 @@
+use std::{fs, path::Path};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::core::models::ModelSchema;
+
+use super::{error::DatabaseError, migrations, tables, Database, EntryId};
+
+/// Version of the dump format, bumped whenever a change to it would stop older dumps from being read.
+pub const DUMP_FORMAT: u32 = 2;
+
+/// Portable copy of every table in a database, which can be restored into any type of database.
+///
+/// Entries are restored under the ids they were dumped with, so anything referring to an entry by its id still finds it.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Dump {
+    pub format: u32,
+    /// Unix timestamp in milliseconds.
+    pub created_at: i64,
+    pub tables: Vec<TableDump>
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct TableDump {
+    pub table: String,
+    /// Definition of the table, as the serialized [`ModelSchema`].
+    pub schema: serde_json::Value,
+    pub entries: Vec<EntryDump>
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct EntryDump {
+    pub id: EntryId,
+    pub entry: serde_json::Value
+}
+
+/// Every table included in a dump, along with its schema.
+/// The migrations table is included so a restored database knows which migrations its tables are at.
+fn dumped_tables() -> Vec<(&'static str, ModelSchema)> {
+    let mut dumped = vec![(migrations::MIGRATIONS_TABLE, migrations::schema())];
+    dumped.extend(tables());
+    dumped
+}
+
+fn definition(table_id: &str, schema: &ModelSchema) -> Result<serde_json::Value, DatabaseError> {
+    serde_json::to_value(schema)
+        .map_err(|e| DatabaseError::InvalidArgument(format!("Failed to serialize schema for table {table_id}: {e}")))
+}
+
+/// Copy every table from `db`.
+/// Errors if `db` has pending migrations, as its tables may not match their schemas yet.
+pub async fn export(db: &dyn Database) -> Result<Dump, DatabaseError> {
+    let pending = migrations::pending(db).await?;
+    if !pending.is_empty() {
+        return Err(DatabaseError::InvalidArgument(format!("Database has {} pending migration(s), apply them before dumping it.", pending.len())));
+    }
+
+    let mut dump = Dump { format: DUMP_FORMAT, created_at: Utc::now().timestamp_millis(), tables: Vec::new() };
+    for (table_id, schema) in dumped_tables() {
+        let entries: Vec<EntryDump> = db.find_entries(table_id, db.filter().build()).await?
+            .into_iter()
+            .map(|(id, entry)| EntryDump { id, entry })
+            .collect();
+        log::debug!("Dumped table {table_id} ({} entries)", entries.len());
+        dump.tables.push(TableDump { table: table_id.into(), schema: definition(table_id, &schema)?, entries });
+    }
+    Ok(dump)
+}
+
+/// Checks that `dump` can be restored, every table must be present with the same definition as the current schema,
+/// and every entry must be valid for it.
+pub fn validate(dump: &Dump) -> Result<(), DatabaseError> {
+    if dump.format != DUMP_FORMAT {
+        return Err(DatabaseError::InvalidArgument(format!("Unsupported dump format {}, expected {DUMP_FORMAT}", dump.format)));
+    }
+
+    let dumped = dumped_tables();
+    if let Some(table) = dump.tables.iter().find(|table| !dumped.iter().any(|(table_id, _)| *table_id == table.table)) {
+        return Err(DatabaseError::SchemaViolation(format!("Dump contains unknown table {}", table.table)));
+    }
+
+    for (table_id, schema) in &dumped {
+        let Some(table) = dump.tables.iter().find(|table| table.table == *table_id) else {
+            return Err(DatabaseError::SchemaViolation(format!("Dump is missing table {table_id}")))
+        };
+        if table.schema != definition(table_id, schema)? {
+            return Err(DatabaseError::SchemaViolation(format!("Definition of table {table_id} doesn't match its current schema, the dumped database may need migrating first.")));
+        }
+        for EntryDump { id, entry } in &table.entries {
+            schema.validate(entry, false)
+                .map_err(|e| DatabaseError::SchemaViolation(format!("Invalid entry {id} in table {table_id}: {e}")))?;
+        }
+    }
+    Ok(())
+}
+
+/// Replace every table in `db` with the tables in `dump`.
+/// The dump is validated first, and is restored within a transaction so a failure leaves `db` untouched.
+pub async fn import(db: &dyn Database, dump: Dump) -> Result<(), DatabaseError> {
+    validate(&dump)?;
+
+    db.transaction(Box::new(move |db| Box::pin(async move {
+        for (table_id, schema) in dumped_tables() {
+            match db.drop_table(table_id).await {
+                Ok(()) | Err(DatabaseError::TableNotFound(_)) => {},
+                Err(e) => return Err(e)
+            }
+            db.create_table(table_id, &schema).await?;
+
+            let table = dump.tables.iter().find(|table| table.table == table_id).expect("Dump was validated");
+            let entries: Vec<_> = table.entries.iter().map(|EntryDump { id, entry }| (*id, entry.clone())).collect();
+            db.restore(table_id, &entries).await?;
+            log::debug!("Restored table {table_id} ({} entries)", table.entries.len());
+        }
+        Ok(())
+    }))).await
+}
+
+/// Dump `db` to the json file at `path`.
+pub async fn save(db: &dyn Database, path: &Path) -> Result<(), DatabaseError> {
+    let dump = export(db).await?;
+    let raw = serde_json::to_vec_pretty(&dump)
+        .map_err(|e| DatabaseError::InvalidArgument(format!("Failed to serialize dump: {e}")))?;
+    fs::write(path, raw)
+        .map_err(|e| DatabaseError::Backend(format!("Failed to write {}: {e}", path.display())))
+}
+
+/// Restore `db` from the json file at `path`.
+pub async fn load(db: &dyn Database, path: &Path) -> Result<(), DatabaseError> {
+    let raw = fs::read(path)
+        .map_err(|e| DatabaseError::Backend(format!("Failed to read {}: {e}", path.display())))?;
+    let dump = serde_json::from_slice::<Dump>(&raw)
+        .map_err(|e| DatabaseError::Parse(format!("Failed to parse {}: {e}", path.display())))?;
+    import(db, dump).await
+}
@@ -0,0 +1,42 @@
+use std::fmt::Display;
+
+/// Error returned by every [`Database`](super::Database) operation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DatabaseError {
+    /// No entry matched the filter, or there is no entry at the given location.
+    NotFound,
+    TableNotFound(String),
+    /// A write would give two entries the same value for a unique field.
+    Conflict { field: String },
+    /// Data written doesn't match the schema of the table, or a change to a table doesn't make sense for its schema.
+    SchemaViolation(String),
+    /// An entry read from the database couldn't be parsed to its model.
+    Parse(String),
+    /// The operation was given something it can't use, e.g. an invalid table id.
+    InvalidArgument(String),
+    /// The underlying storage failed, e.g. a file couldn't be written.
+    Backend(String)
+}
+
+impl DatabaseError {
+    /// Shorthand for checking whether the entry simply didn't exist.
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, DatabaseError::NotFound)
+    }
+}
+
+impl Display for DatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DatabaseError::NotFound => write!(f, "Failed to find entry matching the filter."),
+            DatabaseError::TableNotFound(table_id) => write!(f, "Failed to find table {table_id}"),
+            DatabaseError::Conflict { field } => write!(f, "An entry with the same `{field}` already exists"),
+            DatabaseError::SchemaViolation(message)
+            | DatabaseError::Parse(message)
+            | DatabaseError::InvalidArgument(message)
+            | DatabaseError::Backend(message) => write!(f, "{message}")
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
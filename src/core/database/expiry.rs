@@ -0,0 +1,56 @@
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+
+use super::{error::DatabaseError, expiring, Database};
+
+/// How often [`spawn_sweeper`] removes expired entries when run by the application.
+pub const SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// When entries of a table expire, see [`DatabaseModel::expiry`](super::DatabaseModel::expiry).
+///
+/// Each entry expires `ttl` after the time held in its `field`, in unix milliseconds.
+/// Entries without a value for `field` never expire.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Expiry {
+    pub field: &'static str,
+    pub ttl: Duration
+}
+
+impl Expiry {
+    /// Entries with a value for `field` before this have expired.
+    pub fn cutoff(&self) -> i64 {
+        (Utc::now() - self.ttl).timestamp_millis()
+    }
+}
+
+/// Permanently delete every expired entry in `table_id`, returning how many were deleted.
+pub async fn purge(db: &dyn Database, table_id: &str, expiry: Expiry) -> Result<usize, DatabaseError> {
+    let filter = db.filter().lt(expiry.field, expiry.cutoff().into()).build();
+    db.delete_many(table_id, filter).await
+}
+
+/// Permanently delete every expired entry in every table with an [`Expiry`], returning how many were deleted.
+pub async fn sweep(db: &dyn Database) -> Result<usize, DatabaseError> {
+    let mut purged = 0;
+    for (table_id, expiry) in expiring() {
+        let count = purge(db, table_id, expiry).await
+            .inspect_err(|e| log::error!("Failed to remove expired entries from {table_id}: {e}"))?;
+        if count > 0 {
+            log::debug!("Removed {count} expired entries from {table_id}");
+        }
+        purged += count;
+    }
+    Ok(purged)
+}
+
+/// Run [`sweep`] every `interval` in the background, for as long as the application runs.
+pub fn spawn_sweeper(db: Arc<dyn Database>, interval: std::time::Duration) {
+    async_std::task::spawn(async move {
+        loop {
+            // Failures are already logged, and the next sweep will try again.
+            let _ = sweep(&*db).await;
+            async_std::task::sleep(interval).await;
+        }
+    });
+}
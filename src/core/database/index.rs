@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use crate::core::models::ModelSchema;
+
+use super::{error::DatabaseError, DatabaseFilter, EntryId, FilterValue, PartialFilter};
+
+/// In-memory indexes over the entries of a table, for databases which hold their tables in memory.
+///
+/// Each indexed field maps the values found for it to the ids of the entries holding them.
+/// Entries are indexed by id rather than position, so removing an entry doesn't affect any other.
+/// Missing and null values aren't indexed.
+#[derive(Clone, Default, PartialEq)]
+pub struct Indexes {
+    unique: Vec<&'static str>,
+    fields: HashMap<&'static str, HashMap<String, Vec<EntryId>>>
+}
+
+impl Indexes {
+    /// Index `entries`, stored under `ids`, on every indexed field in `schema`.
+    /// Errors if two entries share a value for a unique field.
+    pub fn build(schema: &ModelSchema, ids: &[EntryId], entries: &[serde_json::Value]) -> Result<Indexes, DatabaseError> {
+        let mut indexes = Indexes {
+            unique: schema.unique.clone(),
+            fields: schema.indexes().into_iter().map(|field| (field, HashMap::new())).collect()
+        };
+
+        for (id, entry) in ids.iter().zip(entries) {
+            indexes.insert(*id, entry)?;
+        }
+        Ok(indexes)
+    }
+
+    /// Checks that `entry` doesn't share a value for any unique field with an existing entry, other than the entry `ignore`.
+    pub fn check(&self, entry: &serde_json::Value, ignore: Option<EntryId>) -> Result<(), DatabaseError> {
+        for field in &self.unique {
+            let conflict = index_key(entry.get(field))
+                .and_then(|key| self.fields[field].get(&key))
+                .is_some_and(|ids| ids.iter().any(|id| Some(*id) != ignore));
+
+            if conflict {
+                return Err(DatabaseError::Conflict { field: field.to_string() });
+            }
+        }
+        Ok(())
+    }
+
+    /// Index `entry`, stored under `id`.
+    /// Errors if it shares a value for a unique field with an existing entry, in which case nothing is indexed.
+    pub fn insert(&mut self, id: EntryId, entry: &serde_json::Value) -> Result<(), DatabaseError> {
+        self.check(entry, None)?;
+        for (field, index) in &mut self.fields {
+            if let Some(key) = index_key(entry.get(field)) {
+                let ids = index.entry(key).or_default();
+                // Kept sorted, so candidates are checked in the same order as a full scan.
+                if let Err(idx) = ids.binary_search(&id) {
+                    ids.insert(idx, id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove `entry`, stored under `id`, from the indexes.
+    pub fn remove(&mut self, id: EntryId, entry: &serde_json::Value) {
+        for (field, index) in &mut self.fields {
+            let Some(key) = index_key(entry.get(field)) else {
+                continue
+            };
+            if let Some(ids) = index.get_mut(&key) {
+                if let Ok(idx) = ids.binary_search(&id) {
+                    ids.remove(idx);
+                }
+                if ids.is_empty() {
+                    index.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// Entries which could match `filter`, along with their positions, in the order they are stored.
+    /// If the filter checks for equality on an indexed field only the entries found in that index are returned,
+    /// otherwise every entry is.
+    ///
+    /// `ids` are the ids of `entries`, which are sorted as ids only ever increase.
+    pub fn candidates<'a>(&'a self, ids: &'a [EntryId], entries: &'a [serde_json::Value], filter: &DatabaseFilter<FilterValue>) -> Box<dyn Iterator<Item = (usize, &'a serde_json::Value)> + 'a> {
+        let indexed = filter.0.iter().find_map(|partial| match partial {
+            PartialFilter::EQ { key, value } => {
+                let index = self.fields.get(key)?;
+                let found = index_key(Some(value)).and_then(|key| index.get(&key));
+                Some(found.map_or(&[][..], |found| &found[..]))
+            },
+            _ => None
+        });
+
+        match indexed {
+            Some(found) => Box::new(found.iter().filter_map(|id| {
+                let pos = ids.binary_search(id).ok()?;
+                Some((pos, &entries[pos]))
+            })),
+            None => Box::new(entries.iter().enumerate())
+        }
+    }
+
+    /// Location of the first entry matching `filter`.
+    pub fn find(&self, ids: &[EntryId], entries: &[serde_json::Value], filter: &DatabaseFilter<FilterValue>) -> Option<usize> {
+        self.candidates(ids, entries, filter)
+            .find(|(_, entry)| filter.matches(entry))
+            .map(|(pos, _)| pos)
+    }
+}
+
+fn index_key(value: Option<&serde_json::Value>) -> Option<String> {
+    value.filter(|value| !value.is_null()).map(|value| value.to_string())
+}
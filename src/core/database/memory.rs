@@ -0,0 +1,414 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::models::ModelSchema;
+
+use super::{aggregate::{AggregateGroup, Aggregation}, changes::{Change, ChangeEvent}, error::DatabaseError, index::Indexes, transaction::Operation, DatabaseFilter, DatabaseQuery, EntryId, FilterValue, QueryResult, TableChange, REVISION};
+
+/// How to undo a write to tables held in memory, one is kept for every write made within a transaction so it can be rolled back.
+pub enum Undo {
+    /// Revert changes made to entries.
+    Entries(Vec<Change>),
+    /// Put back a table as it was before its shape was changed, or remove it if it didn't exist.
+    Table { table_id: String, table: Option<Box<MemoryTable>> }
+}
+
+/// Apply `operation` to the table `table_id` within `tables`, adding how to undo it to `journal` if one is given.
+pub fn apply(tables: &mut HashMap<String, MemoryTable>, table_id: &str, operation: Operation<'_>, journal: Option<&mut Vec<Undo>>) -> Result<Vec<Change>, DatabaseError> {
+    // Changes to the shape of a table are undone by putting back the whole table, which is only copied within a transaction.
+    let reshaped = matches!(operation, Operation::CreateTable(_) | Operation::AlterTable(_) | Operation::DropTable);
+    let previous = (journal.is_some() && reshaped).then(|| tables.get(table_id).cloned().map(Box::new));
+
+    let changes = match operation {
+        Operation::CreateTable(schema) => {
+            match tables.get_mut(table_id) {
+                Some(table) => table.set_schema(schema)?,
+                None => {
+                    tables.insert(table_id.to_string(), MemoryTable::new(schema)?);
+                }
+            }
+            Vec::new()
+        },
+        Operation::DropTable => match tables.remove(table_id) {
+            Some(_) => Vec::new(),
+            None => return Err(DatabaseError::TableNotFound(table_id.into()))
+        },
+        operation => match tables.get_mut(table_id) {
+            Some(table) => table.apply(table_id, operation)?,
+            None => return Err(DatabaseError::TableNotFound(table_id.into()))
+        }
+    };
+
+    if let Some(journal) = journal {
+        journal.push(match previous {
+            Some(table) => Undo::Table { table_id: table_id.into(), table },
+            None => Undo::Entries(changes.clone())
+        });
+    }
+    Ok(changes)
+}
+
+/// Undo every write in `journal`, most recent last, returning the ids of the tables it changed.
+pub fn rollback(tables: &mut HashMap<String, MemoryTable>, journal: Vec<Undo>) -> Result<Vec<String>, DatabaseError> {
+    let mut changed: Vec<String> = Vec::new();
+    for undo in journal.into_iter().rev() {
+        let table_id = match undo {
+            Undo::Entries(changes) => {
+                let Some(table_id) = changes.first().map(|change| change.table_id.clone()) else {
+                    continue
+                };
+                if let Some(table) = tables.get_mut(&table_id) {
+                    table.revert(&changes)?;
+                }
+                table_id
+            },
+            Undo::Table { table_id, table: Some(table) } => {
+                tables.insert(table_id.clone(), *table);
+                table_id
+            },
+            Undo::Table { table_id, table: None } => {
+                tables.remove(&table_id);
+                table_id
+            }
+        };
+        if !changed.contains(&table_id) {
+            changed.push(table_id);
+        }
+    }
+    Ok(changed)
+}
+
+/// A table held entirely in memory, used by the databases which keep their tables in memory.
+///
+/// Entries are kept in insertion order alongside their ids, which only ever increase,
+/// so an id can be resolved with a binary search no matter how many entries have been deleted before it.
+///
+/// Serializing a table includes its schema, but not its indexes, which are built again with [`MemoryTable::reindex`] once it's deserialized.
+/// Tables serialized before schemas were included have none until one is set.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemoryTable {
+    #[serde(default)]
+    schema: Option<ModelSchema>,
+    next_id: EntryId,
+    ids: Vec<EntryId>,
+    entries: Vec<serde_json::Value>,
+    /// Built from the schema, tables without one aren't indexed.
+    #[serde(skip)]
+    indexes: Indexes
+}
+
+impl MemoryTable {
+    /// Create an empty table for entries described by `schema`.
+    pub fn new(schema: &ModelSchema) -> Result<MemoryTable, DatabaseError> {
+        let mut table = MemoryTable { schema: None, next_id: 1, ids: Vec::new(), entries: Vec::new(), indexes: Indexes::default() };
+        table.set_schema(schema)?;
+        Ok(table)
+    }
+
+    pub fn schema(&self) -> Option<&ModelSchema> {
+        self.schema.as_ref()
+    }
+
+    /// Replace the schema of the table, indexing every entry again.
+    /// Errors if existing entries share a value for a unique field in `schema`.
+    pub fn set_schema(&mut self, schema: &ModelSchema) -> Result<(), DatabaseError> {
+        self.indexes = Indexes::build(schema, &self.ids, &self.entries)?;
+        self.schema = Some(schema.clone());
+        Ok(())
+    }
+
+    /// Build the indexes of a table which has just been deserialized, as they aren't serialized along with it.
+    pub fn reindex(&mut self) -> Result<(), DatabaseError> {
+        if let Some(schema) = &self.schema {
+            self.indexes = Indexes::build(schema, &self.ids, &self.entries)?;
+        }
+        Ok(())
+    }
+
+    /// Apply `change` to the schema and every entry.
+    /// The table is left untouched if the change fails, and tables without a schema can't be altered as the change can't be checked.
+    pub fn alter(&mut self, table_id: &str, change: &TableChange) -> Result<(), DatabaseError> {
+        let mut altered = self.clone();
+        let schema = altered.schema.as_mut().ok_or_else(|| DatabaseError::SchemaViolation(format!("Table {table_id} has no schema, it must be created before it can be altered.")))?;
+        change.apply_to_schema(schema)
+            .map_err(|e| DatabaseError::SchemaViolation(format!("Failed to alter table {table_id}: {e}")))?;
+        altered.entries.iter_mut().for_each(|entry| change.apply_to_entry(entry));
+        altered.reindex()?;
+
+        *self = altered;
+        Ok(())
+    }
+
+    /// Apply `operation` to the table, returning the changes it made to entries.
+    /// Tables can't create or drop themselves, that's left to whatever holds them.
+    pub fn apply(&mut self, table_id: &str, operation: Operation<'_>) -> Result<Vec<Change>, DatabaseError> {
+        match operation {
+            Operation::AlterTable(change) => self.alter(table_id, change).map(|_| Vec::new()),
+            Operation::Insert(data) => Ok(vec![self.insert(table_id, data)?]),
+            Operation::InsertMany(data) => self.insert_many(table_id, data),
+            Operation::Restore(entries) => self.restore(table_id, entries),
+            Operation::Update(filter, data) => Ok(vec![self.update(table_id, filter, data)?]),
+            Operation::UpdateMany(filter, data) => self.update_many(table_id, filter, data),
+            Operation::Delete(filter) => Ok(vec![self.delete(table_id, filter)?]),
+            Operation::DeleteMany(filter) => self.delete_many(table_id, filter),
+            Operation::CreateTable(_) | Operation::DropTable => Err(DatabaseError::InvalidArgument(format!("Table {table_id} can't be created or dropped by itself.")))
+        }
+    }
+
+    pub fn insert(&mut self, table_id: &str, data: &serde_json::Value) -> Result<Change, DatabaseError> {
+        self.writable(table_id)?.validate(data, false)
+            .map_err(|e| DatabaseError::SchemaViolation(format!("Invalid entry for table {table_id}: {e}")))?;
+        let id = self.next_id;
+        self.indexes.insert(id, data)?;
+
+        self.next_id += 1;
+        self.ids.push(id);
+        self.entries.push(data.clone());
+        Ok(Change { table_id: table_id.into(), id, event: ChangeEvent::Insert { new: data.clone() } })
+    }
+
+    /// Insert every entry in `data`, if any of them can't be inserted none of them are.
+    pub fn insert_many(&mut self, table_id: &str, data: &[serde_json::Value]) -> Result<Vec<Change>, DatabaseError> {
+        let next_id = self.next_id;
+        let mut changes = Vec::with_capacity(data.len());
+        for entry in data {
+            match self.insert(table_id, entry) {
+                Ok(change) => changes.push(change),
+                Err(e) => {
+                    // None of the ids were ever seen, so they can be given out again.
+                    self.revert(&changes)?;
+                    self.next_id = next_id;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Insert every entry in `entries` under the id given with it, if any of them can't be inserted none of them are.
+    pub fn restore(&mut self, table_id: &str, entries: &[(EntryId, serde_json::Value)]) -> Result<Vec<Change>, DatabaseError> {
+        let next_id = self.next_id;
+        let mut changes = Vec::with_capacity(entries.len());
+        for (id, entry) in entries {
+            match self.restore_one(table_id, *id, entry) {
+                Ok(change) => changes.push(change),
+                Err(e) => {
+                    self.revert(&changes)?;
+                    self.next_id = next_id;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(changes)
+    }
+
+    pub fn update(&mut self, table_id: &str, filter: &DatabaseFilter<FilterValue>, data: &serde_json::Value) -> Result<Change, DatabaseError> {
+        let fields = self.updatable(table_id, data)?;
+        let pos = self.position(table_id, filter)?;
+        self.update_at(table_id, pos, fields)
+    }
+
+    /// Update every entry matching `filter`, if any of them can't be updated none of them are.
+    pub fn update_many(&mut self, table_id: &str, filter: &DatabaseFilter<FilterValue>, data: &serde_json::Value) -> Result<Vec<Change>, DatabaseError> {
+        let fields = self.updatable(table_id, data)?;
+        if fields.is_empty() {
+            return Ok(Vec::new());
+        }
+        let positions = self.positions(table_id, filter)?;
+
+        let mut changes = Vec::with_capacity(positions.len());
+        for pos in positions {
+            match self.update_at(table_id, pos, fields) {
+                Ok(change) => changes.push(change),
+                Err(e) => {
+                    self.revert(&changes)?;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(changes)
+    }
+
+    pub fn delete(&mut self, table_id: &str, filter: &DatabaseFilter<FilterValue>) -> Result<Change, DatabaseError> {
+        self.writable(table_id)?;
+        let pos = self.position(table_id, filter)?;
+        Ok(self.delete_at(table_id, &[pos]).remove(0))
+    }
+
+    pub fn delete_many(&mut self, table_id: &str, filter: &DatabaseFilter<FilterValue>) -> Result<Vec<Change>, DatabaseError> {
+        self.writable(table_id)?;
+        let positions = self.positions(table_id, filter)?;
+        if positions.is_empty() {
+            return Ok(Vec::new());
+        }
+        Ok(self.delete_at(table_id, &positions))
+    }
+
+    pub fn get(&self, table_id: &str, filter: &DatabaseFilter<FilterValue>) -> Result<serde_json::Value, DatabaseError> {
+        Ok(self.entries[self.position(table_id, filter)?].clone())
+    }
+
+    pub fn find(&self, table_id: &str, filter: &DatabaseFilter<FilterValue>) -> Result<EntryId, DatabaseError> {
+        Ok(self.ids[self.position(table_id, filter)?])
+    }
+
+    pub fn find_entries(&self, table_id: &str, filter: &DatabaseFilter<FilterValue>) -> Result<Vec<(EntryId, serde_json::Value)>, DatabaseError> {
+        Ok(self.positions(table_id, filter)?.into_iter().map(|pos| (self.ids[pos], self.entries[pos].clone())).collect())
+    }
+
+    pub fn get_by_id(&self, id: EntryId) -> Result<serde_json::Value, DatabaseError> {
+        match self.ids.binary_search(&id) {
+            Ok(pos) => Ok(self.entries[pos].clone()),
+            Err(_) => Err(DatabaseError::NotFound)
+        }
+    }
+
+    pub fn query(&self, table_id: &str, query: &DatabaseQuery<FilterValue>) -> Result<QueryResult, DatabaseError> {
+        self.check_keys(table_id, &query.keys())?;
+        let candidates = self.indexes.candidates(&self.ids, &self.entries, &query.filter);
+        Ok(query.apply(candidates.map(|(_, entry)| entry)))
+    }
+
+    pub fn aggregate(&self, table_id: &str, aggregation: &Aggregation<FilterValue>) -> Result<Vec<AggregateGroup>, DatabaseError> {
+        self.check_keys(table_id, &aggregation.filter.keys())?;
+        let candidates = self.indexes.candidates(&self.ids, &self.entries, &aggregation.filter);
+        Ok(aggregation.apply(candidates.map(|(_, entry)| entry)))
+    }
+
+    /// Store `entry` under `id`, replacing the entry already stored under it if there is one.
+    /// The entry isn't validated against the schema, this is for restoring entries the table has held before.
+    pub fn put(&mut self, id: EntryId, entry: serde_json::Value) -> Result<(), DatabaseError> {
+        match self.ids.binary_search(&id) {
+            Ok(pos) => {
+                self.replace_at(pos, entry)?;
+            },
+            Err(pos) => {
+                self.indexes.insert(id, &entry)?;
+                self.ids.insert(pos, id);
+                self.entries.insert(pos, entry);
+            }
+        }
+        self.next_id = self.next_id.max(id + 1);
+        Ok(())
+    }
+
+    /// Remove the entry stored under `id`, if there is one.
+    pub fn remove(&mut self, id: EntryId) -> Option<serde_json::Value> {
+        let pos = self.ids.binary_search(&id).ok()?;
+        self.ids.remove(pos);
+        let entry = self.entries.remove(pos);
+        self.indexes.remove(id, &entry);
+        Some(entry)
+    }
+
+    /// Undo `changes`, which were made to this table, most recent last.
+    pub fn revert(&mut self, changes: &[Change]) -> Result<(), DatabaseError> {
+        for change in changes.iter().rev() {
+            match &change.event {
+                ChangeEvent::Insert { .. } => {
+                    self.remove(change.id);
+                },
+                ChangeEvent::Update { old, .. } | ChangeEvent::Delete { old } => self.put(change.id, old.clone())?
+            }
+        }
+        Ok(())
+    }
+
+    fn restore_one(&mut self, table_id: &str, id: EntryId, entry: &serde_json::Value) -> Result<Change, DatabaseError> {
+        self.writable(table_id)?.validate(entry, false)
+            .map_err(|e| DatabaseError::SchemaViolation(format!("Invalid entry for table {table_id}: {e}")))?;
+        if self.ids.binary_search(&id).is_ok() {
+            return Err(DatabaseError::Conflict { field: "id".into() });
+        }
+
+        self.put(id, entry.clone())?;
+        Ok(Change { table_id: table_id.into(), id, event: ChangeEvent::Insert { new: entry.clone() } })
+    }
+
+    /// Writes are only allowed once the table has a schema to validate against.
+    fn writable(&self, table_id: &str) -> Result<&ModelSchema, DatabaseError> {
+        match &self.schema {
+            Some(schema) => Ok(schema),
+            None => Err(DatabaseError::SchemaViolation(format!("Table {table_id} has no schema, it must be created before it can be written to.")))
+        }
+    }
+
+    /// Checks that `data` is a valid update, returning the fields it sets.
+    fn updatable<'a>(&self, table_id: &str, data: &'a serde_json::Value) -> Result<&'a serde_json::Map<String, serde_json::Value>, DatabaseError> {
+        let Some(fields) = data.as_object() else {
+            return Err(DatabaseError::InvalidArgument("Update data must be an object.".into()))
+        };
+        self.writable(table_id)?.validate(data, true)
+            .map_err(|e| DatabaseError::SchemaViolation(format!("Invalid update for table {table_id}: {e}")))?;
+        Ok(fields)
+    }
+
+    fn update_at(&mut self, table_id: &str, pos: usize, fields: &serde_json::Map<String, serde_json::Value>) -> Result<Change, DatabaseError> {
+        let mut entry = self.entries[pos].clone();
+        for (key, value) in fields {
+            entry[key] = value.clone();
+        }
+        if !fields.is_empty() && self.schema.as_ref().is_some_and(|schema| schema.field(REVISION).is_some()) {
+            entry[REVISION] = (self.entries[pos][REVISION].as_u64().unwrap_or(0) + 1).into();
+        }
+
+        let old = self.replace_at(pos, entry.clone())?;
+        Ok(Change { table_id: table_id.into(), id: self.ids[pos], event: ChangeEvent::Update { old, new: entry } })
+    }
+
+    /// Replace the entry at `pos` with `entry`, returning the entry it replaced.
+    fn replace_at(&mut self, pos: usize, entry: serde_json::Value) -> Result<serde_json::Value, DatabaseError> {
+        let id = self.ids[pos];
+        self.indexes.check(&entry, Some(id))?;
+        self.indexes.remove(id, &self.entries[pos]);
+        self.indexes.insert(id, &entry)?;
+        Ok(std::mem::replace(&mut self.entries[pos], entry))
+    }
+
+    /// Remove the entries at `positions`, which must be sorted and not empty.
+    fn delete_at(&mut self, table_id: &str, positions: &[usize]) -> Vec<Change> {
+        let mut changes = Vec::with_capacity(positions.len());
+        let mut removed = positions.iter().peekable();
+
+        // Entries are shifted down over the removed ones in a single pass, everything before the first stays where it is.
+        let mut kept = positions[0];
+        for pos in positions[0]..self.entries.len() {
+            if removed.next_if_eq(&&pos).is_some() {
+                let (id, old) = (self.ids[pos], std::mem::take(&mut self.entries[pos]));
+                self.indexes.remove(id, &old);
+                changes.push(Change { table_id: table_id.into(), id, event: ChangeEvent::Delete { old } });
+            } else {
+                self.ids.swap(kept, pos);
+                self.entries.swap(kept, pos);
+                kept += 1;
+            }
+        }
+        self.ids.truncate(kept);
+        self.entries.truncate(kept);
+        changes
+    }
+
+    /// Errors if any of `keys` isn't a field in the schema, tables without a schema yet can't be checked.
+    fn check_keys(&self, table_id: &str, keys: &[&str]) -> Result<(), DatabaseError> {
+        match &self.schema {
+            Some(schema) => super::check_keys(table_id, keys, |key| schema.field(key).is_some()),
+            None => Ok(())
+        }
+    }
+
+    /// Positions of every entry matching `filter`, in order.
+    fn positions(&self, table_id: &str, filter: &DatabaseFilter<FilterValue>) -> Result<Vec<usize>, DatabaseError> {
+        self.check_keys(table_id, &filter.keys())?;
+        Ok(self.indexes.candidates(&self.ids, &self.entries, filter)
+            .filter(|(_, entry)| filter.matches(entry))
+            .map(|(pos, _)| pos)
+            .collect())
+    }
+
+    /// Position of the first entry matching `filter`.
+    fn position(&self, table_id: &str, filter: &DatabaseFilter<FilterValue>) -> Result<usize, DatabaseError> {
+        self.check_keys(table_id, &filter.keys())?;
+        self.indexes.find(&self.ids, &self.entries, filter).ok_or(DatabaseError::NotFound)
+    }
+}
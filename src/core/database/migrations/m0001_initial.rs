@@ -0,0 +1,51 @@
+use async_trait::async_trait;
+
+use crate::core::{database::{error::DatabaseError, Database}, models::{ModelSchema, ModelValueType}};
+
+use super::Migration;
+
+/// Creates the `accounts` and `sessions` tables.
+pub struct Initial;
+
+#[async_trait]
+impl Migration for Initial {
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn name(&self) -> &'static str {
+        "initial"
+    }
+
+    async fn up(&self, db: &dyn Database) -> Result<(), DatabaseError> {
+        db.create_table("accounts", &ModelSchema {
+            fields: vec![
+                ModelValueType::String { field: "uid" },
+                ModelValueType::String { field: "firstname" },
+                ModelValueType::String { field: "surname" },
+                ModelValueType::String { field: "email" },
+                ModelValueType::String { field: "pass_hash" }
+            ],
+            optional: Vec::new(),
+            unique: Vec::new(),
+            indexed: Vec::new()
+        }).await?;
+
+        db.create_table("sessions", &ModelSchema {
+            fields: vec![
+                ModelValueType::String  { field: "session_id" },
+                ModelValueType::String  { field: "uid" },
+                ModelValueType::Number  { field: "created" },
+                ModelValueType::Boolean { field: "valid" }
+            ],
+            optional: Vec::new(),
+            unique: Vec::new(),
+            indexed: Vec::new()
+        }).await
+    }
+
+    async fn down(&self, db: &dyn Database) -> Result<(), DatabaseError> {
+        db.drop_table("sessions").await?;
+        db.drop_table("accounts").await
+    }
+}
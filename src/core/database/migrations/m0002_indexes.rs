@@ -0,0 +1,40 @@
+use async_trait::async_trait;
+
+use crate::core::database::{error::DatabaseError, Database, TableChange};
+
+use super::Migration;
+
+/// Indexes accounts and sessions, making emails unique.
+pub struct Indexes;
+
+const INDEXES: [(&str, &str, bool); 4] = [
+    ("accounts", "uid", true),
+    ("accounts", "email", true),
+    ("sessions", "session_id", true),
+    ("sessions", "uid", false)
+];
+
+#[async_trait]
+impl Migration for Indexes {
+    fn version(&self) -> u32 {
+        2
+    }
+
+    fn name(&self) -> &'static str {
+        "indexes"
+    }
+
+    async fn up(&self, db: &dyn Database) -> Result<(), DatabaseError> {
+        for (table_id, field, unique) in INDEXES {
+            db.alter_table(table_id, &TableChange::AddIndex { field, unique }).await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, db: &dyn Database) -> Result<(), DatabaseError> {
+        for (table_id, field, _) in INDEXES.into_iter().rev() {
+            db.alter_table(table_id, &TableChange::DropIndex { field }).await?;
+        }
+        Ok(())
+    }
+}
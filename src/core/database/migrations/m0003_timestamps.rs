@@ -0,0 +1,42 @@
+use async_trait::async_trait;
+
+use crate::core::{database::{error::DatabaseError, Database, TableChange}, models::ModelValueType};
+
+use super::Migration;
+
+/// Adds timestamps to accounts and sessions, and soft deletion to accounts.
+/// Existing entries are left without timestamps, as when they were created isn't known.
+pub struct Timestamps;
+
+const FIELDS: [(&str, &str); 5] = [
+    ("accounts", "created_at"),
+    ("accounts", "updated_at"),
+    ("accounts", "deleted_at"),
+    ("sessions", "created_at"),
+    ("sessions", "updated_at")
+];
+
+#[async_trait]
+impl Migration for Timestamps {
+    fn version(&self) -> u32 {
+        3
+    }
+
+    fn name(&self) -> &'static str {
+        "timestamps"
+    }
+
+    async fn up(&self, db: &dyn Database) -> Result<(), DatabaseError> {
+        for (table_id, field) in FIELDS {
+            db.alter_table(table_id, &TableChange::AddField { field: ModelValueType::Number { field }, default: serde_json::Value::Null }).await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, db: &dyn Database) -> Result<(), DatabaseError> {
+        for (table_id, field) in FIELDS.into_iter().rev() {
+            db.alter_table(table_id, &TableChange::DropField { field }).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,39 @@
+use async_trait::async_trait;
+
+use crate::core::{database::{error::DatabaseError, Database}, models::{ModelSchema, ModelValueType}};
+
+use super::Migration;
+
+/// Creates the `audit_log` table.
+pub struct AuditLog;
+
+#[async_trait]
+impl Migration for AuditLog {
+    fn version(&self) -> u32 {
+        4
+    }
+
+    fn name(&self) -> &'static str {
+        "audit_log"
+    }
+
+    async fn up(&self, db: &dyn Database) -> Result<(), DatabaseError> {
+        db.create_table("audit_log", &ModelSchema {
+            fields: vec![
+                ModelValueType::String { field: "uid" },
+                ModelValueType::Number { field: "timestamp" },
+                ModelValueType::String { field: "table_id" },
+                ModelValueType::Number { field: "entry_id" },
+                ModelValueType::String { field: "action" },
+                ModelValueType::Object { field: "diff" }
+            ],
+            optional: vec!["uid"],
+            unique: Vec::new(),
+            indexed: vec!["uid", "entry_id"]
+        }).await
+    }
+
+    async fn down(&self, db: &dyn Database) -> Result<(), DatabaseError> {
+        db.drop_table("audit_log").await
+    }
+}
@@ -0,0 +1,36 @@
+use async_trait::async_trait;
+
+use crate::core::{database::{error::DatabaseError, Database, TableChange}, models::ModelValueType};
+
+use super::Migration;
+
+/// Adds revisions to accounts and sessions.
+/// Existing entries are left without a revision, which is treated as revision 0.
+pub struct Revisions;
+
+const TABLES: [&str; 2] = ["accounts", "sessions"];
+
+#[async_trait]
+impl Migration for Revisions {
+    fn version(&self) -> u32 {
+        5
+    }
+
+    fn name(&self) -> &'static str {
+        "revisions"
+    }
+
+    async fn up(&self, db: &dyn Database) -> Result<(), DatabaseError> {
+        for table_id in TABLES {
+            db.alter_table(table_id, &TableChange::AddField { field: ModelValueType::Number { field: "revision" }, default: serde_json::Value::Null }).await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, db: &dyn Database) -> Result<(), DatabaseError> {
+        for table_id in TABLES.into_iter().rev() {
+            db.alter_table(table_id, &TableChange::DropField { field: "revision" }).await?;
+        }
+        Ok(())
+    }
+}
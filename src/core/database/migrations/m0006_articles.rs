@@ -0,0 +1,39 @@
+use async_trait::async_trait;
+
+use crate::core::{database::{error::DatabaseError, Database}, models::{ModelSchema, ModelValueType}};
+
+use super::Migration;
+
+/// Creates the `articles` table.
+pub struct Articles;
+
+#[async_trait]
+impl Migration for Articles {
+    fn version(&self) -> u32 {
+        6
+    }
+
+    fn name(&self) -> &'static str {
+        "articles"
+    }
+
+    async fn up(&self, db: &dyn Database) -> Result<(), DatabaseError> {
+        db.create_table("articles", &ModelSchema {
+            fields: vec![
+                ModelValueType::String { field: "section" },
+                ModelValueType::String { field: "title" },
+                ModelValueType::String { field: "body" },
+                ModelValueType::Number { field: "created_at" },
+                ModelValueType::Number { field: "updated_at" },
+                ModelValueType::Number { field: "deleted_at" }
+            ],
+            optional: vec!["created_at", "updated_at", "deleted_at"],
+            unique: Vec::new(),
+            indexed: vec!["section"]
+        }).await
+    }
+
+    async fn down(&self, db: &dyn Database) -> Result<(), DatabaseError> {
+        db.drop_table("articles").await
+    }
+}
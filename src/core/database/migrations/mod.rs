@@ -0,0 +1,150 @@
+mod m0001_initial;
+mod m0002_indexes;
+mod m0003_timestamps;
+mod m0004_audit_log;
+mod m0005_revisions;
+mod m0006_articles;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use serde_json::json;
+
+use crate::core::models::{ModelSchema, ModelValueType};
+
+use super::{error::DatabaseError, Database, DatabaseQuery, SortOrder};
+
+/// Table used to record which migrations have been applied.
+pub const MIGRATIONS_TABLE: &str = "_migrations";
+
+/// A single versioned change to the database.
+///
+/// Migrations are applied in order of their version, and each is run within a transaction.
+/// Tables should be described explicitly within a migration, rather than using [`DatabaseModel::schema`](super::DatabaseModel::schema),
+/// so that applying it isn't affected by later changes to the model.
+#[async_trait]
+pub trait Migration: Send + Sync {
+    /// Unique version, each migration must have a greater version than the one before it.
+    fn version(&self) -> u32;
+
+    fn name(&self) -> &'static str;
+
+    /// Apply the migration.
+    async fn up(&self, db: &dyn Database) -> Result<(), DatabaseError>;
+
+    /// Undo everything done by [`Migration::up`].
+    async fn down(&self, db: &dyn Database) -> Result<(), DatabaseError>;
+}
+
+/// Every migration, in the order they are applied.
+pub fn migrations() -> Vec<Box<dyn Migration>> {
+    vec![
+        Box::new(m0001_initial::Initial),
+        Box::new(m0002_indexes::Indexes),
+        Box::new(m0003_timestamps::Timestamps),
+        Box::new(m0004_audit_log::AuditLog),
+        Box::new(m0005_revisions::Revisions),
+        Box::new(m0006_articles::Articles),
+        // add as needed
+    ]
+}
+
+/// Schema of [`MIGRATIONS_TABLE`].
+pub fn schema() -> ModelSchema {
+    ModelSchema {
+        fields: vec![
+            ModelValueType::Number { field: "version" },
+            ModelValueType::String { field: "name" },
+            ModelValueType::Number { field: "applied_at" },
+        ],
+        optional: Vec::new(),
+        unique: vec!["version"],
+        indexed: Vec::new()
+    }
+}
+
+/// Versions of every migration applied to `db`, in ascending order.
+pub async fn applied(db: &dyn Database) -> Result<Vec<u32>, DatabaseError> {
+    db.create_table(MIGRATIONS_TABLE, &schema()).await?;
+
+    let query = DatabaseQuery::new(db.filter().build())
+        .order_by("version", SortOrder::Ascending);
+    let result = db.query(MIGRATIONS_TABLE, query).await?;
+
+    Ok(result.entries.iter()
+        .filter_map(|entry| entry["version"].as_u64())
+        .map(|version| version as u32)
+        .collect())
+}
+
+/// Versions of every migration which hasn't been applied to `db` yet.
+pub async fn pending(db: &dyn Database) -> Result<Vec<u32>, DatabaseError> {
+    let applied = applied(db).await?;
+    Ok(migrations().iter()
+        .map(|migration| migration.version())
+        .filter(|version| !applied.contains(version))
+        .collect())
+}
+
+/// Apply every pending migration up to and including version `target`, or every pending migration if `None`.
+///
+/// Returns the versions that were applied.
+pub async fn migrate(db: &dyn Database, target: Option<u32>) -> Result<Vec<u32>, DatabaseError> {
+    let applied = applied(db).await?;
+    let mut migrated = Vec::new();
+
+    for migration in migrations() {
+        let version = migration.version();
+        if applied.contains(&version) || target.is_some_and(|target| version > target) {
+            continue;
+        }
+
+        log::info!("Applying migration {version} ({})", migration.name());
+        db.transaction(Box::new(move |db| Box::pin(async move {
+            migration.up(db).await?;
+            db.insert(MIGRATIONS_TABLE, &json!({
+                "version": version,
+                "name": migration.name(),
+                "applied_at": Utc::now().timestamp_millis()
+            })).await?;
+            Ok(())
+        }))).await.inspect_err(|e| log::error!("Failed to apply migration {version}: {e}"))?;
+
+        migrated.push(version);
+    }
+    Ok(migrated)
+}
+
+/// Roll back every applied migration with a version greater than `target`, or only the latest migration if `None`.
+///
+/// Returns the versions that were rolled back.
+pub async fn rollback(db: &dyn Database, target: Option<u32>) -> Result<Vec<u32>, DatabaseError> {
+    let applied = applied(db).await?;
+    let rollback: Vec<u32> = match target {
+        Some(target) => applied.iter().copied().filter(|version| *version > target).collect(),
+        None => applied.last().copied().into_iter().collect()
+    };
+
+    let mut migrations = migrations();
+    if let Some(version) = rollback.iter().find(|version| !migrations.iter().any(|migration| migration.version() == **version)) {
+        return Err(DatabaseError::InvalidArgument(format!("Migration {version} has been applied but no longer exists, it can't be rolled back.")));
+    }
+
+    let mut rolled_back = Vec::new();
+    migrations.reverse();
+    for migration in migrations {
+        let version = migration.version();
+        if !rollback.contains(&version) {
+            continue;
+        }
+
+        log::info!("Rolling back migration {version} ({})", migration.name());
+        db.transaction(Box::new(move |db| Box::pin(async move {
+            migration.down(db).await?;
+            db.delete(MIGRATIONS_TABLE, db.filter().eq("version", version.into()).build()).await?;
+            Ok(())
+        }))).await.inspect_err(|e| log::error!("Failed to roll back migration {version}: {e}"))?;
+
+        rolled_back.push(version);
+    }
+    Ok(rolled_back)
+}
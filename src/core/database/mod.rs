@@ -1,18 +1,59 @@
+pub mod error;
+pub mod aggregate;
+pub mod changes;
 pub mod volatile;
+pub mod persistent;
+pub mod sqlite;
+pub mod migrations;
+pub mod expiry;
+pub mod audit;
+pub mod cache;
+pub mod dump;
+pub mod index;
+pub mod memory;
+pub mod repository;
+pub mod search;
+pub mod seed;
+pub mod transaction;
+#[cfg(test)]
+pub mod conformance;
 
-use std::sync::Arc;
+use std::{cmp::Ordering, future::Future, pin::Pin, sync::Arc};
 
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 
-use super::{models::{ModelValueType, account::Account, session::Session}, accounts};
+use async_std::channel::Receiver;
+
+pub use hag_website_derive::DatabaseModel;
+
+use self::{aggregate::{AggregateGroup, Aggregation}, cache::CachePolicy, changes::Change, error::DatabaseError, expiry::Expiry, transaction::Operation};
+
+use super::models::{ModelSchema, ModelValueType, account::Account, article::Article, audit::AuditEntry, session::Session};
 
 pub type FilterValue = serde_json::Value;
-pub type EntryLocation = usize;
 
-static mut DATABASE: Option<Arc<dyn Database>> = None;
+/// Time an entry was inserted, in unix milliseconds, see [`DatabaseModel::timestamps`].
+pub const CREATED_AT: &str = "created_at";
+/// Time an entry was last updated, in unix milliseconds, see [`DatabaseModel::timestamps`].
+pub const UPDATED_AT: &str = "updated_at";
+/// Time an entry was deleted, in unix milliseconds, see [`DatabaseModel::soft_delete`].
+pub const DELETED_AT: &str = "deleted_at";
+/// Revision of an entry, see [`DatabaseModel::revisions`].
+///
+/// Tables with this field have it set to the stored revision plus one by every update, so revisions count the writes to an entry
+/// and are never reused, however many processes share the database.
+pub const REVISION: &str = "revision";
+/// Id given to an entry when it is inserted, ids are never reused within a table.
+pub type EntryId = u64;
+
+/// Future returned by the operation run within [`Database::transaction`].
+pub type TransactionFuture<'t> = Pin<Box<dyn Future<Output = Result<(), DatabaseError>> + Send + 't>>;
+/// Operation run within [`Database::transaction`], it is given the database to run its queries against.
+pub type TransactionFn = Box<dyn for<'t> FnOnce(&'t dyn Database) -> TransactionFuture<'t> + Send>;
 
 /// Attempts to initialize database with type `<T>`.
+/// The returned instance is shared through [`ApplicationState`](crate::core::state::ApplicationState).
 /// 
 /// # Arguments
 /// * `location` - [`String`] containing a database location that can be interpreted by the given database `<T>`. May not be necessary for some systems. (e.g. Volatile)
@@ -21,58 +62,73 @@ static mut DATABASE: Option<Arc<dyn Database>> = None;
 /// ```
 /// use crate::core::{database, volatile::VolatileDb};
 /// 
-/// match database::init<VolatileDb>("").await {
-///     Ok(_) => log::info!("Initialized database!"),
+/// match database::init::<VolatileDb>("").await {
+///     Ok(db) => log::info!("Initialized database!"),
 ///     Err(_) => log::error!("Failed to initialize database!")
 /// };
 /// 
-pub async fn init<T: Database + 'static>(location: &str) -> Result<(), ()> {
+pub async fn init<T: Database + 'static>(location: &str) -> Result<Arc<dyn Database>, DatabaseError> {
     log::debug!("Initalizing database...");
-    unsafe {
-        if let None = DATABASE {
-            DATABASE = Some(
-                T::init(location).await
-            );
-            return Ok(())
-        }
-        Err(())
-    }
+    Ok(T::init(location).await?)
 }
 
-/// Returns the current database instance, if present.
-/// 
-/// # Panics
-/// Will panic if database has not yet been initialized.
-pub fn get() -> Arc<dyn Database> {
-    unsafe {
-        if let Some(db) = &DATABASE {
-            return db.clone();
-        }
-        panic!("Database hasn't been initialized!")
-    }
+/// Every table used by the application, along with the schema of its model.
+pub fn tables() -> Vec<(&'static str, ModelSchema)> {
+    vec![
+        (Account::table(), Account::schema()),
+        (Session::table(), Session::schema()),
+        (AuditEntry::table(), AuditEntry::schema()),
+        (Article::table(), Article::schema()),
+        // add as needed
+    ]
+}
+
+/// Every table whose entries expire, along with when they do.
+pub fn expiring() -> Vec<(&'static str, Expiry)> {
+    [
+        (Account::table(), Account::expiry()),
+        (Session::table(), Session::expiry()),
+        // add as needed
+    ].into_iter().filter_map(|(table_id, expiry)| Some((table_id, expiry?))).collect()
 }
 
-/// Populate the database instance with required tables.
-pub async fn setup() {
-    log::debug!("Creating tables...");
-    let db = get();
-    db.create_table("accounts", &Account::fields()).await.unwrap();
-    db.create_table("sessions", &Session::fields()).await.unwrap();
-    // add as needed
+/// Every table whose reads are cached by [`cache::CachedDb`], along with how they are.
+pub fn cached() -> Vec<(&'static str, CachePolicy)> {
+    [
+        (Account::table(), Account::cache()),
+        (Session::table(), Session::cache()),
+        // add as needed
+    ].into_iter().filter_map(|(table_id, policy)| Some((table_id, policy?))).collect()
 }
 
-/// Populate the database with dummy data, primarily for testing.
-pub async fn dummy() {    
-    log::debug!("Creating dummy data...");
-    let dummy_acc = Account {
-        uid: "".into(),
-        firstname: "Real".into(),
-        surname: "Person".into(),
-        email: "person@email.com".into(),
-        pass_hash: "TestPassword123".into(),
-    };
+/// Every table whose entries can be found with [`search::Search`], along with the fields searched.
+pub fn searchable() -> Vec<(&'static str, Vec<&'static str>)> {
+    [
+        (Account::table(), Account::searchable()),
+        (Session::table(), Session::searchable()),
+        (Article::table(), Article::searchable()),
+        // add as needed
+    ].into_iter().filter(|(_, fields)| !fields.is_empty()).collect()
+}
 
-    log::debug!("Dummy UID {:?}", accounts::register(dummy_acc, true, true).await.unwrap());
+/// Every table with fields whose values are redacted from the audit log, along with those fields.
+pub fn sensitive() -> Vec<(&'static str, Vec<&'static str>)> {
+    [
+        (Account::table(), Account::sensitive()),
+        (Session::table(), Session::sensitive()),
+        // add as needed
+    ].into_iter().filter(|(_, fields)| !fields.is_empty()).collect()
+}
+
+/// Register the schema of every table with the database instance.
+///
+/// Tables are created by [`migrations`], this should be run after they have been applied
+/// so the database knows the current shape of each table.
+pub async fn setup(db: &dyn Database) {
+    log::debug!("Registering tables...");
+    for (table_id, schema) in tables() {
+        db.create_table(table_id, &schema).await.unwrap();
+    }
 }
 
 // Generic database trait
@@ -82,36 +138,371 @@ pub async fn dummy() {
 #[async_trait]
 pub trait Database: Sync + Send {
 
-    async fn init(location: &str) -> Arc<Self> where Self: Sized;
+    /// Create a new instance of the database.
+    /// Will error if `location` can't be used by the database (e.g. it can't be read from).
+    async fn init(location: &str) -> Result<Arc<Self>, DatabaseError> where Self: Sized;
+
+    /// Apply `operation` to `table_id`, returning the change it made to each entry, in the order they were made.
+    /// Every other write goes through this, so wrappers only need to implement it to see every write.
+    ///
+    /// Changes to the shape of tables don't change any entries, so return no changes.
+    async fn write(&self, table_id: &str, operation: Operation<'_>) -> Result<Vec<Change>, DatabaseError>;
+
+    /// Create a table for entries described by `schema`.
+    /// Every entry inserted, and every update, will be validated against the schema.
+    ///
+    /// If the table already exists its entries are kept, and `schema` replaces its current schema.
+    async fn create_table(&self, table_id: &str, schema: &ModelSchema) -> Result<(), DatabaseError> {
+        self.write(table_id, Operation::CreateTable(schema)).await.map(|_| ())
+    }
+
+    /// Change the shape of an existing table, updating both its schema and every existing entry.
+    async fn alter_table(&self, table_id: &str, change: &TableChange) -> Result<(), DatabaseError> {
+        self.write(table_id, Operation::AlterTable(change)).await.map(|_| ())
+    }
 
-    async fn create_table(&self, table_id: &str, model: &Vec<ModelValueType>) -> Result<(), String>;
+    /// Delete a table along with all of its entries.
+    async fn drop_table(&self, table_id: &str) -> Result<(), DatabaseError> {
+        self.write(table_id, Operation::DropTable).await.map(|_| ())
+    }
     
     // Ordinarily, functions that alter the state of an object should require a mutable reference,
     // however in this case none of the data is being contained within the structure itself, and so does not require mutability.
 
-    /// Create a new entry in the database.
-    async fn insert(&self, table_id: &str, data: &serde_json::Value) -> Result<EntryLocation, String>;
+    /// Create a new entry in the database, returning its id.
+    async fn insert(&self, table_id: &str, data: &serde_json::Value) -> Result<EntryId, DatabaseError> {
+        self.write(table_id, Operation::Insert(data)).await.map(|changes| changes[0].id)
+    }
+
+    /// Create every entry in `data`, returning their ids in the same order.
+    /// If any of the entries can't be inserted none of them are.
+    async fn insert_many(&self, table_id: &str, data: &[serde_json::Value]) -> Result<Vec<EntryId>, DatabaseError> {
+        self.write(table_id, Operation::InsertMany(data)).await.map(|changes| changes.iter().map(|change| change.id).collect())
+    }
+
+    /// Create every entry in `entries` under the id given with it, for restoring entries a table has held before, such as from a [`dump`].
+    /// Errors with [`DatabaseError::Conflict`] if an id is already taken, and if any of the entries can't be restored none of them are.
+    async fn restore(&self, table_id: &str, entries: &[(EntryId, serde_json::Value)]) -> Result<(), DatabaseError> {
+        self.write(table_id, Operation::Restore(entries)).await.map(|_| ())
+    }
 
     /// Update an existing entry.
-    async fn update(&self, table_id: &str, filter: DatabaseFilter<FilterValue>, data: &serde_json::Value) -> Result<(), String>;
+    async fn update(&self, table_id: &str, filter: DatabaseFilter<FilterValue>, data: &serde_json::Value) -> Result<(), DatabaseError> {
+        self.write(table_id, Operation::Update(&filter, data)).await.map(|_| ())
+    }
+
+    /// Update an existing entry only if it is still at `revision`, giving it a new [`REVISION`] which is returned.
+    /// Entries without a revision are at revision 0, and `filter` should only match a single entry.
+    ///
+    /// Errors with [`DatabaseError::Conflict`] on [`REVISION`] if the entry has been changed since `revision`.
+    async fn update_if(&self, table_id: &str, filter: DatabaseFilter<FilterValue>, revision: u64, data: &serde_json::Value) -> Result<u64, DatabaseError> {
+        let Some(fields) = data.as_object() else {
+            return Err(DatabaseError::InvalidArgument("Update data must be an object.".into()))
+        };
+
+        // The revision is checked by the update itself, so nothing can change the entry in between.
+        let mut expected = filter.clone();
+        expected.0.push(match revision {
+            0 => PartialFilter::EXISTS { key: REVISION, exists: false },
+            revision => PartialFilter::EQ { key: REVISION, value: revision.into() }
+        });
+        // The update gives the entry the next revision itself, setting it here also checks the table has revisions.
+        let next = revision + 1;
+        let mut data = fields.clone();
+        data.insert(REVISION.into(), next.into());
+
+        match self.update(table_id, expected, &serde_json::Value::Object(data)).await {
+            Ok(()) => Ok(next),
+            Err(DatabaseError::NotFound) => match self.find(table_id, filter).await {
+                Ok(_) => Err(DatabaseError::Conflict { field: REVISION.into() }),
+                Err(e) => Err(e)
+            },
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Update every entry matching `filter`, returning how many were updated.
+    /// If any of the entries can't be updated none of them are.
+    async fn update_many(&self, table_id: &str, filter: DatabaseFilter<FilterValue>, data: &serde_json::Value) -> Result<usize, DatabaseError> {
+        self.write(table_id, Operation::UpdateMany(&filter, data)).await.map(|changes| changes.len())
+    }
 
     /// Delete an entry.
-    async fn delete(&self, table_id: &str, filter: DatabaseFilter<FilterValue>) -> Result<(), String>;
+    async fn delete(&self, table_id: &str, filter: DatabaseFilter<FilterValue>) -> Result<(), DatabaseError> {
+        self.write(table_id, Operation::Delete(&filter)).await.map(|_| ())
+    }
+
+    /// Delete every entry matching `filter`, returning how many were deleted.
+    async fn delete_many(&self, table_id: &str, filter: DatabaseFilter<FilterValue>) -> Result<usize, DatabaseError> {
+        self.write(table_id, Operation::DeleteMany(&filter)).await.map(|changes| changes.len())
+    }
 
     /// Get an existing entry, will be parsed to `T`.
     /// Will error if the result can't be parsed to `T` or if the query otherwise fails.
-    async fn get(&self, table_id: &str,  filter: DatabaseFilter<FilterValue>) -> Result<serde_json::Value, String>;
+    async fn get(&self, table_id: &str,  filter: DatabaseFilter<FilterValue>) -> Result<serde_json::Value, DatabaseError>;
 
     /// Create a new filter.
     fn filter(&self) -> DatabaseFilterBuilder<FilterValue> {
         DatabaseFilterBuilder { filter: DatabaseFilter(Vec::new()) }
     }
 
-    /// Find location of entry matching the given filter
-    async fn find(&self, table_id: &str, filter: DatabaseFilter<FilterValue>) -> Result<EntryLocation, String>;
+    /// Find id of entry matching the given filter
+    async fn find(&self, table_id: &str, filter: DatabaseFilter<FilterValue>) -> Result<EntryId, DatabaseError>;
+
+    /// Every entry matching the given filter along with its id, in the order they were inserted.
+    async fn find_entries(&self, table_id: &str, filter: DatabaseFilter<FilterValue>) -> Result<Vec<(EntryId, serde_json::Value)>, DatabaseError>;
+
+    /// Get entry with the given id, ids stay valid until the entry itself is deleted.
+    async fn get_by_id(&self, table_id: &str, id: EntryId) -> Result<serde_json::Value, DatabaseError>;
+
+    /// Get every entry matching the query, along with the total number of matches before `limit` and `offset` are applied.
+    async fn query(&self, table_id: &str, query: DatabaseQuery<FilterValue>) -> Result<QueryResult, DatabaseError>;
+
+    /// Number of entries matching the given filter.
+    async fn count(&self, table_id: &str, filter: DatabaseFilter<FilterValue>) -> Result<usize, DatabaseError> {
+        Ok(self.query(table_id, DatabaseQuery::new(filter).limit(0)).await?.total)
+    }
+
+    /// Group entries matching the aggregation's filter, computing its metrics over each group.
+    /// Groups are sorted by their key, the same way [`Database::query`] sorts entries.
+    ///
+    /// By default every matching entry is read to compute the groups, backends should compute them in place where they can.
+    async fn aggregate(&self, table_id: &str, aggregation: Aggregation<FilterValue>) -> Result<Vec<AggregateGroup>, DatabaseError> {
+        let entries = self.find_entries(table_id, aggregation.filter.clone()).await?;
+        Ok(aggregation.apply(entries.iter().map(|(_, entry)| entry)))
+    }
+
+    /// Receive every change made to entries in `table_id` from now on, until the receiver is dropped.
+    ///
+    /// Changes made within a transaction are only received once it has been committed.
+    /// Changes made by [`Database::alter_table`] and [`Database::drop_table`] aren't received.
+    fn subscribe(&self, table_id: &str) -> Receiver<Change>;
+
+    /// Run `operation` atomically, if it returns an error every change made within it is rolled back.
+    ///
+    /// Transactions are run one at a time, and writes made outside of a transaction wait for a running one to finish,
+    /// so a check followed by a write within a transaction can't be interleaved with any other write.
+    /// Reads aren't isolated, those made outside of a transaction can see its writes before it is committed.
+    ///
+    /// Writes within `operation` must go through the database it is given, writing to any other handle to the same database will deadlock.
+    /// Transactions can't be nested, starting one from within `operation` is an error.
+    ///
+    /// # Examples
+    /// ```
+    /// db.transaction(Box::new(move |db| Box::pin(async move {
+    ///     let filter = db.filter().eq("email", email.into()).build();
+    ///     if db.find("accounts", filter).await.is_ok() {
+    ///         return Err(DatabaseError::Conflict { field: "email".into() });
+    ///     }
+    ///     db.insert("accounts", &account).await?;
+    ///     Ok(())
+    /// }))).await?;
+    /// ```
+    async fn transaction(&self, operation: TransactionFn) -> Result<(), DatabaseError>;
+}
+
+/// A change to the shape of a table, used with [`Database::alter_table`].
+#[derive(Clone, Debug)]
+pub enum TableChange {
+    /// Add a new field, existing entries are given `default`.
+    /// A null `default` adds the field as optional.
+    AddField { field: ModelValueType, default: serde_json::Value },
+    RenameField { from: &'static str, to: &'static str },
+    DropField { field: &'static str },
+    /// Set `field` of every entry to the value returned by `with`, which is given the entire entry.
+    Backfill { field: &'static str, with: fn(&serde_json::Value) -> serde_json::Value },
+    /// Index `field`, if `unique` is true this will fail if existing entries share a value for it.
+    AddIndex { field: &'static str, unique: bool },
+    DropIndex { field: &'static str }
+}
+
+impl TableChange {
+    /// Apply the change to a tables schema.
+    /// Errors if the change doesn't make sense for the schema, e.g. renaming a field that doesn't exist.
+    pub fn apply_to_schema(&self, schema: &mut ModelSchema) -> Result<(), String> {
+        let exists = |schema: &ModelSchema, field: &str| match schema.field(field) {
+            Some(_) => Ok(()),
+            None => Err(format!("Field `{field}` does not exist"))
+        };
+        let missing = |schema: &ModelSchema, field: &str| match schema.field(field) {
+            Some(_) => Err(format!("Field `{field}` already exists")),
+            None => Ok(())
+        };
+
+        match self {
+            TableChange::AddField { field, default } => {
+                missing(schema, field.field())?;
+                if default.is_null() {
+                    schema.optional.push(field.field());
+                }
+                else if !field.accepts(default) {
+                    return Err(format!("Default for field `{}` must be {}", field.field(), field.type_name()));
+                }
+                schema.fields.push(field.clone());
+            },
+            TableChange::RenameField { from, to } => {
+                exists(schema, from)?;
+                missing(schema, to)?;
+                for value_type in schema.fields.iter_mut().filter(|value_type| value_type.field() == *from) {
+                    *value_type = value_type.renamed(to);
+                }
+                let lists = [&mut schema.optional, &mut schema.unique, &mut schema.indexed];
+                for name in lists.into_iter().flat_map(|list| list.iter_mut()).filter(|name| *name == from) {
+                    *name = to;
+                }
+            },
+            TableChange::DropField { field } => {
+                exists(schema, field)?;
+                schema.fields.retain(|value_type| value_type.field() != *field);
+                schema.optional.retain(|optional| optional != field);
+                schema.unique.retain(|unique| unique != field);
+                schema.indexed.retain(|indexed| indexed != field);
+            },
+            TableChange::Backfill { field, .. } => exists(schema, field)?,
+            TableChange::AddIndex { field, unique } => {
+                exists(schema, field)?;
+                if schema.indexes().contains(field) {
+                    return Err(format!("Field `{field}` is already indexed"));
+                }
+                match unique {
+                    true => schema.unique.push(field),
+                    false => schema.indexed.push(field)
+                }
+            },
+            TableChange::DropIndex { field } => {
+                if !schema.indexes().contains(field) {
+                    return Err(format!("Field `{field}` is not indexed"));
+                }
+                schema.unique.retain(|unique| unique != field);
+                schema.indexed.retain(|indexed| indexed != field);
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the change to a single in-memory entry.
+    pub fn apply_to_entry(&self, entry: &mut serde_json::Value) {
+        let Some(fields) = entry.as_object_mut() else {
+            return
+        };
 
-    /// Get entry at the given location.
-    async fn get_loc(&self, table_id: &str, loc: EntryLocation) -> Result<serde_json::Value, String>;
+        match self {
+            TableChange::AddField { field, default } => {
+                if !default.is_null() {
+                    fields.insert(field.field().to_string(), default.clone());
+                }
+            },
+            TableChange::RenameField { from, to } => {
+                if let Some(value) = fields.remove(*from) {
+                    fields.insert(to.to_string(), value);
+                }
+            },
+            TableChange::DropField { field } => {
+                fields.remove(*field);
+            },
+            TableChange::Backfill { field, with } => {
+                let value = with(entry);
+                entry[*field] = value;
+            },
+            TableChange::AddIndex { .. } | TableChange::DropIndex { .. } => {}
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder {
+    Ascending,
+    Descending
+}
+
+/// A filter along with ordering and paging, used with [`Database::query`].
+///
+/// # Examples
+/// ```
+/// let filter = db.filter().eq("uid", uid.into()).build();
+/// let query = DatabaseQuery::new(filter)
+///     .order_by("created", SortOrder::Descending)
+///     .limit(10)
+///     .offset(20);
+///
+/// let result = db.query("sessions", query).await?;
+/// log::debug!("Showing {} of {} sessions", result.entries.len(), result.total);
+/// ```
+#[derive(Clone, Debug)]
+pub struct DatabaseQuery<T> {
+    pub filter: DatabaseFilter<T>,
+    pub order: Vec<(&'static str, SortOrder)>,
+    pub limit: Option<usize>,
+    pub offset: usize
+}
+
+impl<T> DatabaseQuery<T> {
+    pub fn new(filter: DatabaseFilter<T>) -> Self {
+        DatabaseQuery { filter, order: Vec::new(), limit: None, offset: 0 }
+    }
+
+    /// Sort results by `key`, later calls are used to break ties.
+    pub fn order_by(mut self, key: &'static str, order: SortOrder) -> Self {
+        self.order.push((key, order));
+        self
+    }
+
+    /// Return at most `limit` entries.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Skip the first `offset` matching entries.
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Every key the query filters or sorts by.
+    pub fn keys(&self) -> Vec<&'static str> {
+        let mut keys = self.filter.keys();
+        keys.extend(self.order.iter().map(|(key, _)| *key));
+        keys
+    }
+}
+
+impl DatabaseQuery<FilterValue> {
+    /// Run the query over in-memory entries.
+    pub fn apply<'a>(&self, entries: impl Iterator<Item = &'a serde_json::Value>) -> QueryResult {
+        let mut matched: Vec<&serde_json::Value> = entries.filter(|entry| self.filter.matches(entry)).collect();
+
+        // Stable sort, so entries stay in insertion order when equal.
+        matched.sort_by(|a, b| {
+            self.order.iter()
+                .map(|(key, order)| {
+                    let ordering = sort_compare(a.get(key), b.get(key));
+                    match order {
+                        SortOrder::Ascending => ordering,
+                        SortOrder::Descending => ordering.reverse()
+                    }
+                })
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(Ordering::Equal)
+        });
+
+        let total = matched.len();
+        let entries = matched.into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect();
+
+        QueryResult { entries, total }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryResult<T = serde_json::Value> {
+    /// Entries matching the query, after `limit` and `offset` have been applied.
+    pub entries: Vec<T>,
+    /// Number of entries matching the filter.
+    pub total: usize
 }
 
 pub struct DatabaseFilterBuilder<T: Clone> {
@@ -138,17 +529,315 @@ impl<T: Clone> DatabaseFilterBuilder<T> {
         );
         self
     }
+
+    /// Checks entry\[key\] > value
+    pub fn gt(&mut self, key: &'static str, value: T) -> &mut Self {
+        self.filter.0.push(
+            PartialFilter::GT { key, value }
+        );
+        self
+    }
+
+    /// Checks entry\[key\] >= value
+    pub fn gte(&mut self, key: &'static str, value: T) -> &mut Self {
+        self.filter.0.push(
+            PartialFilter::GTE { key, value }
+        );
+        self
+    }
+
+    /// Checks entry\[key\] < value
+    pub fn lt(&mut self, key: &'static str, value: T) -> &mut Self {
+        self.filter.0.push(
+            PartialFilter::LT { key, value }
+        );
+        self
+    }
+
+    /// Checks entry\[key\] <= value
+    pub fn lte(&mut self, key: &'static str, value: T) -> &mut Self {
+        self.filter.0.push(
+            PartialFilter::LTE { key, value }
+        );
+        self
+    }
+
+    /// Checks entry\[key\] is one of `values`
+    pub fn is_in(&mut self, key: &'static str, values: Vec<T>) -> &mut Self {
+        self.filter.0.push(
+            PartialFilter::IN { key, values }
+        );
+        self
+    }
+
+    /// Checks entry\[key\] is none of `values`
+    pub fn not_in(&mut self, key: &'static str, values: Vec<T>) -> &mut Self {
+        self.filter.0.push(
+            PartialFilter::NIN { key, values }
+        );
+        self
+    }
+
+    /// Checks the string entry\[key\] contains `value`
+    pub fn contains(&mut self, key: &'static str, value: T) -> &mut Self {
+        self.filter.0.push(
+            PartialFilter::CONTAINS { key, value }
+        );
+        self
+    }
+
+    /// Checks the string entry\[key\] starts with `value`
+    pub fn starts_with(&mut self, key: &'static str, value: T) -> &mut Self {
+        self.filter.0.push(
+            PartialFilter::PREFIX { key, value }
+        );
+        self
+    }
+
+    /// Checks entry\[key\] is present (and not null) if `exists` is true, or missing if false.
+    pub fn exists(&mut self, key: &'static str, exists: bool) -> &mut Self {
+        self.filter.0.push(
+            PartialFilter::EXISTS { key, exists }
+        );
+        self
+    }
+
+    /// Checks every filter added by `group` matches.
+    ///
+    /// # Examples
+    /// ```
+    /// let filter = db.filter()
+    ///     .eq("valid", true.into())
+    ///     .or(|f| f
+    ///         .and(|f| f.eq("uid", uid.into()).lt("created", cutoff.into()))
+    ///         .eq("session_id", session_id.into())
+    ///     )
+    ///     .build();
+    /// ```
+    pub fn and(&mut self, group: impl FnOnce(&mut Self) -> &mut Self) -> &mut Self {
+        let mut builder = DatabaseFilterBuilder { filter: DatabaseFilter(Vec::new()) };
+        group(&mut builder);
+        self.filter.0.push(
+            PartialFilter::AND(builder.filter.0)
+        );
+        self
+    }
+
+    /// Checks at least one of the filters added by `group` matches.
+    /// See [`DatabaseFilterBuilder::and`] for an example.
+    pub fn or(&mut self, group: impl FnOnce(&mut Self) -> &mut Self) -> &mut Self {
+        let mut builder = DatabaseFilterBuilder { filter: DatabaseFilter(Vec::new()) };
+        group(&mut builder);
+        self.filter.0.push(
+            PartialFilter::OR(builder.filter.0)
+        );
+        self
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct DatabaseFilter<T>(Vec<PartialFilter<T>>);
 
+impl<T> DatabaseFilter<T> {
+    /// Every key the filter checks, including those within groups.
+    pub fn keys(&self) -> Vec<&'static str> {
+        fn collect<T>(group: &[PartialFilter<T>], keys: &mut Vec<&'static str>) {
+            for partial in group {
+                match partial {
+                    PartialFilter::EQ { key, .. } | PartialFilter::NEQ { key, .. } |
+                    PartialFilter::GT { key, .. } | PartialFilter::GTE { key, .. } |
+                    PartialFilter::LT { key, .. } | PartialFilter::LTE { key, .. } |
+                    PartialFilter::IN { key, .. } | PartialFilter::NIN { key, .. } |
+                    PartialFilter::CONTAINS { key, .. } | PartialFilter::PREFIX { key, .. } |
+                    PartialFilter::EXISTS { key, .. } => keys.push(key),
+                    PartialFilter::AND(group) | PartialFilter::OR(group) => collect(group, keys)
+                }
+            }
+        }
+
+        let mut keys = Vec::new();
+        collect(&self.0, &mut keys);
+        keys
+    }
+}
+
+/// Errors with [`DatabaseError::InvalidArgument`] if any of `keys` isn't a field of `table_id`, according to `is_field`.
+/// Databases differ in how they compare a field which doesn't exist, so filtering or sorting by one is rejected instead.
+pub fn check_keys(table_id: &str, keys: &[&str], is_field: impl Fn(&str) -> bool) -> Result<(), DatabaseError> {
+    match keys.iter().find(|key| !is_field(key)) {
+        Some(key) => Err(DatabaseError::InvalidArgument(format!("Table {table_id} has no field {key:?}."))),
+        None => Ok(())
+    }
+}
+
+impl DatabaseFilter<FilterValue> {
+    /// Checks whether `entry` satisfies every part of the filter.
+    /// Entries missing a filtered key will never match, unless checked with [`PartialFilter::EXISTS`].
+    pub fn matches(&self, entry: &serde_json::Value) -> bool {
+        self.0.iter().all(|partial| partial.matches(entry))
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum PartialFilter<T> {
     EQ { key: &'static str, value: T },
-    NEQ { key: &'static str, value: T }
+    NEQ { key: &'static str, value: T },
+    GT { key: &'static str, value: T },
+    GTE { key: &'static str, value: T },
+    LT { key: &'static str, value: T },
+    LTE { key: &'static str, value: T },
+    IN { key: &'static str, values: Vec<T> },
+    NIN { key: &'static str, values: Vec<T> },
+    CONTAINS { key: &'static str, value: T },
+    PREFIX { key: &'static str, value: T },
+    EXISTS { key: &'static str, exists: bool },
+    AND(Vec<PartialFilter<T>>),
+    OR(Vec<PartialFilter<T>>)
+}
+
+impl PartialFilter<FilterValue> {
+    pub fn matches(&self, entry: &serde_json::Value) -> bool {
+        // Null is treated the same as a missing key.
+        let field = |key: &str| entry.get(key).filter(|val| !val.is_null());
+
+        match self {
+            PartialFilter::EQ { key, value } => field(key).is_some_and(|val| val == value),
+            PartialFilter::NEQ { key, value } => field(key).is_some_and(|val| val != value),
+            PartialFilter::GT { key, value } => field(key).and_then(|val| compare(val, value)).is_some_and(Ordering::is_gt),
+            PartialFilter::GTE { key, value } => field(key).and_then(|val| compare(val, value)).is_some_and(Ordering::is_ge),
+            PartialFilter::LT { key, value } => field(key).and_then(|val| compare(val, value)).is_some_and(Ordering::is_lt),
+            PartialFilter::LTE { key, value } => field(key).and_then(|val| compare(val, value)).is_some_and(Ordering::is_le),
+            PartialFilter::IN { key, values } => field(key).is_some_and(|val| values.contains(val)),
+            PartialFilter::NIN { key, values } => field(key).is_some_and(|val| !values.contains(val)),
+            PartialFilter::CONTAINS { key, value } => match (field(key).and_then(|val| val.as_str()), value.as_str()) {
+                (Some(val), Some(value)) => val.contains(value),
+                _ => false
+            },
+            PartialFilter::PREFIX { key, value } => match (field(key).and_then(|val| val.as_str()), value.as_str()) {
+                (Some(val), Some(value)) => val.starts_with(value),
+                _ => false
+            },
+            PartialFilter::EXISTS { key, exists } => field(key).is_some() == *exists,
+            PartialFilter::AND(group) => group.iter().all(|partial| partial.matches(entry)),
+            PartialFilter::OR(group) => group.iter().any(|partial| partial.matches(entry)),
+        }
+    }
+}
+
+/// Orders any two json values for sorting.
+/// Missing values come first, followed by booleans, numbers, strings, then everything else, similar to SQL.
+fn sort_compare(a: Option<&serde_json::Value>, b: Option<&serde_json::Value>) -> Ordering {
+    fn rank(value: Option<&serde_json::Value>) -> u8 {
+        match value {
+            None | Some(serde_json::Value::Null) => 0,
+            Some(serde_json::Value::Bool(_)) => 1,
+            Some(serde_json::Value::Number(_)) => 2,
+            Some(serde_json::Value::String(_)) => 3,
+            Some(_) => 4
+        }
+    }
+
+    match (a, b) {
+        (Some(serde_json::Value::Bool(a)), Some(serde_json::Value::Bool(b))) => a.cmp(b),
+        (Some(a), Some(b)) => compare(a, b).unwrap_or_else(|| rank(Some(a)).cmp(&rank(Some(b)))),
+        _ => rank(a).cmp(&rank(b))
+    }
+}
+
+/// Orders two json values of the same type, numbers and strings only.
+fn compare(a: &serde_json::Value, b: &serde_json::Value) -> Option<Ordering> {
+    match (a, b) {
+        (serde_json::Value::Number(a), serde_json::Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (serde_json::Value::String(a), serde_json::Value::String(b)) => Some(a.cmp(b)),
+        _ => None
+    }
 }
 
 pub trait DatabaseModel: for<'d> Deserialize<'d> + Serialize + Send + Sync + Sized { 
+    /// Table entries of this model are stored in.
+    fn table() -> &'static str;
+
     fn fields() -> Vec<ModelValueType>;
+
+    /// Fields which may be missing or null.
+    fn optional() -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    /// Fields which no two entries may share a value for.
+    fn unique() -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    /// Fields which are frequently looked up, and should be indexed.
+    fn indexed() -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    /// String fields which are indexed for full-text search by [`search::Search`].
+    fn searchable() -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    /// Fields holding secrets, such as password hashes, whose values [`audit::Audited`] leaves out of the audit log.
+    fn sensitive() -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    /// How long entries of this model are kept for, expired entries are removed by [`expiry::sweep`].
+    fn expiry() -> Option<Expiry> {
+        None
+    }
+
+    /// How reads from the table are cached when the database is wrapped in [`cache::CachedDb`].
+    fn cache() -> Option<CachePolicy> {
+        None
+    }
+
+    /// Whether [`Repository`](repository::Repository) maintains [`CREATED_AT`] and [`UPDATED_AT`] for entries of this model.
+    fn timestamps() -> bool {
+        false
+    }
+
+    /// Whether [`Repository`](repository::Repository) marks entries as deleted with [`DELETED_AT`], rather than removing them.
+    /// Deleted entries are hidden from the repository, but can be recovered.
+    ///
+    /// Deleted entries still hold onto their values for unique fields, so a recovered entry can never conflict with one written since.
+    /// A new entry can't take a value from a deleted one until it has been removed for good.
+    fn soft_delete() -> bool {
+        false
+    }
+
+    /// Whether entries of this model have a [`REVISION`], which goes up by one every time they are written,
+    /// so they can be updated with [`Repository::update_if`](repository::Repository::update_if) without overwriting changes made in the meantime.
+    fn revisions() -> bool {
+        false
+    }
+
+    /// Schema of the table, including the fields maintained by [`Repository`](repository::Repository).
+    fn schema() -> ModelSchema {
+        let mut schema = ModelSchema {
+            fields: Self::fields(),
+            optional: Self::optional(),
+            unique: Self::unique(),
+            indexed: Self::indexed()
+        };
+
+        // Entries written without the repository won't have these, so they are optional.
+        let mut managed = Vec::new();
+        if Self::timestamps() {
+            managed.extend([CREATED_AT, UPDATED_AT]);
+        }
+        if Self::soft_delete() {
+            managed.push(DELETED_AT);
+        }
+        if Self::revisions() {
+            managed.push(REVISION);
+        }
+        for field in managed {
+            schema.fields.push(ModelValueType::Number { field });
+            schema.optional.push(field);
+        }
+        schema
+    }
 } 
\ No newline at end of file
@@ -0,0 +1,888 @@
+use std::{collections::{BTreeMap, HashMap}, fs, io::Write, path::{Path, PathBuf}, sync::{Arc, RwLock}};
+use async_std::{channel::Receiver, sync::Mutex, task};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::{aggregate::{AggregateGroup, Aggregation}, changes::{Change, ChangeEvent, Subscriptions}, error::DatabaseError, memory::{self, MemoryTable, Undo}, Database, DatabaseFilter, DatabaseQuery, QueryResult, EntryId, FilterValue, TransactionFn, transaction::{self, Operation, Transactional}};
+
+/// Schemas are stored in each table's snapshot, tables from snapshots written before they were have no schema until [`Database::create_table`] is called for them.
+type PersistentTables = HashMap<String, MemoryTable>;
+
+/// Extension used for table snapshots, each table is stored as `<location>/<table_id>.json`
+const TABLE_EXTENSION: &str = "json";
+/// Extension used while a snapshot is being written, before it replaces the existing one.
+const TEMP_EXTENSION: &str = "json.tmp";
+/// Extension used for the log of changes made to a table since its snapshot was written, `<location>/<table_id>.log`
+const LOG_EXTENSION: &str = "log";
+/// A log is compacted into a new snapshot once it's larger than this many bytes, or than the snapshot if that's larger.
+const COMPACT_SIZE: u64 = 64 * 1024;
+/// File holding a committed transaction while it's written out to its tables' files, `<location>/transaction.commit`
+/// Table ids can't contain a `.`, so this never clashes with a table's files.
+const COMMIT_FILE: &str = "transaction.commit";
+/// File a transaction is written to before it replaces [`COMMIT_FILE`].
+const COMMIT_TEMP_FILE: &str = "transaction.commit.tmp";
+
+/// A table snapshot as it's stored on disk.
+#[derive(Serialize, Deserialize)]
+struct Snapshot<T> {
+    /// Increased with every snapshot written for a table, and written at the start of its log,
+    /// so a log left over from an older snapshot is never replayed over a newer one.
+    #[serde(default)]
+    generation: u64,
+    #[serde(flatten)]
+    table: T
+}
+
+/// First line of a table's log.
+#[derive(Serialize, Deserialize)]
+struct LogHeader {
+    generation: u64
+}
+
+/// A change to a single entry, each is written to a table's log on its own line.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Record {
+    Put { id: EntryId, entry: serde_json::Value },
+    Remove { id: EntryId }
+}
+
+impl From<&Change> for Record {
+    fn from(change: &Change) -> Record {
+        match &change.event {
+            ChangeEvent::Insert { new } | ChangeEvent::Update { new, .. } => Record::Put { id: change.id, entry: new.clone() },
+            ChangeEvent::Delete { .. } => Record::Remove { id: change.id }
+        }
+    }
+}
+
+/// A committed transaction as it's stored on disk.
+///
+/// It's written as a whole before any of its writes are made to the tables' files, and removed once they all have been,
+/// so a transaction interrupted partway through being written out is finished when the database is next loaded.
+#[derive(Serialize, Deserialize)]
+struct Commit<T> {
+    tables: Vec<(String, Outcome<T>)>
+}
+
+/// What a transaction left a table as.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Outcome<T> {
+    /// The whole table for tables whose shape was changed, `None` if it was dropped.
+    Table(Option<T>),
+    /// Every entry which was changed as it was left, `None` for those removed.
+    Entries(Vec<(EntryId, Option<serde_json::Value>)>)
+}
+
+/// What's on disk for a table.
+#[derive(Clone, Copy, Default)]
+struct TableFiles {
+    generation: u64,
+    snapshot_size: u64,
+    /// Zero until the log is started for the current snapshot.
+    log_size: u64
+}
+
+/// Reads and writes the files of a database, used from blocking tasks.
+#[derive(Clone)]
+struct Storage {
+    root: PathBuf
+}
+
+impl Storage {
+    fn path(&self, table_id: &str, extension: &str) -> PathBuf {
+        self.root.join(format!("{table_id}.{extension}"))
+    }
+
+    /// Atomically replace the file at `path` with `raw`, by writing it to `temp_path` first.
+    fn replace(&self, temp_path: &Path, path: &Path, raw: &[u8]) -> Result<(), DatabaseError> {
+        let mut file = fs::File::create(temp_path)
+            .map_err(|e| DatabaseError::Backend(format!("Failed to create {}: {e}", temp_path.display())))?;
+        file.write_all(raw)
+            .and_then(|_| file.sync_all())
+            .map_err(|e| DatabaseError::Backend(format!("Failed to write {}: {e}", temp_path.display())))?;
+
+        fs::rename(temp_path, path)
+            .map_err(|e| DatabaseError::Backend(format!("Failed to replace {}: {e}", path.display())))?;
+
+        // Ensure the rename itself has reached the disk.
+        if let Ok(dir) = fs::File::open(&self.root) {
+            let _ = dir.sync_all();
+        }
+        Ok(())
+    }
+
+    /// Atomically replace the snapshot of `table_id` with `table`, then remove the log it replaces.
+    /// Returns the size of the snapshot.
+    fn write_snapshot(&self, table_id: &str, generation: u64, table: &MemoryTable) -> Result<u64, DatabaseError> {
+        let raw = serde_json::to_vec(&Snapshot { generation, table })
+            .map_err(|e| DatabaseError::Backend(format!("Failed to serialize table {table_id}: {e}")))?;
+        self.replace(&self.path(table_id, TEMP_EXTENSION), &self.path(table_id, TABLE_EXTENSION), &raw)?;
+
+        // Should this fail the log is left behind, but as it belongs to the previous generation it's never replayed.
+        remove_file(&self.path(table_id, LOG_EXTENSION))?;
+        Ok(raw.len() as u64)
+    }
+
+    /// Append `records` to the log of `table_id`, starting a new log if `files` doesn't have one yet.
+    /// Returns the size of the log.
+    fn append(&self, table_id: &str, files: TableFiles, records: &[u8]) -> Result<u64, DatabaseError> {
+        let path = self.path(table_id, LOG_EXTENSION);
+        let mut file = fs::OpenOptions::new().create(true).append(files.log_size != 0).write(true).truncate(files.log_size == 0).open(&path)
+            .map_err(|e| DatabaseError::Backend(format!("Failed to open {}: {e}", path.display())))?;
+
+        let mut raw = Vec::new();
+        if files.log_size == 0 {
+            serde_json::to_writer(&mut raw, &LogHeader { generation: files.generation })
+                .map_err(|e| DatabaseError::Backend(format!("Failed to serialize log header for table {table_id}: {e}")))?;
+            raw.push(b'\n');
+        }
+        raw.extend_from_slice(records);
+
+        file.write_all(&raw)
+            .and_then(|_| file.sync_data())
+            .map_err(|e| DatabaseError::Backend(format!("Failed to write {}: {e}", path.display())))?;
+        Ok(files.log_size + raw.len() as u64)
+    }
+
+    /// Remove every file of `table_id`.
+    fn remove(&self, table_id: &str) -> Result<(), DatabaseError> {
+        remove_file(&self.path(table_id, TABLE_EXTENSION))?;
+        remove_file(&self.path(table_id, LOG_EXTENSION))
+    }
+
+    /// Atomically write the serialized [`Commit`] of a transaction, once this returns the transaction is committed.
+    fn write_commit(&self, raw: &[u8]) -> Result<(), DatabaseError> {
+        self.replace(&self.root.join(COMMIT_TEMP_FILE), &self.root.join(COMMIT_FILE), raw)
+    }
+
+    /// Remove the committed transaction, once it has been written out to its tables' files.
+    fn remove_commit(&self) -> Result<(), DatabaseError> {
+        remove_file(&self.root.join(COMMIT_FILE))
+    }
+
+    /// Load every table found in `root`, replaying its log over its snapshot, then finish writing out any committed transaction.
+    /// Leftover temporary files are from writes that never completed, and are removed along with logs that don't belong to a snapshot.
+    fn load(&self) -> Result<(PersistentTables, HashMap<String, TableFiles>), DatabaseError> {
+        let (mut tables, mut files) = (PersistentTables::new(), HashMap::new());
+        let mut logs = Vec::new();
+        let entries = fs::read_dir(&self.root)
+            .map_err(|e| DatabaseError::Backend(format!("Failed to read database directory {}: {e}", self.root.display())))?;
+
+        for entry in entries {
+            let path = entry.map_err(|e| DatabaseError::Backend(e.to_string()))?.path();
+            let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+
+            if name.ends_with(&format!(".{TEMP_EXTENSION}")) || name == COMMIT_TEMP_FILE {
+                log::warn!("Removing incomplete write {}", path.display());
+                remove_file(&path)?;
+                continue;
+            }
+
+            if let Some(table_id) = name.strip_suffix(&format!(".{LOG_EXTENSION}")) {
+                logs.push(table_id.to_string());
+                continue;
+            }
+
+            let Some(table_id) = name.strip_suffix(&format!(".{TABLE_EXTENSION}")) else {
+                continue
+            };
+
+            let raw = fs::read(&path)
+                .map_err(|e| DatabaseError::Backend(format!("Failed to read {}: {e}", path.display())))?;
+            let mut snapshot = serde_json::from_slice::<Snapshot<MemoryTable>>(&raw)
+                .map_err(|e| DatabaseError::Backend(format!("Failed to parse {}: {e}", path.display())))?;
+            snapshot.table.reindex()?;
+
+            log::debug!("Loaded table {table_id}");
+            tables.insert(table_id.to_string(), snapshot.table);
+            files.insert(table_id.to_string(), TableFiles { generation: snapshot.generation, snapshot_size: raw.len() as u64, log_size: 0 });
+        }
+
+        for table_id in logs {
+            let path = self.path(&table_id, LOG_EXTENSION);
+            if let (Some(table), Some(table_files)) = (tables.get_mut(&table_id), files.get_mut(&table_id)) {
+                if let Some(log_size) = Self::replay(&path, table_files.generation, table)? {
+                    table_files.log_size = log_size;
+                    continue;
+                }
+            }
+
+            log::warn!("Removing stale log {}", path.display());
+            remove_file(&path)?;
+        }
+
+        self.recover(&mut tables, &mut files)?;
+        Ok((tables, files))
+    }
+
+    /// Finish writing out the transaction in [`COMMIT_FILE`], if there is one.
+    /// Its tables may have been left as they were before the transaction, as they were after, or anywhere in between,
+    /// so each is set to the outcome of the transaction then given a new snapshot.
+    fn recover(&self, tables: &mut PersistentTables, files: &mut HashMap<String, TableFiles>) -> Result<(), DatabaseError> {
+        let path = self.root.join(COMMIT_FILE);
+        let raw = match fs::read(&path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(DatabaseError::Backend(format!("Failed to read {}: {e}", path.display())))
+        };
+        let commit = serde_json::from_slice::<Commit<MemoryTable>>(&raw)
+            .map_err(|e| DatabaseError::Backend(format!("Failed to parse {}: {e}", path.display())))?;
+
+        log::warn!("Finishing a transaction which was committed but not written out");
+        for (table_id, outcome) in commit.tables {
+            match outcome {
+                Outcome::Table(Some(mut table)) => {
+                    table.reindex()?;
+                    tables.insert(table_id.clone(), table);
+                },
+                Outcome::Table(None) => {
+                    tables.remove(&table_id);
+                },
+                Outcome::Entries(entries) => if let Some(table) = tables.get_mut(&table_id) {
+                    // Every changed entry is removed before any are put back, so none clash with an entry which is yet to be changed.
+                    entries.iter().for_each(|(id, _)| {
+                        table.remove(*id);
+                    });
+                    for (id, entry) in entries {
+                        if let Some(entry) = entry {
+                            table.put(id, entry)?;
+                        }
+                    }
+                }
+            }
+
+            match tables.get(&table_id) {
+                Some(table) => {
+                    let generation = files.get(&table_id).map_or(0, |files| files.generation + 1);
+                    let snapshot_size = self.write_snapshot(&table_id, generation, table)?;
+                    files.insert(table_id, TableFiles { generation, snapshot_size, log_size: 0 });
+                },
+                None => {
+                    self.remove(&table_id)?;
+                    files.remove(&table_id);
+                }
+            }
+        }
+        remove_file(&path)
+    }
+
+    /// Replay the log at `path` over `table`, returning its size, or `None` if it doesn't belong to the snapshot of the given `generation`.
+    /// A record cut short by a crash partway through writing it is discarded.
+    fn replay(path: &Path, generation: u64, table: &mut MemoryTable) -> Result<Option<u64>, DatabaseError> {
+        let raw = fs::read(path)
+            .map_err(|e| DatabaseError::Backend(format!("Failed to read {}: {e}", path.display())))?;
+
+        let mut lines = raw.split_inclusive(|byte| *byte == b'\n');
+        let Some(header) = lines.next() else {
+            return Ok(None)
+        };
+        match serde_json::from_slice::<LogHeader>(header) {
+            Ok(header) if header.generation == generation => (),
+            _ => return Ok(None)
+        }
+
+        let mut size = header.len();
+        for line in lines {
+            let record = match (serde_json::from_slice::<Record>(line), line.ends_with(b"\n")) {
+                (Ok(record), true) => record,
+                (Err(e), true) => return Err(DatabaseError::Backend(format!("Failed to parse {}: {e}", path.display()))),
+                // Records are written along with the newline ending them, so only the last one can be missing it.
+                (_, false) => {
+                    log::warn!("Discarding incomplete record at the end of {}", path.display());
+                    fs::OpenOptions::new().write(true).open(path)
+                        .and_then(|file| file.set_len(size as u64))
+                        .map_err(|e| DatabaseError::Backend(format!("Failed to truncate {}: {e}", path.display())))?;
+                    break;
+                }
+            };
+
+            match record {
+                Record::Put { id, entry } => table.put(id, entry)?,
+                Record::Remove { id } => {
+                    table.remove(id);
+                }
+            }
+            size += line.len();
+        }
+        Ok(Some(size as u64))
+    }
+}
+
+/// Remove the file at `path`, if there is one.
+fn remove_file(path: &Path) -> Result<(), DatabaseError> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(DatabaseError::Backend(format!("Failed to remove {}: {e}", path.display()))),
+        _ => Ok(())
+    }
+}
+
+/// File backed database.
+///
+/// Tables are held in memory, and each is stored on disk as a snapshot along with a log of the changes made since.
+/// Every change is appended to the log, and once a log has grown large enough it's compacted into a new snapshot.
+/// Snapshots are written to a temporary file which is then renamed over the existing one,
+/// so a crash partway through a write will leave the previous snapshot and its log intact.
+///
+/// Nothing is written to disk within a transaction until it's committed, when every write it made is first stored as a whole
+/// in a single file, then written out to each table's files. Should that be interrupted it's finished when the database is next loaded.
+///
+/// Disk writes are made from blocking tasks without holding onto the tables, so reads can see a write before it has reached the disk.
+pub struct PersistentDb {
+    storage: Storage,
+    tables: RwLock<PersistentTables>,
+    /// Only touched by writes, which hold [`PersistentDb::transaction`].
+    files: std::sync::Mutex<HashMap<String, TableFiles>>,
+    /// Held for the duration of a transaction, so only one can run at a time, and by each write made outside of one while it runs.
+    transaction: Mutex<()>,
+    /// Tables of a committed transaction which couldn't all be written out, they're given new snapshots before the next write.
+    unfinished: std::sync::Mutex<Vec<String>>,
+    subscriptions: Subscriptions
+}
+
+/// Journal of a transaction on a [`PersistentDb`].
+pub struct PersistentJournal {
+    undo: Vec<Undo>,
+    /// Every table written to, and what needs writing out for it once the transaction is committed.
+    pending: HashMap<String, Pending>
+}
+
+enum Pending {
+    /// The shape of the table was changed, so a new snapshot is written.
+    Table,
+    /// Entries were changed, and are appended to the table's log as they were left.
+    Entries(BTreeMap<EntryId, Option<serde_json::Value>>)
+}
+
+impl PersistentDb {
+    /// Run `f` against the table `table_id`.
+    fn read<R>(&self, table_id: &str, f: impl FnOnce(&MemoryTable) -> Result<R, DatabaseError>) -> Result<R, DatabaseError> {
+        match self.tables.read().unwrap().get(table_id) {
+            Some(table) => f(table),
+            None => Err(DatabaseError::TableNotFound(table_id.into()))
+        }
+    }
+
+    /// Run `f` against the database's files from a blocking task.
+    async fn storage<R: Send + 'static>(&self, f: impl FnOnce(&Storage) -> Result<R, DatabaseError> + Send + 'static) -> Result<R, DatabaseError> {
+        let storage = self.storage.clone();
+        task::spawn_blocking(move || f(&storage)).await
+    }
+
+    /// Append `records` to the log of `table_id`, compacting it once it has grown too large.
+    async fn append(&self, table_id: &str, records: impl IntoIterator<Item = Record>) -> Result<(), DatabaseError> {
+        let mut raw = Vec::new();
+        for record in records {
+            serde_json::to_writer(&mut raw, &record)
+                .map_err(|e| DatabaseError::Backend(format!("Failed to serialize change to table {table_id}: {e}")))?;
+            raw.push(b'\n');
+        }
+        if raw.is_empty() {
+            return Ok(());
+        }
+
+        let files = self.files.lock().unwrap().get(table_id).copied().unwrap_or_default();
+        let id = table_id.to_string();
+        let log_size = self.storage(move |storage| storage.append(&id, files, &raw)).await?;
+        self.files.lock().unwrap().insert(table_id.to_string(), TableFiles { log_size, ..files });
+
+        // The change has already been written, so a failed compaction leaves the log to be compacted by a later write.
+        if log_size > COMPACT_SIZE.max(files.snapshot_size) {
+            if let Err(e) = self.snapshot(table_id).await {
+                log::warn!("Failed to compact the log of table {table_id}: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a new snapshot of the table `table_id` as it's held in memory, replacing its log.
+    /// If the table no longer exists its files are removed instead.
+    async fn snapshot(&self, table_id: &str) -> Result<(), DatabaseError> {
+        let id = table_id.to_string();
+        let Some(table) = self.tables.read().unwrap().get(table_id).cloned() else {
+            self.storage(move |storage| storage.remove(&id)).await?;
+            self.files.lock().unwrap().remove(table_id);
+            return Ok(());
+        };
+
+        let generation = self.files.lock().unwrap().get(table_id).map_or(0, |files| files.generation + 1);
+        let snapshot_size = self.storage(move |storage| storage.write_snapshot(&id, generation, &table)).await?;
+        self.files.lock().unwrap().insert(table_id.to_string(), TableFiles { generation, snapshot_size, log_size: 0 });
+        Ok(())
+    }
+
+    /// Write out every table in `pending` to its files, then remove the committed transaction they belong to.
+    /// Should any fail, each is given a new snapshot before the next write, so later writes are never followed by the older transaction.
+    async fn write_out(&self, pending: HashMap<String, Pending>) -> Result<(), DatabaseError> {
+        let table_ids = pending.keys().cloned().collect::<Vec<_>>();
+        let mut result = Ok(());
+        for (table_id, pending) in pending {
+            result = match pending {
+                Pending::Table => self.snapshot(&table_id).await,
+                Pending::Entries(entries) => self.append(&table_id, entries.into_iter().map(|(id, entry)| match entry {
+                    Some(entry) => Record::Put { id, entry },
+                    None => Record::Remove { id }
+                })).await
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+
+        match result.and(self.storage(|storage| storage.remove_commit()).await) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                self.unfinished.lock().unwrap().extend(table_ids);
+                Err(e)
+            }
+        }
+    }
+
+    /// Finish writing out a committed transaction which [`PersistentDb::write_out`] couldn't.
+    async fn finish(&self) -> Result<(), DatabaseError> {
+        let unfinished = std::mem::take(&mut *self.unfinished.lock().unwrap());
+        if unfinished.is_empty() {
+            return Ok(());
+        }
+
+        for (i, table_id) in unfinished.iter().enumerate() {
+            if let Err(e) = self.snapshot(table_id).await {
+                self.unfinished.lock().unwrap().extend(unfinished[i..].iter().cloned());
+                return Err(e);
+            }
+        }
+        if let Err(e) = self.storage(|storage| storage.remove_commit()).await {
+            self.unfinished.lock().unwrap().extend(unfinished);
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+/// Table ids are used as file names, so restrict them to a safe set of characters.
+fn validate_table_id(table_id: &str) -> Result<(), DatabaseError> {
+    if table_id.is_empty() || !table_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err(DatabaseError::InvalidArgument(format!("Invalid table id {table_id:?}")));
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl Database for PersistentDb {
+
+    async fn init(location: &str) -> Result<Arc<PersistentDb>, DatabaseError> {
+        if location.is_empty() {
+            return Err(DatabaseError::InvalidArgument("A database location is required for persistent databases.".into()));
+        }
+
+        let root = PathBuf::from(location);
+        fs::create_dir_all(&root)
+            .map_err(|e| DatabaseError::Backend(format!("Failed to create database directory {}: {e}", root.display())))?;
+
+        let storage = Storage { root };
+        let (tables, files) = storage.load()?;
+        Ok(Arc::new(PersistentDb {
+            storage,
+            tables: RwLock::new(tables),
+            files: std::sync::Mutex::new(files),
+            transaction: Mutex::new(()),
+            unfinished: std::sync::Mutex::new(Vec::new()),
+            subscriptions: Subscriptions::default()
+        }))
+    }
+
+    async fn write(&self, table_id: &str, operation: Operation<'_>) -> Result<Vec<Change>, DatabaseError> {
+        transaction::write(self, table_id, operation).await
+    }
+
+    async fn get(&self, table_id: &str,  filter: DatabaseFilter<FilterValue>) -> Result<serde_json::Value, DatabaseError> {
+        self.read(table_id, |table| table.get(table_id, &filter))
+    }
+
+    async fn find(&self, table_id: &str, filter: DatabaseFilter<FilterValue>) -> Result<EntryId, DatabaseError> {
+        self.read(table_id, |table| table.find(table_id, &filter))
+    }
+
+    async fn find_entries(&self, table_id: &str, filter: DatabaseFilter<FilterValue>) -> Result<Vec<(EntryId, serde_json::Value)>, DatabaseError> {
+        self.read(table_id, |table| table.find_entries(table_id, &filter))
+    }
+
+    async fn get_by_id(&self, table_id: &str, id: EntryId) -> Result<serde_json::Value, DatabaseError> {
+        self.read(table_id, |table| table.get_by_id(id))
+    }
+
+    async fn query(&self, table_id: &str, query: DatabaseQuery<FilterValue>) -> Result<QueryResult, DatabaseError> {
+        self.read(table_id, |table| table.query(table_id, &query))
+    }
+
+    async fn aggregate(&self, table_id: &str, aggregation: Aggregation<FilterValue>) -> Result<Vec<AggregateGroup>, DatabaseError> {
+        self.read(table_id, |table| table.aggregate(table_id, &aggregation))
+    }
+
+    fn subscribe(&self, table_id: &str) -> Receiver<Change> {
+        self.subscriptions.subscribe(table_id)
+    }
+
+    async fn transaction(&self, operation: TransactionFn) -> Result<(), DatabaseError> {
+        transaction::run(self, operation).await
+    }
+}
+
+#[async_trait]
+impl Transactional for PersistentDb {
+    // Changes are only made in memory until the transaction is committed, so rolling back only needs to undo them there.
+    type Journal = PersistentJournal;
+
+    fn lock(&self) -> &Mutex<()> {
+        &self.transaction
+    }
+
+    fn subscriptions(&self) -> &Subscriptions {
+        &self.subscriptions
+    }
+
+    async fn begin(&self) -> Result<PersistentJournal, DatabaseError> {
+        Ok(PersistentJournal { undo: Vec::new(), pending: HashMap::new() })
+    }
+
+    async fn apply(&self, table_id: &str, operation: Operation<'_>, journal: Option<&mut PersistentJournal>) -> Result<Vec<Change>, DatabaseError> {
+        if let Operation::CreateTable(_) = operation {
+            validate_table_id(table_id)?;
+        }
+        self.finish().await?;
+
+        // The write is applied in memory first and undone if it can't be written to disk, so memory and disk don't drift apart.
+        let mut undo = Vec::new();
+        let (changes, unchanged) = {
+            let mut tables = self.tables.write().unwrap();
+            let unchanged = match operation {
+                Operation::CreateTable(schema) => tables.get(table_id).and_then(|table| table.schema()) == Some(schema),
+                _ => false
+            };
+            (memory::apply(&mut tables, table_id, operation, Some(&mut undo))?, unchanged)
+        };
+
+        if let Some(journal) = journal {
+            journal.undo.append(&mut undo);
+            let reshaped = matches!(operation, Operation::CreateTable(_) | Operation::AlterTable(_) | Operation::DropTable);
+            match journal.pending.entry(table_id.to_string()).or_insert_with(|| Pending::Entries(BTreeMap::new())) {
+                pending if reshaped && !unchanged => *pending = Pending::Table,
+                Pending::Entries(entries) => for change in &changes {
+                    entries.insert(change.id, match &change.event {
+                        ChangeEvent::Insert { new } | ChangeEvent::Update { new, .. } => Some(new.clone()),
+                        ChangeEvent::Delete { .. } => None
+                    });
+                },
+                Pending::Table => ()
+            }
+            return Ok(changes);
+        }
+
+        let written = match operation {
+            // Creating an existing table with the schema it already has changes nothing.
+            Operation::CreateTable(_) if unchanged => Ok(()),
+            // Changes to the shape of a table aren't logged, a new snapshot is written instead.
+            Operation::CreateTable(_) | Operation::AlterTable(_) | Operation::DropTable => self.snapshot(table_id).await,
+            _ => self.append(table_id, changes.iter().map(Record::from)).await
+        };
+        if let Err(e) = written {
+            memory::rollback(&mut self.tables.write().unwrap(), undo)?;
+            return Err(e);
+        }
+        Ok(changes)
+    }
+
+    async fn commit(&self, journal: PersistentJournal) -> Result<(), DatabaseError> {
+        if journal.pending.is_empty() {
+            return Ok(());
+        }
+
+        let raw = {
+            let tables = self.tables.read().unwrap();
+            let commit = Commit {
+                tables: journal.pending.iter().map(|(table_id, pending)| (table_id.clone(), match pending {
+                    Pending::Table => Outcome::Table(tables.get(table_id)),
+                    Pending::Entries(entries) => Outcome::Entries(entries.iter().map(|(id, entry)| (*id, entry.clone())).collect())
+                })).collect()
+            };
+            serde_json::to_vec(&commit)
+                .map_err(|e| DatabaseError::Backend(format!("Failed to serialize transaction: {e}")))
+        };
+
+        // Until the transaction has been written as a whole it can still be rolled back.
+        let written = match raw {
+            Ok(raw) => self.storage(move |storage| storage.write_commit(&raw)).await,
+            Err(e) => Err(e)
+        };
+        if let Err(e) = written {
+            memory::rollback(&mut self.tables.write().unwrap(), journal.undo)?;
+            return Err(e);
+        }
+
+        // The transaction is committed, failing to write it out is only logged as it will be finished before the next write or when next loaded.
+        if let Err(e) = self.write_out(journal.pending).await {
+            log::warn!("Failed to write out a committed transaction: {e}");
+        }
+        Ok(())
+    }
+
+    async fn rollback(&self, journal: PersistentJournal) -> Result<(), DatabaseError> {
+        memory::rollback(&mut self.tables.write().unwrap(), journal.undo)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::path::PathBuf;
+
+    use serde_json::json;
+
+    use crate::core::{database::{error::DatabaseError, Database, TableChange}, models::{ModelSchema, ModelValueType}};
+
+    use super::PersistentDb;
+
+    crate::core::database::conformance::conformance!(PersistentDb::init(temp_location("conformance").to_str().unwrap()).await.unwrap());
+
+    fn users() -> ModelSchema {
+        ModelSchema {
+            fields: vec![
+                ModelValueType::String { field: "email" },
+                ModelValueType::String { field: "name" },
+            ],
+            optional: vec!["name"],
+            unique: vec!["email"],
+            indexed: Vec::new()
+        }
+    }
+
+    fn temp_location(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("hag_persistent_{name}_{}", uuid::Uuid::new_v4()));
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn reload() {
+        let location = temp_location("reload");
+        let location = location.to_str().unwrap();
+
+        let db = PersistentDb::init(location).await.unwrap();
+        db.create_table("users", &users()).await.unwrap();
+        db.insert("users", &json!({ "email": "test@email.com", "name": "Test" })).await.unwrap();
+        db.insert("users", &json!({ "email": "other@email.com", "name": "Other" })).await.unwrap();
+        db.update("users", db.filter().eq("email", "test@email.com".into()).build(), &json!({ "name": "Updated" })).await.unwrap();
+        db.delete("users", db.filter().eq("email", "other@email.com".into()).build()).await.unwrap();
+        drop(db);
+
+        let db = PersistentDb::init(location).await.unwrap();
+        // Creating an existing table must not clear it.
+        db.create_table("users", &users()).await.unwrap();
+        let filter = db.filter().eq("email", "test@email.com".into()).build();
+        assert_eq!(db.get("users", filter).await.unwrap(), json!({ "email": "test@email.com", "name": "Updated" }));
+        assert!(db.find("users", db.filter().eq("email", "other@email.com".into()).build()).await.is_err());
+
+        let _ = std::fs::remove_dir_all(location);
+    }
+
+    #[tokio::test]
+    async fn rollback() {
+        let location = temp_location("rollback");
+        let location = location.to_str().unwrap();
+
+        let db = PersistentDb::init(location).await.unwrap();
+        db.create_table("users", &users()).await.unwrap();
+        db.insert("users", &json!({ "email": "test@email.com" })).await.unwrap();
+
+        let result = db.transaction(Box::new(|db| Box::pin(async move {
+            db.create_table("posts", &users()).await?;
+            db.delete("users", db.filter().build()).await?;
+            Err(DatabaseError::InvalidArgument("Cancelled".into()))
+        }))).await;
+        assert!(result.is_err());
+        drop(db);
+
+        let db = PersistentDb::init(location).await.unwrap();
+        assert_eq!(db.get_by_id("users", 1).await.unwrap(), json!({ "email": "test@email.com" }));
+        assert!(db.find("posts", db.filter().build()).await.is_err());
+
+        let _ = std::fs::remove_dir_all(location);
+    }
+
+    #[tokio::test]
+    async fn commit() {
+        let location = temp_location("commit");
+        let db = PersistentDb::init(location.to_str().unwrap()).await.unwrap();
+        db.create_table("users", &users()).await.unwrap();
+        db.insert("users", &json!({ "email": "a@email.com" })).await.unwrap();
+
+        // Nothing is written to disk until the transaction is committed.
+        let root = location.clone();
+        db.transaction(Box::new(move |db| Box::pin(async move {
+            db.drop_table("users").await?;
+            db.create_table("users", &users()).await?;
+            db.insert("users", &json!({ "email": "b@email.com" })).await?;
+            db.create_table("posts", &users()).await?;
+            db.insert("posts", &json!({ "email": "c@email.com" })).await?;
+            assert!(root.join("users.json").exists());
+            assert!(!root.join("posts.json").exists());
+            Ok(())
+        }))).await.unwrap();
+        assert!(!location.join(super::COMMIT_FILE).exists());
+        drop(db);
+
+        let db = PersistentDb::init(location.to_str().unwrap()).await.unwrap();
+        assert_eq!(db.get("users", db.filter().build()).await.unwrap(), json!({ "email": "b@email.com" }));
+        assert_eq!(db.get("posts", db.filter().build()).await.unwrap(), json!({ "email": "c@email.com" }));
+        drop(db);
+
+        // Simulate a crash after a transaction was committed but before it was written out to its tables' files.
+        let commit = json!({ "tables": [
+            ["users", { "entries": [[1, null], [2, { "email": "b@email.com" }]] }],
+            ["posts", { "table": null }]
+        ] });
+        std::fs::write(location.join(super::COMMIT_FILE), commit.to_string()).unwrap();
+
+        let db = PersistentDb::init(location.to_str().unwrap()).await.unwrap();
+        assert_eq!(db.find_entries("users", db.filter().build()).await.unwrap(), vec![(2, json!({ "email": "b@email.com" }))]);
+        assert!(db.find("posts", db.filter().build()).await.is_err());
+        assert!(!location.join(super::COMMIT_FILE).exists());
+        assert!(!location.join("posts.json").exists());
+        drop(db);
+
+        // The transaction was written out, so isn't replayed again.
+        let db = PersistentDb::init(location.to_str().unwrap()).await.unwrap();
+        db.delete("users", db.filter().build()).await.unwrap();
+        drop(db);
+        let db = PersistentDb::init(location.to_str().unwrap()).await.unwrap();
+        assert!(db.find("users", db.filter().build()).await.is_err());
+
+        let _ = std::fs::remove_dir_all(location);
+    }
+
+    #[tokio::test]
+    async fn schema() {
+        let location = temp_location("schema");
+        let location = location.to_str().unwrap();
+
+        let db = PersistentDb::init(location).await.unwrap();
+        db.create_table("users", &users()).await.unwrap();
+        db.insert("users", &json!({ "email": "test@email.com" })).await.unwrap();
+        assert!(db.insert("users", &json!({ "name": "Test" })).await.is_err());
+        assert!(db.update("users", db.filter().build(), &json!({ "email": 1 })).await.is_err());
+        drop(db);
+
+        // Schemas are stored along with their tables, so are still enforced once they're loaded again, including when altering them.
+        let db = PersistentDb::init(location).await.unwrap();
+        assert!(db.insert("users", &json!({ "name": "Test" })).await.is_err());
+        db.insert("users", &json!({ "email": "other@email.com", "name": "Same" })).await.unwrap();
+        db.insert("users", &json!({ "email": "another@email.com", "name": "Same" })).await.unwrap();
+        assert!(db.alter_table("users", &TableChange::AddIndex { field: "name", unique: true }).await.is_err());
+        assert!(db.alter_table("users", &TableChange::DropField { field: "missing" }).await.is_err());
+        assert!(db.insert("users", &json!({ "email": "other@email.com" })).await.is_err());
+        drop(db);
+
+        // Snapshots written before schemas were stored can't be altered until their schema is known.
+        std::fs::write(PathBuf::from(location).join("old.json"), br#"{"next_id":1,"ids":[],"entries":[]}"#).unwrap();
+        let db = PersistentDb::init(location).await.unwrap();
+        assert!(db.alter_table("old", &TableChange::AddIndex { field: "email", unique: true }).await.is_err());
+        db.create_table("old", &users()).await.unwrap();
+        db.alter_table("old", &TableChange::AddIndex { field: "name", unique: false }).await.unwrap();
+
+        let _ = std::fs::remove_dir_all(location);
+    }
+
+    #[tokio::test]
+    async fn unique() {
+        let location = temp_location("unique");
+        let location = location.to_str().unwrap();
+
+        let db = PersistentDb::init(location).await.unwrap();
+        db.create_table("users", &users()).await.unwrap();
+        db.insert("users", &json!({ "email": "a@email.com", "name": "A" })).await.unwrap();
+        db.insert("users", &json!({ "email": "b@email.com", "name": "B" })).await.unwrap();
+        db.insert("users", &json!({ "email": "c@email.com", "name": "C" })).await.unwrap();
+
+        assert!(db.insert("users", &json!({ "email": "a@email.com" })).await.is_err());
+        let filter = db.filter().eq("email", "b@email.com".into()).build();
+        assert!(db.update("users", filter.clone(), &json!({ "email": "a@email.com" })).await.is_err());
+        db.update("users", filter, &json!({ "email": "b@email.com", "name": "Bee" })).await.unwrap();
+
+        // Entries after a deleted entry are still found through the index.
+        db.delete("users", db.filter().eq("email", "a@email.com".into()).build()).await.unwrap();
+        let filter = db.filter().eq("email", "c@email.com".into()).build();
+        assert_eq!(db.get("users", filter).await.unwrap()["name"], "C");
+        db.insert("users", &json!({ "email": "a@email.com" })).await.unwrap();
+
+        // Reloaded tables are indexed straight away.
+        drop(db);
+        let db = PersistentDb::init(location).await.unwrap();
+        assert!(db.insert("users", &json!({ "email": "c@email.com" })).await.is_err());
+
+        let _ = std::fs::remove_dir_all(location);
+    }
+
+    #[tokio::test]
+    async fn ids() {
+        let location = temp_location("ids");
+        let location = location.to_str().unwrap();
+
+        let db = PersistentDb::init(location).await.unwrap();
+        db.create_table("users", &users()).await.unwrap();
+        let a = db.insert("users", &json!({ "email": "a@email.com" })).await.unwrap();
+        let b = db.insert("users", &json!({ "email": "b@email.com" })).await.unwrap();
+        let c = db.insert("users", &json!({ "email": "c@email.com" })).await.unwrap();
+        assert_eq!(db.find("users", db.filter().eq("email", "b@email.com".into()).build()).await, Ok(b));
+
+        // Ids of later entries are unaffected by deletes, and deleted ids are never given out again.
+        db.delete("users", db.filter().eq("email", "a@email.com".into()).build()).await.unwrap();
+        assert_eq!(db.get_by_id("users", a).await, Err(DatabaseError::NotFound));
+        assert_eq!(db.get_by_id("users", c).await.unwrap()["email"], "c@email.com");
+        db.delete("users", db.filter().eq("email", "c@email.com".into()).build()).await.unwrap();
+        let d = db.insert("users", &json!({ "email": "d@email.com" })).await.unwrap();
+        assert!(d > c);
+        drop(db);
+
+        let db = PersistentDb::init(location).await.unwrap();
+        db.create_table("users", &users()).await.unwrap();
+        assert_eq!(db.get_by_id("users", b).await.unwrap()["email"], "b@email.com");
+        assert_eq!(db.get_by_id("users", d).await.unwrap()["email"], "d@email.com");
+        assert!(db.insert("users", &json!({ "email": "e@email.com" })).await.unwrap() > d);
+
+        let _ = std::fs::remove_dir_all(location);
+    }
+
+    #[tokio::test]
+    async fn incomplete_write() {
+        let location = temp_location("incomplete");
+        let db = PersistentDb::init(location.to_str().unwrap()).await.unwrap();
+        db.create_table("users", &users()).await.unwrap();
+        db.insert("users", &json!({ "email": "test@email.com" })).await.unwrap();
+        drop(db);
+
+        // Simulate a crash after the temporary file was partially written.
+        std::fs::write(location.join("users.json.tmp"), b"[{\"email\": \"trunc").unwrap();
+
+        let db = PersistentDb::init(location.to_str().unwrap()).await.unwrap();
+        assert_eq!(db.get_by_id("users", 1).await.unwrap(), json!({ "email": "test@email.com" }));
+        assert!(!location.join("users.json.tmp").exists());
+        drop(db);
+
+        // Simulate a crash partway through appending to the log, the incomplete record is discarded.
+        let mut log = std::fs::OpenOptions::new().append(true).open(location.join("users.log")).unwrap();
+        std::io::Write::write_all(&mut log, b"{\"put\":{\"id\":2,\"entry\":{\"email\":\"tru").unwrap();
+        drop(log);
+
+        let db = PersistentDb::init(location.to_str().unwrap()).await.unwrap();
+        db.create_table("users", &users()).await.unwrap();
+        assert_eq!(db.get_by_id("users", 2).await, Err(DatabaseError::NotFound));
+        let id = db.insert("users", &json!({ "email": "other@email.com" })).await.unwrap();
+        drop(db);
+
+        let db = PersistentDb::init(location.to_str().unwrap()).await.unwrap();
+        assert_eq!(db.get_by_id("users", id).await.unwrap(), json!({ "email": "other@email.com" }));
+
+        let _ = std::fs::remove_dir_all(location);
+    }
+}
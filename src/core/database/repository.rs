@@ -0,0 +1,213 @@
+use std::marker::PhantomData;
+
+use chrono::Utc;
+
+use super::{aggregate::{AggregateGroup, Aggregation}, error::DatabaseError, Database, DatabaseFilter, DatabaseFilterBuilder, DatabaseModel, DatabaseQuery, EntryId, FilterValue, PartialFilter, QueryResult, CREATED_AT, DELETED_AT, REVISION, UPDATED_AT};
+
+/// Typed access to the table of a [`DatabaseModel`], converting entries to and from `T`.
+///
+/// Entries which can't be parsed to `T` are reported as errors.
+/// If `T` uses soft deletion, deleted entries are hidden unless asked for with [`Repository::deleted`].
+/// If `T` uses revisions, entries start at revision 1 and every write moves the entries it changes to the next one.
+///
+/// # Examples
+/// ```
+/// let sessions = Repository::<Session>::new(&*req.state().db);
+///
+/// let filter = sessions.filter().eq("session_id", session_id.into()).build();
+/// let session: Session = sessions.get(filter).await?;
+/// ```
+pub struct Repository<'d, T: DatabaseModel> {
+    db: &'d dyn Database,
+    model: PhantomData<T>
+}
+
+impl<'d, T: DatabaseModel> Repository<'d, T> {
+    /// Create a repository over `db`, which may also be the database given to a transaction.
+    pub fn new(db: &'d dyn Database) -> Self {
+        Repository { db, model: PhantomData }
+    }
+
+    /// Create a new filter.
+    pub fn filter(&self) -> DatabaseFilterBuilder<FilterValue> {
+        self.db.filter()
+    }
+
+    /// Insert `model`, setting its timestamps and revision if `T` uses them.
+    pub async fn insert(&self, model: &T) -> Result<EntryId, DatabaseError> {
+        let mut entry = Repository::to_entry(model)?;
+        if T::timestamps() {
+            let now = Utc::now().timestamp_millis();
+            entry[CREATED_AT] = now.into();
+            entry[UPDATED_AT] = now.into();
+        }
+        if T::revisions() {
+            entry[REVISION] = 1.into();
+        }
+        self.db.insert(T::table(), &entry).await
+    }
+
+    /// Insert every model in `models`, returning their ids in the same order.
+    pub async fn insert_many(&self, models: &[T]) -> Result<Vec<EntryId>, DatabaseError> {
+        let now = Utc::now().timestamp_millis();
+        let entries = models.iter()
+            .map(|model| {
+                let mut entry = Repository::to_entry(model)?;
+                if T::timestamps() {
+                    entry[CREATED_AT] = now.into();
+                    entry[UPDATED_AT] = now.into();
+                }
+                if T::revisions() {
+                    entry[REVISION] = 1.into();
+                }
+                Ok(entry)
+            })
+            .collect::<Result<Vec<_>, DatabaseError>>()?;
+        self.db.insert_many(T::table(), &entries).await
+    }
+
+    /// Replace the first entry matching `filter` with `model`.
+    pub async fn update(&self, filter: DatabaseFilter<FilterValue>, model: &T) -> Result<(), DatabaseError> {
+        self.write(Self::visible(filter), Repository::to_entry(model)?).await
+    }
+
+    /// Replace the first entry matching `filter` with `model`, only if it is still at `revision`, returning its new revision.
+    /// Errors with [`DatabaseError::Conflict`] if the entry has been written since, see [`Database::update_if`].
+    pub async fn update_if(&self, filter: DatabaseFilter<FilterValue>, revision: u64, model: &T) -> Result<u64, DatabaseError> {
+        if !T::revisions() {
+            return Err(DatabaseError::InvalidArgument(format!("Table {} doesn't use revisions, entries can't be updated by revision.", T::table())));
+        }
+
+        let mut data = Repository::to_entry(model)?;
+        if T::timestamps() {
+            data[UPDATED_AT] = Utc::now().timestamp_millis().into();
+        }
+        self.db.update_if(T::table(), Self::visible(filter), revision, &data).await
+    }
+
+    /// Delete the first entry matching `filter`.
+    /// If `T` uses soft deletion the entry is only marked as deleted, and can be brought back with [`Repository::recover`].
+    pub async fn delete(&self, filter: DatabaseFilter<FilterValue>) -> Result<(), DatabaseError> {
+        if !T::soft_delete() {
+            return self.db.delete(T::table(), filter).await;
+        }
+
+        self.write(Self::visible(filter), serde_json::json!({ DELETED_AT: Utc::now().timestamp_millis() })).await
+    }
+
+    /// Set the fields in `data` for every entry matching `filter`, returning how many were updated.
+    pub async fn update_many(&self, filter: DatabaseFilter<FilterValue>, mut data: serde_json::Value) -> Result<usize, DatabaseError> {
+        if T::timestamps() {
+            data[UPDATED_AT] = Utc::now().timestamp_millis().into();
+        }
+        self.db.update_many(T::table(), Self::visible(filter), &data).await
+    }
+
+    /// Delete every entry matching `filter`, returning how many were deleted.
+    /// As with [`Repository::delete`], entries are only marked as deleted if `T` uses soft deletion.
+    pub async fn delete_many(&self, filter: DatabaseFilter<FilterValue>) -> Result<usize, DatabaseError> {
+        match T::soft_delete() {
+            true => self.update_many(filter, serde_json::json!({ DELETED_AT: Utc::now().timestamp_millis() })).await,
+            false => self.db.delete_many(T::table(), filter).await
+        }
+    }
+
+    /// Permanently delete the first entry matching `filter`, whether or not it has been marked as deleted.
+    pub async fn purge(&self, filter: DatabaseFilter<FilterValue>) -> Result<(), DatabaseError> {
+        self.db.delete(T::table(), filter).await
+    }
+
+    /// Bring back the first deleted entry matching `filter`.
+    /// Deleted entries still hold on to their values for unique fields, so recovering one can't conflict with another entry.
+    pub async fn recover(&self, filter: DatabaseFilter<FilterValue>) -> Result<(), DatabaseError> {
+        let filter = Self::deleted_only(filter)?;
+        self.write(filter, serde_json::json!({ DELETED_AT: null })).await
+    }
+
+    /// Every deleted entry matching the query.
+    pub async fn deleted(&self, query: DatabaseQuery<FilterValue>) -> Result<QueryResult<T>, DatabaseError> {
+        let filter = Self::deleted_only(query.filter.clone())?;
+        self.query_all(DatabaseQuery { filter, ..query }).await
+    }
+
+    pub async fn get(&self, filter: DatabaseFilter<FilterValue>) -> Result<T, DatabaseError> {
+        Repository::parse(self.db.get(T::table(), Self::visible(filter)).await?)
+    }
+
+    /// Get the first entry matching `filter` along with its revision, to later update it with [`Repository::update_if`].
+    pub async fn get_with_revision(&self, filter: DatabaseFilter<FilterValue>) -> Result<(T, u64), DatabaseError> {
+        let entry = self.db.get(T::table(), Self::visible(filter)).await?;
+        let revision = entry.get(REVISION).and_then(|revision| revision.as_u64()).unwrap_or(0);
+        Ok((Repository::parse(entry)?, revision))
+    }
+
+    pub async fn find(&self, filter: DatabaseFilter<FilterValue>) -> Result<EntryId, DatabaseError> {
+        self.db.find(T::table(), Self::visible(filter)).await
+    }
+
+    pub async fn get_by_id(&self, id: EntryId) -> Result<T, DatabaseError> {
+        let entry = self.db.get_by_id(T::table(), id).await?;
+        if T::soft_delete() && !entry[DELETED_AT].is_null() {
+            return Err(DatabaseError::NotFound);
+        }
+        Repository::parse(entry)
+    }
+
+    pub async fn query(&self, query: DatabaseQuery<FilterValue>) -> Result<QueryResult<T>, DatabaseError> {
+        let filter = Self::visible(query.filter.clone());
+        self.query_all(DatabaseQuery { filter, ..query }).await
+    }
+
+    pub async fn count(&self, filter: DatabaseFilter<FilterValue>) -> Result<usize, DatabaseError> {
+        self.db.count(T::table(), Self::visible(filter)).await
+    }
+
+    pub async fn aggregate(&self, aggregation: Aggregation<FilterValue>) -> Result<Vec<AggregateGroup>, DatabaseError> {
+        let filter = Self::visible(aggregation.filter.clone());
+        self.db.aggregate(T::table(), Aggregation { filter, ..aggregation }).await
+    }
+
+    async fn query_all(&self, query: DatabaseQuery<FilterValue>) -> Result<QueryResult<T>, DatabaseError> {
+        let result = self.db.query(T::table(), query).await?;
+        Ok(QueryResult {
+            entries: result.entries.into_iter().map(Repository::parse).collect::<Result<_, _>>()?,
+            total: result.total
+        })
+    }
+
+    /// Update the first entry matching `filter` with `data`, setting when it was updated if `T` uses timestamps.
+    /// The database moves the entry to its next revision itself.
+    async fn write(&self, filter: DatabaseFilter<FilterValue>, mut data: serde_json::Value) -> Result<(), DatabaseError> {
+        if T::timestamps() {
+            data[UPDATED_AT] = Utc::now().timestamp_millis().into();
+        }
+        self.db.update(T::table(), filter, &data).await
+    }
+
+    /// Restrict `filter` to entries which haven't been deleted.
+    fn visible(mut filter: DatabaseFilter<FilterValue>) -> DatabaseFilter<FilterValue> {
+        if T::soft_delete() {
+            filter.0.push(PartialFilter::EXISTS { key: DELETED_AT, exists: false });
+        }
+        filter
+    }
+
+    /// Restrict `filter` to entries which have been deleted.
+    fn deleted_only(mut filter: DatabaseFilter<FilterValue>) -> Result<DatabaseFilter<FilterValue>, DatabaseError> {
+        if !T::soft_delete() {
+            return Err(DatabaseError::InvalidArgument(format!("Table {} doesn't use soft deletion, deleted entries can't be recovered.", T::table())));
+        }
+        filter.0.push(PartialFilter::EXISTS { key: DELETED_AT, exists: true });
+        Ok(filter)
+    }
+
+    fn to_entry(model: &T) -> Result<serde_json::Value, DatabaseError> {
+        serde_json::to_value(model)
+            .map_err(|e| DatabaseError::InvalidArgument(format!("Failed to serialize entry for table {}: {e}", T::table())))
+    }
+
+    fn parse(entry: serde_json::Value) -> Result<T, DatabaseError> {
+        serde_json::from_value(entry)
+            .map_err(|e| DatabaseError::Parse(format!("Failed to parse entry from table {}: {e}", T::table())))
+    }
+}
@@ -0,0 +1,475 @@
+use std::{collections::{HashMap, HashSet}, sync::{Arc, Mutex, Weak}};
+
+use async_std::{channel::Receiver, task};
+
+use super::{changes::{Change, ChangeEvent}, error::DatabaseError, searchable, Database, EntryId, QueryResult, DELETED_AT};
+
+/// BM25 term frequency saturation, higher values let repeated terms count for more.
+const K1: f64 = 1.2;
+/// BM25 length normalisation, from 0 where length doesn't matter to 1 where it fully does.
+const B: f64 = 0.75;
+
+/// Common English words which say little about an entry, so aren't indexed.
+const STOP_WORDS: [&str; 35] = [
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "from", "if", "in", "into", "is", "it", "its",
+    "no", "not", "of", "on", "or", "so", "such", "that", "the", "their", "then", "there", "these", "they", "this", "to", "was", "with"
+];
+
+/// Split `text` into the terms it is indexed and searched by.
+///
+/// Words are split on anything other than letters and digits, lowercased and stemmed, see [`stem`].
+/// Stop words, such as "the" and "and", are left out.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .filter(|word| !STOP_WORDS.contains(&word.as_str()))
+        .map(|word| stem(&word))
+        .collect()
+}
+
+/// Reduce an English word to its stem with the Porter stemming algorithm, so "running" and "runs" both become "run".
+///
+/// Stems aren't always words themselves, "happy" becomes "happi".
+/// Words which aren't lowercase ascii letters are returned as they are.
+pub fn stem(word: &str) -> String {
+    if word.len() <= 2 || !word.bytes().all(|c| c.is_ascii_lowercase()) {
+        return word.to_string();
+    }
+
+    let mut stemmer = Stemmer { word: word.as_bytes().to_vec(), stem: 0 };
+    stemmer.step1();
+    stemmer.step2();
+    stemmer.step3();
+    stemmer.step4();
+    stemmer.step5();
+    String::from_utf8(stemmer.word).unwrap()
+}
+
+/// State of a word being stemmed, see <https://tartarus.org/martin/PorterStemmer/def.txt>.
+struct Stemmer {
+    word: Vec<u8>,
+    /// Length of the word without the suffix last matched by [`Stemmer::ends`].
+    stem: usize
+}
+
+impl Stemmer {
+    /// Whether the letter at `i` is a consonant, "y" is one unless it follows a consonant.
+    fn cons(&self, i: usize) -> bool {
+        match self.word[i] {
+            b'a' | b'e' | b'i' | b'o' | b'u' => false,
+            b'y' => i == 0 || !self.cons(i - 1),
+            _ => true
+        }
+    }
+
+    /// Number of vowel-consonant sequences in the first `len` letters.
+    fn measure(&self, len: usize) -> usize {
+        (1..len).filter(|&i| !self.cons(i - 1) && self.cons(i)).count()
+    }
+
+    fn vowel_in(&self, len: usize) -> bool {
+        (0..len).any(|i| !self.cons(i))
+    }
+
+    /// Whether the letters at `i` and before it are the same consonant.
+    fn double_cons(&self, i: usize) -> bool {
+        i >= 1 && self.word[i] == self.word[i - 1] && self.cons(i)
+    }
+
+    /// Whether the letters ending at `i` are consonant-vowel-consonant, where the last consonant isn't w, x or y.
+    fn cvc(&self, i: usize) -> bool {
+        i >= 2 && self.cons(i) && !self.cons(i - 1) && self.cons(i - 2) && !matches!(self.word[i], b'w' | b'x' | b'y')
+    }
+
+    /// Whether the word ends in `suffix`, setting the stem to what's before it if it does.
+    fn ends(&mut self, suffix: &str) -> bool {
+        if !self.word.ends_with(suffix.as_bytes()) {
+            return false;
+        }
+        self.stem = self.word.len() - suffix.len();
+        true
+    }
+
+    /// Replace the suffix last matched by [`Stemmer::ends`] with `replacement`.
+    fn set(&mut self, replacement: &str) {
+        self.word.truncate(self.stem);
+        self.word.extend_from_slice(replacement.as_bytes());
+    }
+
+    /// Replace the first of `suffixes` the word ends in, if its stem has a measure of more than `min`.
+    fn replace(&mut self, suffixes: &[(&str, &str)], min: usize) {
+        if let Some((_, replacement)) = suffixes.iter().find(|(suffix, _)| self.ends(suffix)) {
+            if self.measure(self.stem) > min {
+                self.set(replacement);
+            }
+        }
+    }
+
+    /// Plurals and -ed or -ing, then a terminal y to i.
+    fn step1(&mut self) {
+        if self.ends("sses") {
+            self.set("ss");
+        } else if self.ends("ies") {
+            self.set("i");
+        } else if !self.ends("ss") && self.ends("s") {
+            self.set("");
+        }
+
+        if self.ends("eed") {
+            if self.measure(self.stem) > 0 {
+                self.word.pop();
+            }
+        } else if (self.ends("ed") || self.ends("ing")) && self.vowel_in(self.stem) {
+            self.set("");
+            if self.ends("at") || self.ends("bl") || self.ends("iz") {
+                self.word.push(b'e');
+            } else if self.double_cons(self.word.len() - 1) {
+                if !matches!(self.word.last(), Some(b'l' | b's' | b'z')) {
+                    self.word.pop();
+                }
+            } else if self.measure(self.word.len()) == 1 && self.cvc(self.word.len() - 1) {
+                self.word.push(b'e');
+            }
+        }
+
+        if self.ends("y") && self.vowel_in(self.stem) {
+            self.set("i");
+        }
+    }
+
+    /// Double suffixes to single ones, such as -ization to -ize.
+    fn step2(&mut self) {
+        self.replace(&[
+            ("ational", "ate"), ("tional", "tion"), ("enci", "ence"), ("anci", "ance"), ("izer", "ize"),
+            ("bli", "ble"), ("alli", "al"), ("entli", "ent"), ("eli", "e"), ("ousli", "ous"),
+            ("ization", "ize"), ("ation", "ate"), ("ator", "ate"), ("alism", "al"), ("iveness", "ive"),
+            ("fulness", "ful"), ("ousness", "ous"), ("aliti", "al"), ("iviti", "ive"), ("biliti", "ble"), ("logi", "log")
+        ], 0);
+    }
+
+    /// -ic-, -full, -ness and similar.
+    fn step3(&mut self) {
+        self.replace(&[
+            ("icate", "ic"), ("ative", ""), ("alize", "al"), ("iciti", "ic"), ("ical", "ic"), ("ful", ""), ("ness", "")
+        ], 0);
+    }
+
+    /// -ant, -ence and similar, from words which are long enough without them.
+    fn step4(&mut self) {
+        const SUFFIXES: [&str; 19] = [
+            "al", "ance", "ence", "er", "ic", "able", "ible", "ant", "ement", "ment", "ent", "ion", "ou", "ism", "ate", "iti", "ous", "ive", "ize"
+        ];
+        let Some(suffix) = SUFFIXES.iter().find(|suffix| self.ends(suffix)) else {
+            return
+        };
+        // -ion is only removed after s or t.
+        if *suffix == "ion" && !(self.stem > 0 && matches!(self.word[self.stem - 1], b's' | b't')) {
+            return;
+        }
+        if self.measure(self.stem) > 1 {
+            self.set("");
+        }
+    }
+
+    /// A final -e, and -ll to -l, from words which are long enough without them.
+    fn step5(&mut self) {
+        if self.ends("e") {
+            let measure = self.measure(self.stem);
+            if measure > 1 || (measure == 1 && !self.cvc(self.stem - 1)) {
+                self.word.pop();
+            }
+        }
+        let len = self.word.len();
+        if self.word.last() == Some(&b'l') && self.double_cons(len - 1) && self.measure(len) > 1 {
+            self.word.pop();
+        }
+    }
+}
+
+/// Terms of a single indexed entry.
+struct Document {
+    length: usize,
+    terms: HashSet<String>
+}
+
+/// Inverted index over the string `fields` of a table's entries, ranked with BM25.
+pub struct SearchIndex {
+    fields: Vec<&'static str>,
+    /// Occurrences of each term in each entry containing it.
+    postings: HashMap<String, HashMap<EntryId, usize>>,
+    documents: HashMap<EntryId, Document>,
+    /// Sum of the lengths of every document, to find their average.
+    total_length: usize
+}
+
+impl SearchIndex {
+    pub fn new(fields: Vec<&'static str>) -> SearchIndex {
+        SearchIndex { fields, postings: HashMap::new(), documents: HashMap::new(), total_length: 0 }
+    }
+
+    /// Index `entry`, replacing it if it has already been indexed.
+    /// Fields which are missing or aren't strings are skipped, and entries marked as deleted aren't indexed, see [`DatabaseModel::soft_delete`](super::DatabaseModel::soft_delete).
+    pub fn insert(&mut self, id: EntryId, entry: &serde_json::Value) {
+        self.remove(id);
+        if is_deleted(entry) {
+            return;
+        }
+
+        let tokens: Vec<String> = self.fields.iter()
+            .filter_map(|field| entry.get(field).and_then(|val| val.as_str()))
+            .flat_map(tokenize)
+            .collect();
+        if tokens.is_empty() {
+            return;
+        }
+
+        for token in &tokens {
+            *self.postings.entry(token.clone()).or_default().entry(id).or_default() += 1;
+        }
+        self.total_length += tokens.len();
+        self.documents.insert(id, Document { length: tokens.len(), terms: tokens.into_iter().collect() });
+    }
+
+    pub fn remove(&mut self, id: EntryId) {
+        let Some(document) = self.documents.remove(&id) else {
+            return
+        };
+        for term in document.terms {
+            if let Some(posting) = self.postings.get_mut(&term) {
+                posting.remove(&id);
+                if posting.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+        self.total_length -= document.length;
+    }
+
+    /// Keep the index up to date with a change made to its table.
+    pub fn apply(&mut self, change: &Change) {
+        match &change.event {
+            ChangeEvent::Insert { new } | ChangeEvent::Update { new, .. } => self.insert(change.id, new),
+            ChangeEvent::Delete { .. } => self.remove(change.id)
+        }
+    }
+
+    /// Ids of entries containing any of the terms in `query`, along with their scores, best first.
+    /// Entries scoring the same are in the order they were inserted.
+    pub fn search(&self, query: &str) -> Vec<(EntryId, f64)> {
+        let terms: HashSet<String> = tokenize(query).into_iter().collect();
+        let count = self.documents.len() as f64;
+        let average = self.total_length as f64 / count.max(1.0);
+
+        let mut scores: HashMap<EntryId, f64> = HashMap::new();
+        for posting in terms.iter().filter_map(|term| self.postings.get(term)) {
+            let matches = posting.len() as f64;
+            let idf = (1.0 + (count - matches + 0.5) / (matches + 0.5)).ln();
+            for (id, occurrences) in posting {
+                let occurrences = *occurrences as f64;
+                let length = self.documents[id].length as f64;
+                let score = idf * occurrences * (K1 + 1.0) / (occurrences + K1 * (1.0 - B + B * length / average));
+                *scores.entry(*id).or_default() += score;
+            }
+        }
+
+        let mut ranked: Vec<_> = scores.into_iter().collect();
+        ranked.sort_by(|(a_id, a), (b_id, b)| b.total_cmp(a).then(a_id.cmp(b_id)));
+        ranked
+    }
+}
+
+/// An entry matching a search, from [`Search::search`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchHit {
+    pub id: EntryId,
+    /// How well the entry matches, higher is better.
+    pub score: f64,
+    pub entry: serde_json::Value
+}
+
+/// Full-text search over the string fields of entries, see [`DatabaseModel::searchable`](super::DatabaseModel::searchable).
+///
+/// Each table is indexed in memory when it is added, the index is then kept up to date in the background
+/// by applying each change made to the table as it is made, see [`spawn_updater`].
+///
+/// # Examples
+/// ```
+/// let search = Search::init(db).await?;
+/// let result = search.search("articles", "healthy eating", Some(10), 0).await?;
+/// log::debug!("Showing {} of {} articles", result.entries.len(), result.total);
+/// ```
+pub struct Search {
+    db: Arc<dyn Database>,
+    tables: Mutex<HashMap<String, Arc<Mutex<SearchIndex>>>>
+}
+
+impl Search {
+    pub fn new(db: Arc<dyn Database>) -> Search {
+        Search { db, tables: Mutex::new(HashMap::new()) }
+    }
+
+    /// Create a search over every table in [`searchable`].
+    pub async fn init(db: Arc<dyn Database>) -> Result<Search, DatabaseError> {
+        let search = Search::new(db);
+        for (table_id, fields) in searchable() {
+            let indexed = search.index(table_id, fields).await?;
+            log::debug!("Indexed {indexed} entries of {table_id} for search.");
+        }
+        Ok(search)
+    }
+
+    /// Index `fields` of every entry in `table_id`, replacing its existing index, returning how many entries were indexed.
+    pub async fn index(&self, table_id: &str, fields: Vec<&'static str>) -> Result<usize, DatabaseError> {
+        // Subscribe first so nothing written while the entries are read is missed, changes already indexed are applied again harmlessly.
+        let changes = self.db.subscribe(table_id);
+        let entries = self.db.find_entries(table_id, self.db.filter().build()).await?;
+
+        let mut index = SearchIndex::new(fields);
+        for (id, entry) in &entries {
+            index.insert(*id, entry);
+        }
+        let index = Arc::new(Mutex::new(index));
+        spawn_updater(Arc::downgrade(&index), changes);
+        self.tables.lock().unwrap().insert(table_id.to_string(), index);
+        Ok(entries.len())
+    }
+
+    /// Entries of `table_id` matching `query`, best first, along with the total number of matches before `limit` and `offset` are applied.
+    /// Errors with [`DatabaseError::InvalidArgument`] if the table hasn't been indexed.
+    pub async fn search(&self, table_id: &str, query: &str, limit: Option<usize>, offset: usize) -> Result<QueryResult<SearchHit>, DatabaseError> {
+        let Some(index) = self.tables.lock().unwrap().get(table_id).cloned() else {
+            return Err(DatabaseError::InvalidArgument(format!("Table {table_id} isn't searchable.")))
+        };
+        let ranked = index.lock().unwrap().search(query);
+
+        let total = ranked.len();
+        let mut entries = Vec::new();
+        for (id, score) in ranked.into_iter().skip(offset).take(limit.unwrap_or(usize::MAX)) {
+            match self.db.get_by_id(table_id, id).await {
+                // Deleted since the index was last updated.
+                Ok(entry) if is_deleted(&entry) => continue,
+                Ok(entry) => entries.push(SearchHit { id, score, entry }),
+                Err(DatabaseError::NotFound) => continue,
+                Err(e) => return Err(e)
+            }
+        }
+        Ok(QueryResult { entries, total })
+    }
+}
+
+/// Whether `entry` has been marked as deleted, such entries are hidden by [`Repository`](super::repository::Repository) so aren't searchable either.
+fn is_deleted(entry: &serde_json::Value) -> bool {
+    entry.get(DELETED_AT).is_some_and(|deleted_at| !deleted_at.is_null())
+}
+
+/// Apply every change in `changes` to `index` in the background as it is received,
+/// until the index is replaced or the search holding it is dropped.
+fn spawn_updater(index: Weak<Mutex<SearchIndex>>, changes: Receiver<Change>) {
+    task::spawn(async move {
+        while let Ok(change) = changes.recv().await {
+            let Some(index) = index.upgrade() else {
+                break
+            };
+            index.lock().unwrap().apply(&change);
+        }
+    });
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use serde_json::json;
+
+    use crate::core::{database::{migrations, repository::Repository, volatile::VolatileDb, Database, DatabaseModel, EntryId}, models::{article::{Article, ArticleSection}, ModelSchema, ModelValueType}};
+
+    use super::{stem, tokenize, Search};
+
+    #[test]
+    fn stemming() {
+        let cases = [
+            ("caresses", "caress"), ("ponies", "poni"), ("cats", "cat"), ("feed", "feed"), ("agreed", "agre"),
+            ("running", "run"), ("hopping", "hop"), ("filing", "file"), ("happy", "happi"), ("relational", "relat"),
+            ("conditional", "condit"), ("generalization", "gener"), ("hopefulness", "hope"), ("connections", "connect"),
+            ("adoption", "adopt"), ("controll", "control"), ("probate", "probat"), ("rate", "rate"), ("sky", "sky")
+        ];
+        for (word, expected) in cases {
+            assert_eq!(stem(word), expected, "stemming {word}");
+        }
+        assert_eq!(tokenize("The NHS's advice: keep running, and stay hydrated!"), ["nh", "s", "advic", "keep", "run", "stai", "hydrat"]);
+    }
+
+    #[tokio::test]
+    async fn ranking() {
+        let db = VolatileDb::init("").await.unwrap();
+        db.create_table("articles", &ModelSchema {
+            fields: vec![ModelValueType::String { field: "title" }, ModelValueType::String { field: "body" }],
+            optional: vec!["body"],
+            unique: Vec::new(),
+            indexed: Vec::new()
+        }).await.unwrap();
+
+        let flu = db.insert("articles", &json!({ "title": "Flu season", "body": "How to avoid catching the flu this winter." })).await.unwrap();
+        let sleep = db.insert("articles", &json!({ "title": "Sleeping well", "body": "Regular sleep helps you avoid illness." })).await.unwrap();
+        db.insert("articles", &json!({ "title": "Clinic opening hours" })).await.unwrap();
+
+        let search = Search::new(Arc::new(VolatileDb::default()));
+        assert!(search.search("articles", "flu", None, 0).await.is_err());
+        let search = Search::new(db.clone());
+        assert_eq!(search.index("articles", vec!["title", "body"]).await, Ok(3));
+
+        let result = search.search("articles", "avoiding the flu", None, 0).await.unwrap();
+        assert_eq!(result.total, 2);
+        assert_eq!(result.entries.iter().map(|hit| hit.id).collect::<Vec<_>>(), [flu, sleep]);
+        assert!(result.entries[0].score > result.entries[1].score);
+        assert_eq!(search.search("articles", "sleep", Some(1), 0).await.unwrap().entries[0].entry["title"], json!("Sleeping well"));
+        assert_eq!(search.search("articles", "the", None, 0).await.unwrap().total, 0);
+
+        // Writes made after indexing are searchable.
+        let news = db.insert("articles", &json!({ "title": "Flu vaccines available" })).await.unwrap();
+        db.update("articles", db.filter().eq("title", "Flu season".into()).build(), &json!({ "title": "Winter season" , "body": "Stay warm." })).await.unwrap();
+        db.delete("articles", db.filter().eq("title", "Sleeping well".into()).build()).await.unwrap();
+
+        // Changes are applied in the background, so wait for the last of them to be.
+        for _ in 0..100 {
+            if search.search("articles", "sleep", None, 0).await.unwrap().total == 0 {
+                break;
+            }
+            async_std::task::sleep(Duration::from_millis(10)).await;
+        }
+        let result = search.search("articles", "flu", None, 0).await.unwrap();
+        assert_eq!(result.entries.iter().map(|hit| hit.id).collect::<Vec<_>>(), [news]);
+        assert_eq!(search.search("articles", "sleep", None, 0).await.unwrap().total, 0);
+        assert_eq!(search.search("articles", "winter", None, 0).await.unwrap().entries[0].id, flu);
+    }
+
+    #[tokio::test]
+    async fn soft_delete() {
+        let db: Arc<dyn Database> = Arc::new(VolatileDb::default());
+        migrations::migrate(&*db, None).await.unwrap();
+        let articles = Repository::<Article>::new(&*db);
+        let article = |title: &str| Article { section: ArticleSection::News, title: title.into(), body: "Opening hours are changing.".into() };
+        let id = articles.insert(&article("Clinic closed")).await.unwrap();
+        let deleted = articles.insert(&article("Clinic moved")).await.unwrap();
+        articles.delete(articles.filter().eq("title", "Clinic moved".into()).build()).await.unwrap();
+
+        let search = Search::init(db.clone()).await.unwrap();
+        assert_eq!(found(&search, "clinic").await, [id]);
+
+        // Entries deleted and recovered after indexing are hidden and shown again.
+        articles.recover(articles.filter().eq("title", "Clinic moved".into()).build()).await.unwrap();
+        articles.delete(articles.filter().eq("title", "Clinic closed".into()).build()).await.unwrap();
+        for _ in 0..100 {
+            if found(&search, "clinic").await == [deleted] {
+                break;
+            }
+            async_std::task::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(found(&search, "clinic").await, [deleted]);
+    }
+
+    async fn found(search: &Search, query: &str) -> Vec<EntryId> {
+        search.search(Article::table(), query, None, 0).await.unwrap().entries.iter().map(|hit| hit.id).collect()
+    }
+}
@@ -0,0 +1,161 @@
+use std::{fs, path::Path};
+
+use chrono::Utc;
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::core::{accounts, models::account::Account};
+
+use super::{error::DatabaseError, tables, Database, CREATED_AT, UPDATED_AT};
+
+/// Directory fixtures are read from by default.
+pub const FIXTURES_DIR: &str = "fixtures";
+
+/// Password of every account created by [`random_accounts`].
+pub const DUMMY_PASSWORD: &str = "TestPassword123";
+
+const FIRST_NAMES: [&str; 20] = [
+    "Olivia", "Amelia", "Isla", "Ava", "Mia", "Freya", "Lily", "Grace", "Sophia", "Ella",
+    "Noah", "Oliver", "George", "Arthur", "Leo", "Harry", "Oscar", "Archie", "Henry", "Jack"
+];
+
+const SURNAMES: [&str; 20] = [
+    "Smith", "Jones", "Taylor", "Brown", "Williams", "Wilson", "Johnson", "Davies", "Patel", "Robinson",
+    "Wright", "Thompson", "Evans", "Walker", "White", "Roberts", "Green", "Hall", "Wood", "O'Connor"
+];
+
+/// Populate the database with seed data, primarily for testing.
+///
+/// Fixtures are loaded from `dir` first, followed by `accounts` random accounts generated from `seed`.
+/// Seeding the same database twice with the same arguments leaves it unchanged the second time.
+pub async fn seed(db: &dyn Database, dir: &Path, accounts: usize, seed: u64) -> Result<(), DatabaseError> {
+    log::debug!("Loading fixtures from {}...", dir.display());
+    let loaded = load_fixtures(db, dir).await?;
+    log::debug!("Loaded {loaded} fixture(s).");
+
+    if accounts > 0 {
+        log::debug!("Creating {accounts} random account(s) from seed {seed}...");
+        let created = random_accounts(db, accounts, seed).await;
+        log::debug!("Created {} account(s), each with the password {DUMMY_PASSWORD:?}.", created.len());
+    }
+    Ok(())
+}
+
+/// Insert the entries of every table in [`tables`] from `dir/<table>.json`, returning how many were inserted.
+///
+/// Each file holds an array of entries, tables without a file are skipped.
+/// Tables which already hold any of the entries are skipped too, as the fixtures have already been loaded.
+///
+/// Missing [`CREATED_AT`] and [`UPDATED_AT`] fields are set for tables which have them.
+pub async fn load_fixtures(db: &dyn Database, dir: &Path) -> Result<usize, DatabaseError> {
+    let now = Utc::now().timestamp_millis();
+    let mut loaded = 0;
+
+    for (table_id, schema) in tables() {
+        let path = dir.join(format!("{table_id}.json"));
+        if !path.exists() {
+            continue;
+        }
+
+        let raw = fs::read(&path)
+            .map_err(|e| DatabaseError::Backend(format!("Failed to read {}: {e}", path.display())))?;
+        let mut entries = serde_json::from_slice::<Vec<serde_json::Value>>(&raw)
+            .map_err(|e| DatabaseError::Parse(format!("Failed to parse {}: {e}", path.display())))?;
+
+        let timestamps = [CREATED_AT, UPDATED_AT].into_iter()
+            .filter(|field| schema.fields.iter().any(|value_type| value_type.field() == *field))
+            .collect::<Vec<_>>();
+        for entry in entries.iter_mut().filter_map(|entry| entry.as_object_mut()) {
+            for field in &timestamps {
+                entry.entry(*field).or_insert(now.into());
+            }
+        }
+
+        match db.insert_many(table_id, &entries).await {
+            Ok(ids) => loaded += ids.len(),
+            Err(DatabaseError::Conflict { .. }) => log::warn!("Skipped fixtures for {table_id}, they have already been loaded."),
+            Err(e) => return Err(e)
+        }
+    }
+    Ok(loaded)
+}
+
+/// Register `count` accounts with random names, returning their uids.
+///
+/// The same `seed` always generates the same accounts, uids included.
+/// Accounts which already exist are skipped.
+pub async fn random_accounts(db: &dyn Database, count: usize, seed: u64) -> Vec<String> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut uids = Vec::with_capacity(count);
+
+    for n in 1..=count {
+        let firstname = FIRST_NAMES.choose(&mut rng).unwrap();
+        let surname = SURNAMES.choose(&mut rng).unwrap();
+        let uid = uuid::Builder::from_random_bytes(rng.gen()).into_uuid().to_string();
+        let local = format!("{firstname}.{surname}{n}").to_lowercase().replace('\'', "");
+
+        let account = Account {
+            uid,
+            firstname: firstname.to_string(),
+            surname: surname.to_string(),
+            email: format!("{local}@example.com"),
+            pass_hash: DUMMY_PASSWORD.into()
+        };
+        match accounts::register(db, account, false, true).await {
+            Ok(uid) => uids.push(uid),
+            Err(e) => log::warn!("Skipped random account {n}: {e}")
+        }
+    }
+    uids
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::path::PathBuf;
+
+    use serde_json::json;
+
+    use crate::core::{accounts, database::{migrations, repository::Repository, volatile::VolatileDb, Database, DatabaseQuery}, models::account::Account};
+
+    use super::{load_fixtures, random_accounts, DUMMY_PASSWORD, FIXTURES_DIR};
+
+    async fn db() -> VolatileDb {
+        let db = VolatileDb::default();
+        migrations::migrate(&db, None).await.unwrap();
+        crate::core::database::setup(&db).await;
+        db
+    }
+
+    async fn emails(db: &dyn Database) -> Vec<String> {
+        Repository::<Account>::new(db).query(DatabaseQuery::new(db.filter().build())).await.unwrap()
+            .entries.into_iter().map(|account| account.email).collect()
+    }
+
+    #[tokio::test]
+    async fn fixtures() {
+        let db = db().await;
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(FIXTURES_DIR);
+
+        assert!(load_fixtures(&db, &dir).await.unwrap() > 0);
+        assert_eq!(load_fixtures(&db, &dir).await, Ok(0));
+        let account = db.get("accounts", db.filter().eq("email", "person@email.com".into()).build()).await.unwrap();
+        assert_eq!(account["firstname"], json!("Real"));
+        assert!(account["created_at"].is_i64());
+        assert!(accounts::login(&db, "person@email.com".into(), accounts::hash_password(DUMMY_PASSWORD.into())).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn reproducible() {
+        let (a, b, c) = (db().await, db().await, db().await);
+        let uids = random_accounts(&a, 25, 7).await;
+        assert_eq!(uids.len(), 25);
+        assert_eq!(random_accounts(&b, 25, 7).await, uids);
+        assert_eq!(emails(&a).await, emails(&b).await);
+        assert_ne!(random_accounts(&c, 25, 8).await, uids);
+
+        // Seeding again doesn't create duplicates.
+        assert!(random_accounts(&a, 25, 7).await.is_empty());
+        assert_eq!(emails(&a).await.len(), 25);
+        assert!(accounts::login(&a, emails(&a).await[0].clone(), accounts::hash_password(DUMMY_PASSWORD.into())).await.is_ok());
+    }
+}
@@ -0,0 +1,1237 @@
+use std::{collections::HashMap, sync::{Arc, Mutex, RwLock}};
+use async_std::{channel::Receiver, task};
+use async_trait::async_trait;
+use rusqlite::{Connection, params_from_iter, types::Value as SqlValue};
+
+use crate::core::models::{ModelSchema, ModelValueType};
+
+use super::{aggregate::{AggregateGroup, Aggregation, Grouping, Metric}, changes::{Change, ChangeEvent, Subscriptions}, error::DatabaseError, Database, DatabaseFilter, DatabaseQuery, PartialFilter, QueryResult, SortOrder, EntryId, FilterValue, TableChange, TransactionFn, REVISION, transaction::{self, Operation, Transactional}};
+
+/// Column holding the id of each entry, the rowid is an alias for it.
+/// Tables created before entries had ids don't have it, their rowid is used instead.
+const ID_COLUMN: &str = "_id";
+/// Table holding the schema of every other table, as json, so schemas survive the database being reopened.
+const SCHEMAS_TABLE: &str = "_schemas";
+
+/// Embedded SQLite database, used for production.
+///
+/// Each table is created with a typed column per model field.
+/// Entries are converted between json and columns using the declared column types,
+/// see [`SqliteDb::column_type`].
+///
+/// Queries block until SQLite is done with them, so they're run from blocking tasks, see [`SqliteDb::blocking`].
+pub struct SqliteDb {
+    connection: Arc<SqliteConnection>,
+    /// Held for the duration of a transaction, so only one can run at a time, and by each write made outside of one while it runs.
+    transaction: async_std::sync::Mutex<()>,
+    subscriptions: Subscriptions
+}
+
+/// Everything used by queries, shared with the blocking tasks they run from.
+struct SqliteConnection {
+    conn: Mutex<Connection>,
+    /// Schema of each table, registered by [`Database::create_table`] and used to validate writes.
+    /// A copy of [`SCHEMAS_TABLE`], loaded when the database is opened.
+    schemas: RwLock<HashMap<String, ModelSchema>>
+}
+
+impl SqliteDb {
+    /// Run `f` against the connection from a blocking task.
+    async fn blocking<R: Send + 'static>(&self, f: impl FnOnce(&SqliteConnection) -> Result<R, DatabaseError> + Send + 'static) -> Result<R, DatabaseError> {
+        let connection = self.connection.clone();
+        task::spawn_blocking(move || f(&connection)).await
+    }
+
+    /// Declared SQLite column type for a model field.
+    /// Objects and arrays are stored as json text, booleans as 0/1.
+    fn column_type(field: &ModelValueType) -> (&'static str, &'static str) {
+        match field {
+            ModelValueType::String  { field } => (field, "TEXT"),
+            ModelValueType::Number  { field } => (field, "NUMERIC"),
+            ModelValueType::Boolean { field } => (field, "BOOLEAN"),
+            ModelValueType::Object  { field } => (field, "OBJECT"),
+            ModelValueType::Array   { field } => (field, "ARRAY"),
+        }
+    }
+
+    fn column_definition(field: &ModelValueType, optional: bool) -> String {
+        let (field, column_type) = SqliteDb::column_type(field);
+        match optional {
+            true => format!("{} {column_type}", quote(field)),
+            false => format!("{} {column_type} NOT NULL", quote(field))
+        }
+    }
+
+    /// Convert a json value into a value that can be bound as a query parameter.
+    fn to_sql(value: &serde_json::Value) -> SqlValue {
+        match value {
+            serde_json::Value::Null => SqlValue::Null,
+            serde_json::Value::Bool(b) => SqlValue::Integer(*b as i64),
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(i) => SqlValue::Integer(i),
+                None => SqlValue::Real(n.as_f64().unwrap_or_default())
+            },
+            serde_json::Value::String(s) => SqlValue::Text(s.clone()),
+            other => SqlValue::Text(other.to_string())
+        }
+    }
+
+    /// Convert a column value back into json, using the declared type of the column.
+    fn from_sql(value: SqlValue, column_type: &str) -> serde_json::Value {
+        match (value, column_type) {
+            (SqlValue::Null, _) => serde_json::Value::Null,
+            (SqlValue::Integer(i), "BOOLEAN") => (i != 0).into(),
+            (SqlValue::Integer(i), _) => i.into(),
+            (SqlValue::Real(f), _) => f.into(),
+            (SqlValue::Text(s), "OBJECT" | "ARRAY") => serde_json::from_str(&s).unwrap_or(serde_json::Value::Null),
+            (SqlValue::Text(s), _) => s.into(),
+            (SqlValue::Blob(_), _) => serde_json::Value::Null,
+        }
+    }
+
+    /// Compile `filter` to a parameterised WHERE clause.
+    /// Errors if it checks a key without a column, which SQLite would otherwise read as a string.
+    fn where_clause(table_id: &str, columns: &[(String, String)], filter: &DatabaseFilter<FilterValue>) -> Result<(String, Vec<SqlValue>), DatabaseError> {
+        SqliteDb::check_keys(table_id, columns, &filter.keys())?;
+        let mut params = Vec::new();
+        let clause = SqliteDb::compile_group(&filter.0, " AND ", "1", &mut params);
+        Ok((clause, params))
+    }
+
+    /// Errors if any of `keys` isn't a column of `table_id`.
+    fn check_keys(table_id: &str, columns: &[(String, String)], keys: &[&str]) -> Result<(), DatabaseError> {
+        super::check_keys(table_id, keys, |key| key != ID_COLUMN && columns.iter().any(|(name, _)| name == key))
+    }
+
+    /// Join each compiled partial in `group` with `separator`, or use `empty` if there are none.
+    fn compile_group(group: &[PartialFilter<FilterValue>], separator: &str, empty: &str, params: &mut Vec<SqlValue>) -> String {
+        if group.is_empty() {
+            return empty.into();
+        }
+
+        let clauses: Vec<String> = group.iter()
+            .map(|partial| SqliteDb::compile(partial, params))
+            .collect();
+        format!("({})", clauses.join(separator))
+    }
+
+    fn compile(partial: &PartialFilter<FilterValue>, params: &mut Vec<SqlValue>) -> String {
+        let mut compare = |key: &str, op: &str, value: &FilterValue| {
+            params.push(SqliteDb::to_sql(value));
+            format!("{} {op} ?", quote(key))
+        };
+
+        match partial {
+            PartialFilter::EQ { key, value } => compare(key, "=", value),
+            PartialFilter::NEQ { key, value } => compare(key, "!=", value),
+            PartialFilter::GT { key, value } => compare(key, ">", value),
+            PartialFilter::GTE { key, value } => compare(key, ">=", value),
+            PartialFilter::LT { key, value } => compare(key, "<", value),
+            PartialFilter::LTE { key, value } => compare(key, "<=", value),
+            // instr is used over LIKE as LIKE is case insensitive and treats % and _ as wildcards.
+            PartialFilter::CONTAINS { key, value } => {
+                params.push(SqliteDb::to_sql(value));
+                format!("instr({}, ?) > 0", quote(key))
+            },
+            PartialFilter::PREFIX { key, value } => {
+                params.push(SqliteDb::to_sql(value));
+                format!("instr({}, ?) = 1", quote(key))
+            },
+            PartialFilter::IN { key, values } | PartialFilter::NIN { key, values } => {
+                let negate = matches!(partial, PartialFilter::NIN { .. });
+                if values.is_empty() {
+                    // Nothing is in an empty list, though missing keys still never match.
+                    return match negate {
+                        true => format!("{} IS NOT NULL", quote(key)),
+                        false => "0".into()
+                    };
+                }
+                params.extend(values.iter().map(SqliteDb::to_sql));
+                format!(
+                    "{} {}IN ({})",
+                    quote(key),
+                    if negate { "NOT " } else { "" },
+                    vec!["?"; values.len()].join(", ")
+                )
+            },
+            PartialFilter::EXISTS { key, exists } => match exists {
+                true => format!("{} IS NOT NULL", quote(key)),
+                false => format!("{} IS NULL", quote(key))
+            },
+            PartialFilter::AND(group) => SqliteDb::compile_group(group, " AND ", "1", params),
+            PartialFilter::OR(group) => SqliteDb::compile_group(group, " OR ", "0", params),
+        }
+    }
+
+    /// Compile a grouping to an expression, along with the column type of its result.
+    /// Fields without a column are null, matching entries missing them in the other databases.
+    fn grouping_expression(grouping: &Grouping, columns: &[(String, String)]) -> (String, String) {
+        let column = |key: &str| columns.iter().find(|(name, _)| name == key);
+        match grouping {
+            Grouping::Field(key) => match column(key) {
+                Some((name, column_type)) => (quote(name), column_type.clone()),
+                None => ("NULL".into(), String::new())
+            },
+            // Floors to a multiple of width, as % truncates towards zero.
+            Grouping::Interval { key, width } => match column(key) {
+                Some((name, _)) if *width > 0 => {
+                    let name = quote(name);
+                    (format!("CASE WHEN typeof({name}) = 'integer' THEN {name} - (({name} % {width}) + {width}) % {width} END"), "INTEGER".into())
+                },
+                _ => ("NULL".into(), String::new())
+            },
+            Grouping::After { key, separator } => match column(key) {
+                Some((name, _)) => {
+                    let (name, separator) = (quote(name), literal(&SqlValue::Text(separator.to_string())));
+                    (format!("CASE WHEN typeof({name}) = 'text' THEN substr({name}, instr({name}, {separator}) + 1) END"), "TEXT".into())
+                },
+                None => ("NULL".into(), String::new())
+            }
+        }
+    }
+
+    /// Compile a metric to an aggregate expression, along with the column type of its result.
+    fn metric_expression(metric: &Metric, columns: &[(String, String)]) -> (String, String) {
+        let column = |key: &str| columns.iter().find(|(name, _)| name == key);
+        match metric {
+            Metric::Count => ("COUNT(*)".into(), "INTEGER".into()),
+            // Only numbers are totalled, SUM would otherwise convert text to numbers.
+            Metric::Sum(key) => match column(key) {
+                Some((name, _)) => {
+                    let name = quote(name);
+                    (format!("SUM(CASE WHEN typeof({name}) IN ('integer', 'real') THEN {name} END)"), String::new())
+                },
+                None => ("NULL".into(), String::new())
+            },
+            Metric::Min(key) | Metric::Max(key) => match column(key) {
+                Some((name, column_type)) => {
+                    let function = if matches!(metric, Metric::Min(_)) { "MIN" } else { "MAX" };
+                    (format!("{function}({})", quote(name)), column_type.clone())
+                },
+                None => ("NULL".into(), String::new())
+            }
+        }
+    }
+
+    /// Declared type of each column in `table_id`, in column order.
+    fn columns(conn: &Connection, table_id: &str) -> Result<Vec<(String, String)>, DatabaseError> {
+        let mut statement = conn.prepare(&format!("PRAGMA table_info({})", quote(table_id)))
+            .map_err(|e| DatabaseError::Backend(e.to_string()))?;
+        let columns = statement.query_map([], |row| Ok((row.get::<_, String>(1)?, row.get::<_, String>(2)?)))
+            .map_err(|e| DatabaseError::Backend(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DatabaseError::Backend(e.to_string()))?;
+
+        if columns.is_empty() {
+            return Err(DatabaseError::TableNotFound(table_id.into()));
+        }
+        Ok(columns)
+    }
+
+    /// Convert a row selected with `SELECT *` to a json entry.
+    fn to_entry(row: &rusqlite::Row, columns: &[(String, String)]) -> rusqlite::Result<serde_json::Value> {
+        let mut entry = serde_json::Map::new();
+        for (idx, (name, column_type)) in columns.iter().enumerate() {
+            let value = SqliteDb::from_sql(row.get::<_, SqlValue>(idx)?, column_type);
+            // Missing fields are left out, matching entries in the other databases.
+            if !value.is_null() && name != ID_COLUMN {
+                entry.insert(name.clone(), value);
+            }
+        }
+        Ok(serde_json::Value::Object(entry))
+    }
+
+    /// Every schema stored in [`SCHEMAS_TABLE`].
+    fn load_schemas(conn: &Connection) -> Result<HashMap<String, ModelSchema>, DatabaseError> {
+        let mut statement = conn.prepare(&format!("SELECT table_id, schema FROM {}", quote(SCHEMAS_TABLE)))
+            .map_err(|e| DatabaseError::Backend(format!("Failed to read schemas: {e}")))?;
+        let rows = statement.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| DatabaseError::Backend(format!("Failed to read schemas: {e}")))?;
+
+        rows.into_iter()
+            .map(|(table_id, raw)| match serde_json::from_str::<ModelSchema>(&raw) {
+                Ok(schema) => Ok((table_id, schema)),
+                Err(e) => Err(DatabaseError::Parse(format!("Failed to parse schema of table {table_id}: {e}")))
+            })
+            .collect()
+    }
+
+    fn ensure_table(conn: &Connection, table_id: &str) -> Result<(), DatabaseError> {
+        SqliteDb::columns(conn, table_id).map(|_| ())
+    }
+
+    /// Id of the first entry in `table_id` matching `filter`.
+    fn find_id(conn: &Connection, table_id: &str, columns: &[(String, String)], filter: &DatabaseFilter<FilterValue>) -> Result<EntryId, DatabaseError> {
+        let (clause, params) = SqliteDb::where_clause(table_id, columns, filter)?;
+        let sql = format!("SELECT rowid FROM {} WHERE {clause} LIMIT 1", quote(table_id));
+
+        match conn.query_row(&sql, params_from_iter(params), |row| row.get::<_, i64>(0)) {
+            Ok(rowid) => Ok(rowid as EntryId),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Err(DatabaseError::NotFound),
+            Err(e) => Err(DatabaseError::Backend(format!("Failed to query {table_id}: {e}")))
+        }
+    }
+
+    /// Id and entry of every entry in `table_id` matching `filter`.
+    fn matching(conn: &Connection, table_id: &str, columns: &[(String, String)], filter: &DatabaseFilter<FilterValue>) -> Result<Vec<(EntryId, serde_json::Value)>, DatabaseError> {
+        let (clause, params) = SqliteDb::where_clause(table_id, columns, filter)?;
+        // The rowid is selected last so the other columns line up with `columns`.
+        let sql = format!("SELECT *, rowid FROM {} WHERE {clause} ORDER BY rowid", quote(table_id));
+        conn.prepare(&sql)
+            .and_then(|mut statement| {
+                statement.query_map(params_from_iter(params), |row| {
+                    Ok((row.get::<_, i64>(columns.len())? as EntryId, SqliteDb::to_entry(row, columns)?))
+                })?.collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|e| DatabaseError::Backend(format!("Failed to query {table_id}: {e}")))
+    }
+
+    /// Insert `fields` as a new entry, under `id` if one is given.
+    fn insert_entry(conn: &Connection, table_id: &str, id: Option<EntryId>, fields: &serde_json::Map<String, serde_json::Value>) -> Result<EntryId, DatabaseError> {
+        let names: Vec<String> = id.map(|_| "rowid".to_string()).into_iter().chain(fields.keys().map(|key| quote(key))).collect();
+        let sql = if names.is_empty() {
+            format!("INSERT INTO {} DEFAULT VALUES", quote(table_id))
+        } else {
+            format!("INSERT INTO {} ({}) VALUES ({})", quote(table_id), names.join(", "), vec!["?"; names.len()].join(", "))
+        };
+        let params = id.map(|id| SqlValue::Integer(id as i64)).into_iter().chain(fields.values().map(SqliteDb::to_sql));
+
+        // Cached, as bulk inserts will usually prepare the same statement for every entry.
+        conn.prepare_cached(&sql)
+            .and_then(|mut statement| statement.execute(params_from_iter(params)))
+            .map_err(|e| SqliteDb::write_error(e, format!("Failed to insert into {table_id}")))?;
+        Ok(conn.last_insert_rowid() as EntryId)
+    }
+
+    /// `SET` clause of an update to `fields`, along with its parameters.
+    /// If `revised` the entries are also moved to their next [`REVISION`], whatever revision `fields` sets.
+    fn assignments(fields: &serde_json::Map<String, serde_json::Value>, revised: bool) -> (String, Vec<SqlValue>) {
+        let (mut assignments, params): (Vec<_>, Vec<_>) = fields.iter()
+            .filter(|(key, _)| !revised || key.as_str() != REVISION)
+            .map(|(key, value)| (format!("{} = ?", quote(key)), SqliteDb::to_sql(value)))
+            .unzip();
+        if revised {
+            assignments.push(format!("{0} = COALESCE({0}, 0) + 1", quote(REVISION)));
+        }
+        (assignments.join(", "), params)
+    }
+
+    fn entry(conn: &Connection, table_id: &str, columns: &[(String, String)], id: EntryId) -> Result<serde_json::Value, DatabaseError> {
+        let sql = format!("SELECT * FROM {} WHERE rowid = ?", quote(table_id));
+        match conn.query_row(&sql, [id as i64], |row| SqliteDb::to_entry(row, columns)) {
+            Ok(entry) => Ok(entry),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Err(DatabaseError::NotFound),
+            Err(e) => Err(DatabaseError::Backend(format!("Failed to query {table_id}: {e}")))
+        }
+    }
+
+    /// Index names aren't scoped to their table, so the table is included in the name.
+    fn index_name(table_id: &str, field: &str) -> String {
+        quote(&format!("{table_id}_{field}_idx"))
+    }
+
+    fn create_index(conn: &Connection, table_id: &str, field: &str, unique: bool) -> rusqlite::Result<()> {
+        let sql = format!(
+            "CREATE {}INDEX IF NOT EXISTS {} ON {} ({})",
+            if unique { "UNIQUE " } else { "" },
+            SqliteDb::index_name(table_id, field),
+            quote(table_id),
+            quote(field)
+        );
+        conn.execute(&sql, []).map(|_| ())
+    }
+
+    fn drop_index(conn: &Connection, table_id: &str, field: &str) -> rusqlite::Result<()> {
+        conn.execute(&format!("DROP INDEX IF EXISTS {}", SqliteDb::index_name(table_id, field)), []).map(|_| ())
+    }
+
+    /// Whether the index on `field` is unique, or `None` if the field isn't indexed.
+    fn index_unique(conn: &Connection, table_id: &str, field: &str) -> rusqlite::Result<Option<bool>> {
+        let result = conn.query_row(
+            "SELECT \"unique\" FROM pragma_index_list(?) WHERE name = ?",
+            [table_id.to_string(), format!("{table_id}_{field}_idx")],
+            |row| row.get::<_, bool>(0)
+        );
+        match result {
+            Ok(unique) => Ok(Some(unique)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Describe a failed write, unique constraint failures are reported as conflicts.
+    fn write_error(e: rusqlite::Error, description: String) -> DatabaseError {
+        if let rusqlite::Error::SqliteFailure(_, Some(message)) = &e {
+            // Formatted as "UNIQUE constraint failed: <table>.<column>"
+            let column = message.strip_prefix("UNIQUE constraint failed: ")
+                .and_then(|columns| columns.split_once('.'));
+            if let Some((_, column)) = column {
+                return DatabaseError::Conflict { field: column.into() };
+            }
+        }
+        DatabaseError::Backend(format!("{description}: {e}"))
+    }
+}
+
+/// Format a value as an SQL literal, for statements that don't accept parameters.
+fn literal(value: &SqlValue) -> String {
+    match value {
+        SqlValue::Null => "NULL".into(),
+        SqlValue::Integer(i) => i.to_string(),
+        SqlValue::Real(f) => f.to_string(),
+        SqlValue::Text(s) => format!("'{}'", s.replace('\'', "''")),
+        SqlValue::Blob(_) => "NULL".into()
+    }
+}
+
+/// Quote an identifier so it can be safely used as a table or column name.
+fn quote(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// Writes made by [`Transactional::apply`], each returns the changes it made to entries.
+impl SqliteConnection {
+    fn apply(&self, table_id: &str, operation: Operation<'_>) -> Result<Vec<Change>, DatabaseError> {
+        match operation {
+            Operation::CreateTable(schema) => self.create(table_id, schema),
+            Operation::AlterTable(change) => self.alter(table_id, change),
+            Operation::DropTable => self.remove(table_id),
+            Operation::Insert(data) => self.insert_one(table_id, data),
+            Operation::InsertMany(data) => self.insert_batch(table_id, &data.iter().map(|entry| (None, entry)).collect::<Vec<_>>()),
+            Operation::Restore(entries) => self.insert_batch(table_id, &entries.iter().map(|(id, entry)| (Some(*id), entry)).collect::<Vec<_>>()),
+            Operation::Update(filter, data) => self.update_one(table_id, filter, data),
+            Operation::UpdateMany(filter, data) => self.update_batch(table_id, filter, data),
+            Operation::Delete(filter) => self.delete_one(table_id, filter),
+            Operation::DeleteMany(filter) => self.delete_batch(table_id, filter)
+        }
+    }
+
+    /// Whether the schema registered for `table_id` has a [`REVISION`], which every update moves on.
+    fn revised(&self, table_id: &str) -> bool {
+        self.schemas.read().unwrap().get(table_id).is_some_and(|schema| schema.field(REVISION).is_some())
+    }
+
+    /// Validate `data` against the schema registered for `table_id`.
+    fn validate(&self, table_id: &str, data: &serde_json::Value, partial: bool) -> Result<(), DatabaseError> {
+        let schemas = self.schemas.read().unwrap();
+        let Some(schema) = schemas.get(table_id) else {
+            // Missing tables are reported as such, rather than as missing a schema.
+            SqliteDb::columns(&self.conn.lock().unwrap(), table_id)?;
+            return Err(DatabaseError::SchemaViolation(format!("Table {table_id} has no schema, it must be created before it can be written to.")))
+        };
+
+        schema.validate(data, partial).map_err(|e| DatabaseError::SchemaViolation(match partial {
+            true => format!("Invalid update for table {table_id}: {e}"),
+            false => format!("Invalid entry for table {table_id}: {e}")
+        }))
+    }
+
+    /// Store the schema of `table_id`, or remove it if there is none.
+    fn save_schema(&self, conn: &Connection, table_id: &str, schema: Option<&ModelSchema>) -> Result<(), DatabaseError> {
+        let result = match schema {
+            Some(schema) => {
+                let raw = serde_json::to_string(schema)
+                    .map_err(|e| DatabaseError::Backend(format!("Failed to serialize schema of table {table_id}: {e}")))?;
+                conn.execute(&format!("INSERT OR REPLACE INTO {} (table_id, schema) VALUES (?, ?)", quote(SCHEMAS_TABLE)), [table_id, &raw])
+            },
+            None => conn.execute(&format!("DELETE FROM {} WHERE table_id = ?", quote(SCHEMAS_TABLE)), [table_id])
+        };
+        result.map_err(|e| DatabaseError::Backend(format!("Failed to store schema of table {table_id}: {e}")))?;
+
+        let mut schemas = self.schemas.write().unwrap();
+        match schema {
+            Some(schema) => schemas.insert(table_id.to_string(), schema.clone()),
+            None => schemas.remove(table_id)
+        };
+        Ok(())
+    }
+
+    // Existing tables are kept, so running setup against an existing database won't wipe it.
+    fn create(&self, table_id: &str, schema: &ModelSchema) -> Result<Vec<Change>, DatabaseError> {
+        let fields: Vec<String> = schema.fields.iter()
+            .map(|field| SqliteDb::column_definition(field, schema.is_optional(field.field())))
+            .collect();
+
+        if fields.is_empty() {
+            return Err(DatabaseError::InvalidArgument(format!("Table {table_id} must have at least one field.")));
+        }
+
+        // AUTOINCREMENT stops ids of deleted entries from being given out again.
+        let columns = [format!("{} INTEGER PRIMARY KEY AUTOINCREMENT", quote(ID_COLUMN))].into_iter().chain(fields);
+        let conn = self.conn.lock().unwrap();
+        conn.execute(&format!("CREATE TABLE IF NOT EXISTS {} ({})", quote(table_id), columns.collect::<Vec<_>>().join(", ")), [])
+            .map_err(|e| DatabaseError::Backend(format!("Failed to create table {table_id}: {e}")))?;
+        for field in schema.indexes() {
+            SqliteDb::create_index(&conn, table_id, field, schema.is_unique(field))
+                .map_err(|e| SqliteDb::write_error(e, format!("Failed to create table {table_id}")))?;
+        }
+
+        self.save_schema(&conn, table_id, Some(schema))?;
+        Ok(Vec::new())
+    }
+
+    fn alter(&self, table_id: &str, change: &TableChange) -> Result<Vec<Change>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let columns = SqliteDb::columns(&conn, table_id)?;
+
+        // Without a schema the change can't be checked, nor can the table's indexes be kept in step with it.
+        let Some(mut schema) = self.schemas.read().unwrap().get(table_id).cloned() else {
+            return Err(DatabaseError::SchemaViolation(format!("Table {table_id} has no schema, it must be created before it can be altered.")))
+        };
+        change.apply_to_schema(&mut schema)
+            .map_err(|e| DatabaseError::SchemaViolation(format!("Failed to alter table {table_id}: {e}")))?;
+
+        let table = quote(table_id);
+        let result = match change {
+            TableChange::AddField { field, default } => {
+                let mut sql = format!("ALTER TABLE {table} ADD COLUMN {}", SqliteDb::column_definition(field, default.is_null()));
+                // Defaults can't be bound as parameters within ALTER TABLE.
+                if !default.is_null() {
+                    sql += &format!(" DEFAULT {}", literal(&SqliteDb::to_sql(default)));
+                }
+                conn.execute(&sql, []).map(|_| ())
+            },
+            TableChange::RenameField { from, to } => (|| {
+                // Indexes are named after their field, so are recreated under the new name.
+                let unique = SqliteDb::index_unique(&conn, table_id, from)?;
+                if unique.is_some() {
+                    SqliteDb::drop_index(&conn, table_id, from)?;
+                }
+                conn.execute(&format!("ALTER TABLE {table} RENAME COLUMN {} TO {}", quote(from), quote(to)), [])?;
+                match unique {
+                    Some(unique) => SqliteDb::create_index(&conn, table_id, to, unique),
+                    None => Ok(())
+                }
+            })(),
+            TableChange::DropField { field } => {
+                // Indexed columns can't be dropped.
+                SqliteDb::drop_index(&conn, table_id, field)
+                    .and_then(|_| conn.execute(&format!("ALTER TABLE {table} DROP COLUMN {}", quote(field)), []).map(|_| ()))
+            },
+            TableChange::Backfill { field, with } => (|| {
+                // The rowid is selected last so the other columns line up with `columns`.
+                let mut statement = conn.prepare(&format!("SELECT *, rowid FROM {table}"))?;
+                let rows = statement.query_map([], |row| {
+                    Ok((row.get::<_, i64>(columns.len())?, SqliteDb::to_entry(row, &columns)?))
+                })?.collect::<Result<Vec<_>, _>>()?;
+
+                let mut update = conn.prepare(&format!("UPDATE {table} SET {} = ? WHERE rowid = ?", quote(field)))?;
+                for (rowid, entry) in rows {
+                    update.execute(rusqlite::params![SqliteDb::to_sql(&with(&entry)), rowid])?;
+                }
+                Ok(())
+            })(),
+            TableChange::AddIndex { field, unique } => SqliteDb::create_index(&conn, table_id, field, *unique),
+            TableChange::DropIndex { field } => SqliteDb::drop_index(&conn, table_id, field)
+        };
+        result.map_err(|e| SqliteDb::write_error(e, format!("Failed to alter table {table_id}")))?;
+
+        self.save_schema(&conn, table_id, Some(&schema))?;
+        Ok(Vec::new())
+    }
+
+    fn remove(&self, table_id: &str) -> Result<Vec<Change>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        SqliteDb::ensure_table(&conn, table_id)?;
+        conn.execute(&format!("DROP TABLE {}", quote(table_id)), [])
+            .map_err(|e| DatabaseError::Backend(format!("Failed to drop table {table_id}: {e}")))?;
+
+        self.save_schema(&conn, table_id, None)?;
+        Ok(Vec::new())
+    }
+
+    fn insert_one(&self, table_id: &str, data: &serde_json::Value) -> Result<Vec<Change>, DatabaseError> {
+        let Some(fields) = data.as_object() else {
+            return Err(DatabaseError::InvalidArgument("Inserted data must be an object.".into()))
+        };
+        self.validate(table_id, data, false)?;
+
+        let conn = self.conn.lock().unwrap();
+        let columns = SqliteDb::columns(&conn, table_id)?;
+
+        let id = SqliteDb::insert_entry(&conn, table_id, None, fields)?;
+        let new = SqliteDb::entry(&conn, table_id, &columns, id)?;
+        Ok(vec![Change { table_id: table_id.into(), id, event: ChangeEvent::Insert { new } }])
+    }
+
+    fn update_one(&self, table_id: &str, filter: &DatabaseFilter<FilterValue>, data: &serde_json::Value) -> Result<Vec<Change>, DatabaseError> {
+        let Some(fields) = data.as_object() else {
+            return Err(DatabaseError::InvalidArgument("Update data must be an object.".into()))
+        };
+        self.validate(table_id, data, true)?;
+        let (assignments, params) = SqliteDb::assignments(fields, self.revised(table_id));
+
+        let conn = self.conn.lock().unwrap();
+        let columns = SqliteDb::columns(&conn, table_id)?;
+        let id = SqliteDb::find_id(&conn, table_id, &columns, filter)?;
+        let old = SqliteDb::entry(&conn, table_id, &columns, id)?;
+        if fields.is_empty() {
+            return Ok(vec![Change { table_id: table_id.into(), id, event: ChangeEvent::Update { old: old.clone(), new: old } }]);
+        }
+
+        let sql = format!("UPDATE {} SET {assignments} WHERE rowid = ?", quote(table_id));
+        let params = params.into_iter().chain([SqlValue::Integer(id as i64)]);
+        conn.execute(&sql, params_from_iter(params))
+            .map_err(|e| SqliteDb::write_error(e, format!("Failed to update {table_id}")))?;
+
+        let new = SqliteDb::entry(&conn, table_id, &columns, id)?;
+        Ok(vec![Change { table_id: table_id.into(), id, event: ChangeEvent::Update { old, new } }])
+    }
+
+    fn delete_one(&self, table_id: &str, filter: &DatabaseFilter<FilterValue>) -> Result<Vec<Change>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let columns = SqliteDb::columns(&conn, table_id)?;
+        let id = SqliteDb::find_id(&conn, table_id, &columns, filter)?;
+        let old = SqliteDb::entry(&conn, table_id, &columns, id)?;
+
+        conn.execute(&format!("DELETE FROM {} WHERE rowid = ?", quote(table_id)), [id as i64])
+            .map_err(|e| DatabaseError::Backend(format!("Failed to delete from {table_id}: {e}")))?;
+        Ok(vec![Change { table_id: table_id.into(), id, event: ChangeEvent::Delete { old } }])
+    }
+
+    /// Insert every entry in `data`, under its id if one is given.
+    fn insert_batch(&self, table_id: &str, data: &[(Option<EntryId>, &serde_json::Value)]) -> Result<Vec<Change>, DatabaseError> {
+        let entries = data.iter()
+            .map(|(id, entry)| Ok((*id, entry.as_object().ok_or_else(|| DatabaseError::InvalidArgument("Inserted data must be an object.".into()))?)))
+            .collect::<Result<Vec<_>, DatabaseError>>()?;
+        for (_, entry) in data {
+            self.validate(table_id, entry, false)?;
+        }
+
+        let conn = self.conn.lock().unwrap();
+        let columns = SqliteDb::columns(&conn, table_id)?;
+
+        // A savepoint rather than a transaction, so this also works within `Database::transaction`.
+        conn.execute_batch("SAVEPOINT insert_many")
+            .map_err(|e| DatabaseError::Backend(format!("Failed to insert into {table_id}: {e}")))?;
+        let result = entries.into_iter().map(|(id, fields)| SqliteDb::insert_entry(&conn, table_id, id, fields)).collect::<Result<Vec<_>, _>>();
+        let end = match result {
+            Ok(_) => "RELEASE insert_many",
+            Err(_) => "ROLLBACK TO insert_many; RELEASE insert_many"
+        };
+        conn.execute_batch(end)
+            .map_err(|e| DatabaseError::Backend(format!("Failed to insert into {table_id}: {e}")))?;
+
+        result?.into_iter()
+            .map(|id| Ok(Change { table_id: table_id.into(), id, event: ChangeEvent::Insert { new: SqliteDb::entry(&conn, table_id, &columns, id)? } }))
+            .collect()
+    }
+
+    fn update_batch(&self, table_id: &str, filter: &DatabaseFilter<FilterValue>, data: &serde_json::Value) -> Result<Vec<Change>, DatabaseError> {
+        let Some(fields) = data.as_object() else {
+            return Err(DatabaseError::InvalidArgument("Update data must be an object.".into()))
+        };
+        self.validate(table_id, data, true)?;
+        if fields.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (assignments, params) = SqliteDb::assignments(fields, self.revised(table_id));
+
+        let conn = self.conn.lock().unwrap();
+        let columns = SqliteDb::columns(&conn, table_id)?;
+        let old = SqliteDb::matching(&conn, table_id, &columns, filter)?;
+
+        let (clause, filter_params) = SqliteDb::where_clause(table_id, &columns, filter)?;
+        let sql = format!("UPDATE {} SET {assignments} WHERE {clause}", quote(table_id));
+        let params = params.into_iter().chain(filter_params);
+        // A single statement, so a constraint failing for any entry leaves every entry untouched.
+        conn.execute(&sql, params_from_iter(params))
+            .map_err(|e| SqliteDb::write_error(e, format!("Failed to update {table_id}")))?;
+
+        old.into_iter()
+            .map(|(id, old)| Ok(Change { table_id: table_id.into(), id, event: ChangeEvent::Update { old, new: SqliteDb::entry(&conn, table_id, &columns, id)? } }))
+            .collect()
+    }
+
+    fn delete_batch(&self, table_id: &str, filter: &DatabaseFilter<FilterValue>) -> Result<Vec<Change>, DatabaseError> {
+        let conn = self.conn.lock().unwrap();
+        let columns = SqliteDb::columns(&conn, table_id)?;
+        let old = SqliteDb::matching(&conn, table_id, &columns, filter)?;
+
+        let (clause, params) = SqliteDb::where_clause(table_id, &columns, filter)?;
+        conn.execute(&format!("DELETE FROM {} WHERE {clause}", quote(table_id)), params_from_iter(params))
+            .map_err(|e| DatabaseError::Backend(format!("Failed to delete from {table_id}: {e}")))?;
+
+        Ok(old.into_iter().map(|(id, old)| Change { table_id: table_id.into(), id, event: ChangeEvent::Delete { old } }).collect())
+    }
+}
+
+#[async_trait]
+impl Database for SqliteDb {
+
+    async fn init(location: &str) -> Result<Arc<SqliteDb>, DatabaseError> {
+        if location.is_empty() {
+            return Err(DatabaseError::InvalidArgument("A database location is required for production databases.".into()));
+        }
+
+        let location = location.to_string();
+        let connection = task::spawn_blocking(move || {
+            let conn = Connection::open(&location)
+                .map_err(|e| DatabaseError::Backend(format!("Failed to open database {location}: {e}")))?;
+            conn.pragma_update(None, "journal_mode", "WAL")
+                .map_err(|e| DatabaseError::Backend(format!("Failed to enable WAL for {location}: {e}")))?;
+            conn.execute(&format!("CREATE TABLE IF NOT EXISTS {} (table_id TEXT PRIMARY KEY, schema TEXT NOT NULL)", quote(SCHEMAS_TABLE)), [])
+                .map_err(|e| DatabaseError::Backend(format!("Failed to create {SCHEMAS_TABLE} for {location}: {e}")))?;
+            let schemas = SqliteDb::load_schemas(&conn)?;
+            Ok::<_, DatabaseError>(SqliteConnection { conn: Mutex::new(conn), schemas: RwLock::new(schemas) })
+        }).await?;
+
+        Ok(Arc::new(SqliteDb {
+            connection: Arc::new(connection),
+            transaction: async_std::sync::Mutex::new(()),
+            subscriptions: Subscriptions::default()
+        }))
+    }
+
+    async fn write(&self, table_id: &str, operation: Operation<'_>) -> Result<Vec<Change>, DatabaseError> {
+        transaction::write(self, table_id, operation).await
+    }
+
+    async fn get(&self, table_id: &str,  filter: DatabaseFilter<FilterValue>) -> Result<serde_json::Value, DatabaseError> {
+        let id = self.find(table_id, filter).await?;
+        self.get_by_id(table_id, id).await
+    }
+
+    async fn find(&self, table_id: &str, filter: DatabaseFilter<FilterValue>) -> Result<EntryId, DatabaseError> {
+        let table_id = table_id.to_string();
+        self.blocking(move |connection| {
+            let conn = connection.conn.lock().unwrap();
+            let columns = SqliteDb::columns(&conn, &table_id)?;
+            SqliteDb::find_id(&conn, &table_id, &columns, &filter)
+        }).await
+    }
+
+    async fn find_entries(&self, table_id: &str, filter: DatabaseFilter<FilterValue>) -> Result<Vec<(EntryId, serde_json::Value)>, DatabaseError> {
+        let table_id = table_id.to_string();
+        self.blocking(move |connection| {
+            let conn = connection.conn.lock().unwrap();
+            let columns = SqliteDb::columns(&conn, &table_id)?;
+            SqliteDb::matching(&conn, &table_id, &columns, &filter)
+        }).await
+    }
+
+    async fn get_by_id(&self, table_id: &str, id: EntryId) -> Result<serde_json::Value, DatabaseError> {
+        let table_id = table_id.to_string();
+        self.blocking(move |connection| {
+            let conn = connection.conn.lock().unwrap();
+            let columns = SqliteDb::columns(&conn, &table_id)?;
+            SqliteDb::entry(&conn, &table_id, &columns, id)
+        }).await
+    }
+
+    async fn query(&self, table_id: &str, query: DatabaseQuery<FilterValue>) -> Result<QueryResult, DatabaseError> {
+        let table_id = table_id.to_string();
+        self.blocking(move |connection| SqliteDb::query_table(&connection.conn.lock().unwrap(), &table_id, &query)).await
+    }
+
+    async fn aggregate(&self, table_id: &str, aggregation: Aggregation<FilterValue>) -> Result<Vec<AggregateGroup>, DatabaseError> {
+        let table_id = table_id.to_string();
+        self.blocking(move |connection| SqliteDb::aggregate_table(&connection.conn.lock().unwrap(), &table_id, &aggregation)).await
+    }
+
+    fn subscribe(&self, table_id: &str) -> Receiver<Change> {
+        self.subscriptions.subscribe(table_id)
+    }
+
+    async fn transaction(&self, operation: TransactionFn) -> Result<(), DatabaseError> {
+        transaction::run(self, operation).await
+    }
+}
+
+/// Reads which are more than a single lookup.
+impl SqliteDb {
+    fn query_table(conn: &Connection, table_id: &str, query: &DatabaseQuery<FilterValue>) -> Result<QueryResult, DatabaseError> {
+        let columns = SqliteDb::columns(conn, table_id)?;
+        SqliteDb::check_keys(table_id, &columns, &query.keys())?;
+        let (clause, params) = SqliteDb::where_clause(table_id, &columns, &query.filter)?;
+
+        let total = conn.query_row(
+            &format!("SELECT COUNT(*) FROM {} WHERE {clause}", quote(table_id)),
+            params_from_iter(params.iter()),
+            |row| row.get::<_, i64>(0)
+        ).map_err(|e| DatabaseError::Backend(format!("Failed to query {table_id}: {e}")))? as usize;
+
+        // Order by rowid last so results are in insertion order when equal, matching the other databases.
+        let order: String = query.order.iter()
+            .map(|(key, order)| match order {
+                SortOrder::Ascending => format!("{} ASC, ", quote(key)),
+                SortOrder::Descending => format!("{} DESC, ", quote(key)),
+            })
+            .collect();
+        // A negative limit is treated as no limit by SQLite.
+        let limit = query.limit.map_or(-1, |limit| limit as i64);
+
+        let sql = format!("SELECT * FROM {} WHERE {clause} ORDER BY {order}rowid LIMIT {limit} OFFSET {}", quote(table_id), query.offset);
+        let mut statement = conn.prepare(&sql).map_err(|e| DatabaseError::Backend(format!("Failed to query {table_id}: {e}")))?;
+        let entries = statement.query_map(params_from_iter(params.iter()), |row| SqliteDb::to_entry(row, &columns))
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| DatabaseError::Backend(format!("Failed to query {table_id}: {e}")))?;
+
+        Ok(QueryResult { entries, total })
+    }
+
+    fn aggregate_table(conn: &Connection, table_id: &str, aggregation: &Aggregation<FilterValue>) -> Result<Vec<AggregateGroup>, DatabaseError> {
+        let columns = SqliteDb::columns(conn, table_id)?;
+        let (clause, params) = SqliteDb::where_clause(table_id, &columns, &aggregation.filter)?;
+
+        let groups: Vec<_> = aggregation.group_by.iter().map(|grouping| SqliteDb::grouping_expression(grouping, &columns)).collect();
+        let metrics: Vec<_> = aggregation.metrics.iter().map(|metric| SqliteDb::metric_expression(metric, &columns)).collect();
+        let selected: Vec<&str> = groups.iter().chain(&metrics).map(|(expression, _)| expression.as_str()).collect();
+        // Without any metrics or groupings there is nothing to select, but a row is still needed for the single group.
+        let selected = if selected.is_empty() { "1".into() } else { selected.join(", ") };
+
+        // Groups are referred to by their position in the selected columns.
+        let mut sql = format!("SELECT {selected} FROM {} WHERE {clause}", quote(table_id));
+        if !groups.is_empty() {
+            let positions = (1..=groups.len()).map(|pos| pos.to_string()).collect::<Vec<_>>().join(", ");
+            sql.push_str(&format!(" GROUP BY {positions} ORDER BY {positions}"));
+        }
+
+        let mut statement = conn.prepare(&sql).map_err(|e| DatabaseError::Backend(format!("Failed to aggregate {table_id}: {e}")))?;
+        let rows = statement.query_map(params_from_iter(params.iter()), |row| {
+            let mut values = Vec::with_capacity(groups.len() + metrics.len());
+            for (idx, (_, column_type)) in groups.iter().chain(&metrics).enumerate() {
+                values.push(SqliteDb::from_sql(row.get::<_, SqlValue>(idx)?, column_type));
+            }
+            let metrics = values.split_off(groups.len());
+            Ok(AggregateGroup { key: values, values: metrics })
+        });
+        rows.and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| DatabaseError::Backend(format!("Failed to aggregate {table_id}: {e}")))
+    }
+}
+
+#[async_trait]
+impl Transactional for SqliteDb {
+    // SQLite rolls back the transaction itself.
+    type Journal = ();
+
+    fn lock(&self) -> &async_std::sync::Mutex<()> {
+        &self.transaction
+    }
+
+    fn subscriptions(&self) -> &Subscriptions {
+        &self.subscriptions
+    }
+
+    async fn begin(&self) -> Result<(), DatabaseError> {
+        self.blocking(|connection| {
+            connection.conn.lock().unwrap().execute_batch("BEGIN IMMEDIATE")
+                .map_err(|e| DatabaseError::Backend(format!("Failed to begin transaction: {e}")))
+        }).await
+    }
+
+    async fn apply(&self, table_id: &str, operation: Operation<'_>, _journal: Option<&mut ()>) -> Result<Vec<Change>, DatabaseError> {
+        let (table_id, operation) = (table_id.to_string(), operation.into_owned());
+        self.blocking(move |connection| connection.apply(&table_id, operation.as_operation())).await
+    }
+
+    async fn commit(&self, _journal: ()) -> Result<(), DatabaseError> {
+        self.blocking(|connection| {
+            let conn = connection.conn.lock().unwrap();
+            if let Err(e) = conn.execute_batch("COMMIT") {
+                // Ensure the connection isn't left inside the transaction.
+                let _ = conn.execute_batch("ROLLBACK");
+                return Err(DatabaseError::Backend(format!("Failed to commit transaction: {e}")));
+            }
+            Ok(())
+        }).await
+    }
+
+    async fn rollback(&self, _journal: ()) -> Result<(), DatabaseError> {
+        self.blocking(|connection| {
+            let conn = connection.conn.lock().unwrap();
+            conn.execute_batch("ROLLBACK")
+                .map_err(|e| DatabaseError::Backend(format!("Failed to roll back transaction: {e}")))?;
+            // Schemas changed within the transaction were rolled back along with it.
+            *connection.schemas.write().unwrap() = SqliteDb::load_schemas(&conn)?;
+            Ok(())
+        }).await
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use serde_json::json;
+
+    use crate::core::{database::{self, changes::{Change, ChangeEvent}, dump::{self, EntryDump}, error::DatabaseError, migrations, volatile::VolatileDb, repository::Repository, Database, DatabaseModel, DatabaseQuery, SortOrder, TableChange}, models::{ModelSchema, ModelValueType, account::Account, session::Session}};
+
+    use super::SqliteDb;
+
+    database::conformance::conformance!(SqliteDb::init(":memory:").await.unwrap());
+
+    #[tokio::test]
+    async fn typed_columns() {
+        let db = SqliteDb::init(":memory:").await.unwrap();
+        db.create_table("sessions", &Session::schema()).await.unwrap();
+        db.create_table("profiles", &ModelSchema {
+            fields: vec![
+                ModelValueType::String { field: "uid" },
+                ModelValueType::Object { field: "settings" },
+                ModelValueType::Array  { field: "tags" },
+            ],
+            optional: vec!["tags"],
+            unique: Vec::new(),
+            indexed: vec!["uid"]
+        }).await.unwrap();
+
+        let session = json!({ "session_id": "abc", "uid": "123", "created": 1678000000000i64, "valid": true });
+        db.insert("sessions", &session).await.unwrap();
+        db.insert("sessions", &json!({ "session_id": "def", "uid": "456", "created": 1, "valid": false })).await.unwrap();
+
+        let filter = db.filter().eq("valid", true.into()).build();
+        assert_eq!(db.get("sessions", filter).await.unwrap(), session);
+
+        let filter = db.filter().neq("session_id", "abc".into()).build();
+        db.update("sessions", filter.clone(), &json!({ "valid": true })).await.unwrap();
+        assert_eq!(db.get("sessions", filter.clone()).await.unwrap()["valid"], json!(true));
+
+        db.delete("sessions", filter.clone()).await.unwrap();
+        assert!(db.find("sessions", filter).await.is_err());
+
+        let profile = json!({ "uid": "123", "settings": { "theme": "dark" }, "tags": ["a", "b"] });
+        let id = db.insert("profiles", &profile).await.unwrap();
+        assert_eq!(db.get_by_id("profiles", id).await.unwrap(), profile);
+
+        let profile = json!({ "uid": "456", "settings": {} });
+        let id = db.insert("profiles", &profile).await.unwrap();
+        assert_eq!(db.get_by_id("profiles", id).await.unwrap(), profile);
+        assert!(db.insert("profiles", &json!({ "uid": "789", "settings": [] })).await.is_err());
+        assert!(db.insert("profiles", &json!({ "uid": "789", "settings": {}, "extra": 1 })).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn ids() {
+        let db = SqliteDb::init(":memory:").await.unwrap();
+        db.create_table("sessions", &Session::schema()).await.unwrap();
+        let mut ids = Vec::new();
+        for session_id in ["a", "b", "c"] {
+            ids.push(db.insert("sessions", &json!({ "session_id": session_id, "uid": "123", "created": 0, "valid": true })).await.unwrap());
+        }
+        assert_eq!(db.find("sessions", db.filter().eq("session_id", "b".into()).build()).await, Ok(ids[1]));
+
+        // The id column isn't part of the entry, and ids of deleted entries are never given out again.
+        db.delete("sessions", db.filter().eq("session_id", "a".into()).build()).await.unwrap();
+        db.delete("sessions", db.filter().eq("session_id", "c".into()).build()).await.unwrap();
+        assert_eq!(db.get_by_id("sessions", ids[0]).await, Err(DatabaseError::NotFound));
+        assert_eq!(db.get_by_id("sessions", ids[1]).await.unwrap(), json!({ "session_id": "b", "uid": "123", "created": 0, "valid": true }));
+        assert!(db.insert("sessions", &json!({ "session_id": "d", "uid": "123", "created": 0, "valid": true })).await.unwrap() > ids[2]);
+    }
+
+    #[tokio::test]
+    async fn bulk() {
+        let db = SqliteDb::init(":memory:").await.unwrap();
+        db.create_table("sessions", &Session::schema()).await.unwrap();
+        let changes = db.subscribe("sessions");
+
+        let sessions: Vec<_> = (0..6).map(|n| json!({ "session_id": n.to_string(), "uid": (n % 2).to_string(), "created": n, "valid": true })).collect();
+        let ids = db.insert_many("sessions", &sessions).await.unwrap();
+        assert_eq!(db.get_by_id("sessions", ids[5]).await.unwrap(), sessions[5]);
+        // Nothing is inserted if any entry conflicts.
+        let conflicting = [json!({ "session_id": "6", "uid": "0", "created": 6, "valid": true }), sessions[0].clone()];
+        assert!(matches!(db.insert_many("sessions", &conflicting).await, Err(DatabaseError::Conflict { .. })));
+        assert!(db.find("sessions", db.filter().eq("session_id", "6".into()).build()).await.is_err());
+
+        let filter = db.filter().eq("uid", "0".into()).build();
+        assert_eq!(db.update_many("sessions", filter.clone(), &json!({ "valid": false })).await, Ok(3));
+        assert!(db.update_many("sessions", filter.clone(), &json!({ "session_id": "0" })).await.is_err());
+        assert_eq!(db.query("sessions", DatabaseQuery::new(db.filter().eq("valid", false.into()).build())).await.unwrap().total, 3);
+
+        assert_eq!(db.delete_many("sessions", filter.clone()).await, Ok(3));
+        assert_eq!(db.delete_many("sessions", filter).await, Ok(0));
+        assert_eq!(db.query("sessions", DatabaseQuery::new(db.filter().build())).await.unwrap().total, 3);
+
+        let received: Vec<_> = std::iter::from_fn(|| changes.try_recv().ok()).collect();
+        assert_eq!(received.len(), 12);
+        assert_eq!(received[6].event, ChangeEvent::Update { old: sessions[0].clone(), new: json!({ "session_id": "0", "uid": "0", "created": 0, "valid": false, "revision": 1 }) });
+        assert_eq!(received[11].id, ids[4]);
+    }
+
+    #[tokio::test]
+    async fn operators() {
+        let db = SqliteDb::init(":memory:").await.unwrap();
+        db.create_table("sessions", &Session::schema()).await.unwrap();
+        for (session_id, uid, created) in [("a", "flu", 10), ("b", "influenza", 20), ("c", "cold", 30)] {
+            db.insert("sessions", &json!({ "session_id": session_id, "uid": uid, "created": created, "valid": true })).await.unwrap();
+        }
+
+        let find = |filter| async { db.get("sessions", filter).await.map(|entry| entry["session_id"].clone()) };
+
+        assert_eq!(find(db.filter().gt("created", 10.into()).lt("created", 30.into()).build()).await, Ok(json!("b")));
+        assert_eq!(find(db.filter().gte("created", 30.into()).build()).await, Ok(json!("c")));
+        assert!(find(db.filter().lte("created", 9.into()).build()).await.is_err());
+        assert_eq!(find(db.filter().is_in("uid", vec!["cold".into(), "x".into()]).build()).await, Ok(json!("c")));
+        assert_eq!(find(db.filter().not_in("session_id", vec!["a".into(), "b".into()]).build()).await, Ok(json!("c")));
+        assert_eq!(find(db.filter().contains("uid", "flu".into()).neq("session_id", "a".into()).build()).await, Ok(json!("b")));
+        assert_eq!(find(db.filter().starts_with("uid", "in".into()).build()).await, Ok(json!("b")));
+        assert!(find(db.filter().exists("uid", false).build()).await.is_err());
+        assert_eq!(find(db.filter().or(|f| f.eq("uid", "cold".into()).and(|f| f.eq("uid", "x".into()))).build()).await, Ok(json!("c")));
+        assert!(find(db.filter().or(|f| f).build()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn query() {
+        let db = SqliteDb::init(":memory:").await.unwrap();
+        db.create_table("sessions", &Session::schema()).await.unwrap();
+        for (session_id, uid, created) in [("a", "1", 30), ("b", "2", 10), ("c", "1", 20), ("d", "1", 20)] {
+            db.insert("sessions", &json!({ "session_id": session_id, "uid": uid, "created": created, "valid": true })).await.unwrap();
+        }
+
+        let query = DatabaseQuery::new(db.filter().eq("uid", "1".into()).build())
+            .order_by("created", SortOrder::Ascending)
+            .limit(2)
+            .offset(1);
+        let result = db.query("sessions", query).await.unwrap();
+        let ids: Vec<_> = result.entries.iter().map(|entry| entry["session_id"].clone()).collect();
+        assert_eq!(result.total, 3);
+        assert_eq!(ids, vec![json!("d"), json!("a")]);
+
+        let query = DatabaseQuery::new(db.filter().build()).order_by("created", SortOrder::Descending);
+        let result = db.query("sessions", query).await.unwrap();
+        assert_eq!(result.total, 4);
+        assert_eq!(result.entries[0]["session_id"], json!("a"));
+    }
+
+    #[tokio::test]
+    async fn transaction() {
+        let db = SqliteDb::init(":memory:").await.unwrap();
+        db.create_table("sessions", &Session::schema()).await.unwrap();
+
+        let result = db.transaction(Box::new(|db| Box::pin(async move {
+            db.insert("sessions", &json!({ "session_id": "a", "uid": "1", "created": 0, "valid": true })).await?;
+            Err(DatabaseError::InvalidArgument("Cancelled".into()))
+        }))).await;
+        assert_eq!(result, Err(DatabaseError::InvalidArgument("Cancelled".into())));
+        assert!(db.find("sessions", db.filter().build()).await.is_err());
+
+        db.transaction(Box::new(|db| Box::pin(async move {
+            db.insert("sessions", &json!({ "session_id": "b", "uid": "1", "created": 0, "valid": true })).await?;
+            Ok(())
+        }))).await.unwrap();
+        assert_eq!(db.get("sessions", db.filter().build()).await.unwrap()["session_id"], json!("b"));
+    }
+
+    #[tokio::test]
+    async fn missing_table() {
+        let db = SqliteDb::init(":memory:").await.unwrap();
+        let filter = db.filter().eq("email", "test@email.com".into()).build();
+        assert_eq!(db.get("users", filter).await, Err(DatabaseError::TableNotFound("users".into())));
+        assert!(db.insert("users", &json!({ "email": "test@email.com" })).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn alter_table() {
+        let db = SqliteDb::init(":memory:").await.unwrap();
+        db.create_table("users", &ModelSchema {
+            fields: vec![ModelValueType::String { field: "name" }],
+            optional: Vec::new(),
+            unique: Vec::new(),
+            indexed: Vec::new()
+        }).await.unwrap();
+        db.insert("users", &json!({ "name": "Al" })).await.unwrap();
+
+        db.alter_table("users", &TableChange::AddField { field: ModelValueType::Number { field: "age" }, default: json!(0) }).await.unwrap();
+        db.alter_table("users", &TableChange::RenameField { from: "name", to: "firstname" }).await.unwrap();
+        db.alter_table("users", &TableChange::Backfill {
+            field: "age",
+            with: |entry| json!(entry["firstname"].as_str().unwrap().len())
+        }).await.unwrap();
+
+        let filter = db.filter().eq("firstname", "Al".into()).build();
+        assert_eq!(db.get("users", filter).await.unwrap(), json!({ "firstname": "Al", "age": 2 }));
+        assert!(db.insert("users", &json!({ "name": "Bo", "age": 1 })).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn indexes() {
+        let db = SqliteDb::init(":memory:").await.unwrap();
+        db.create_table("users", &ModelSchema {
+            fields: vec![ModelValueType::String { field: "email" }, ModelValueType::String { field: "name" }],
+            optional: vec!["name"],
+            unique: vec!["email"],
+            indexed: vec!["name"]
+        }).await.unwrap();
+
+        db.insert("users", &json!({ "email": "a@email.com" })).await.unwrap();
+        db.insert("users", &json!({ "email": "b@email.com" })).await.unwrap();
+        assert_eq!(db.insert("users", &json!({ "email": "a@email.com" })).await, Err(DatabaseError::Conflict { field: "email".into() }));
+
+        let filter = db.filter().eq("email", "b@email.com".into()).build();
+        assert!(db.update("users", filter, &json!({ "email": "a@email.com" })).await.is_err());
+
+        // Renamed fields keep their index.
+        db.alter_table("users", &TableChange::RenameField { from: "email", to: "address" }).await.unwrap();
+        assert!(db.insert("users", &json!({ "address": "a@email.com" })).await.is_err());
+        db.alter_table("users", &TableChange::DropIndex { field: "address" }).await.unwrap();
+        db.insert("users", &json!({ "address": "a@email.com" })).await.unwrap();
+        assert!(db.alter_table("users", &TableChange::AddIndex { field: "address", unique: true }).await.is_err());
+
+        // Indexed fields can still be dropped.
+        db.alter_table("users", &TableChange::DropField { field: "name" }).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn schemas() {
+        let path = std::env::temp_dir().join(format!("hag_sqlite_schemas_{}.db", uuid::Uuid::new_v4()));
+        let location = path.to_str().unwrap();
+        {
+            let db = SqliteDb::init(location).await.unwrap();
+            db.create_table("users", &ModelSchema {
+                fields: vec![ModelValueType::String { field: "email" }, ModelValueType::Number { field: "age" }],
+                optional: Vec::new(),
+                unique: Vec::new(),
+                indexed: Vec::new()
+            }).await.unwrap();
+            db.insert("users", &json!({ "email": "a@email.com", "age": 1 })).await.unwrap();
+            db.insert("users", &json!({ "email": "a@email.com", "age": 2 })).await.unwrap();
+
+            // Schema changes rolled back with a transaction aren't kept.
+            assert!(db.transaction(Box::new(|db| Box::pin(async move {
+                db.alter_table("users", &TableChange::DropField { field: "age" }).await?;
+                Err(DatabaseError::Backend("Rolled back".into()))
+            }))).await.is_err());
+        }
+
+        // Schemas are still enforced once the database is reopened.
+        let db = SqliteDb::init(location).await.unwrap();
+        assert!(db.insert("users", &json!({ "email": "b@email.com" })).await.is_err());
+        assert!(db.alter_table("users", &TableChange::AddIndex { field: "email", unique: true }).await.is_err());
+        assert!(db.alter_table("users", &TableChange::RenameField { from: "name", to: "firstname" }).await.is_err());
+        db.alter_table("users", &TableChange::DropField { field: "age" }).await.unwrap();
+        db.insert("users", &json!({ "email": "b@email.com" })).await.unwrap();
+
+        // Dropped tables take their schema with them.
+        db.drop_table("users").await.unwrap();
+        assert!(db.alter_table("users", &TableChange::DropField { field: "email" }).await.is_err());
+
+        drop(db);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{location}{suffix}"));
+        }
+    }
+
+    #[tokio::test]
+    async fn repository() {
+        let db = SqliteDb::init(":memory:").await.unwrap();
+        db.create_table("sessions", &Session::schema()).await.unwrap();
+        let sessions = Repository::<Session>::new(&*db);
+
+        let session = Session { session_id: "abc".into(), uid: "123".into(), created: 1, valid: true };
+        sessions.insert(&session).await.unwrap();
+        let filter = sessions.filter().eq("session_id", "abc".into()).build();
+        sessions.update(filter.clone(), &Session { valid: false, ..session }).await.unwrap();
+        assert!(!sessions.get(filter).await.unwrap().valid);
+
+        // Entries that don't match the model are errors rather than panics.
+        db.alter_table("sessions", &TableChange::DropField { field: "valid" }).await.unwrap();
+        assert!(sessions.query(DatabaseQuery::new(sessions.filter().build())).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn soft_delete() {
+        let db = SqliteDb::init(":memory:").await.unwrap();
+        migrations::migrate(&*db, None).await.unwrap();
+        database::setup(&*db).await;
+        let accounts = Repository::<Account>::new(&*db);
+        let all = || DatabaseQuery::new(accounts.filter().build());
+
+        let account = Account { uid: "123".into(), firstname: "Test".into(), surname: "User".into(), email: "test@email.com".into(), pass_hash: "hash".into() };
+        let id = accounts.insert(&account).await.unwrap();
+        let entry = db.get_by_id("accounts", id).await.unwrap();
+        assert!(entry["created_at"].is_i64());
+        assert_eq!(entry["created_at"], entry["updated_at"]);
+
+        // Deleted entries are kept, but hidden from the repository.
+        let filter = accounts.filter().eq("uid", "123".into()).build();
+        accounts.delete(filter.clone()).await.unwrap();
+        assert!(accounts.get(filter.clone()).await.unwrap_err().is_not_found());
+        assert!(accounts.get_by_id(id).await.unwrap_err().is_not_found());
+        assert!(accounts.delete(filter.clone()).await.unwrap_err().is_not_found());
+        assert_eq!(accounts.query(all()).await.unwrap().total, 0);
+        assert_eq!(accounts.deleted(all()).await.unwrap().total, 1);
+        assert!(db.get_by_id("accounts", id).await.unwrap()["deleted_at"].is_i64());
+        // Unique values stay reserved by the deleted entry, so it can always be recovered.
+        assert!(accounts.insert(&account).await.is_err());
+
+        accounts.recover(filter.clone()).await.unwrap();
+        assert_eq!(accounts.get(filter.clone()).await.unwrap().email, "test@email.com");
+        assert_eq!(accounts.deleted(all()).await.unwrap().total, 0);
+        assert!(db.get_by_id("accounts", id).await.unwrap().get("deleted_at").is_none());
+
+        accounts.purge(filter).await.unwrap();
+        assert!(db.get_by_id("accounts", id).await.unwrap_err().is_not_found());
+
+        let sessions = Repository::<Session>::new(&*db);
+        assert!(matches!(sessions.recover(sessions.filter().build()).await, Err(DatabaseError::InvalidArgument(_))));
+    }
+
+    #[tokio::test]
+    async fn migrations() {
+        let db = SqliteDb::init(":memory:").await.unwrap();
+        let latest = migrations::migrations().last().unwrap().version();
+
+        assert_eq!(migrations::pending(&*db).await.unwrap().last(), Some(&latest));
+        migrations::migrate(&*db, None).await.unwrap();
+        assert!(migrations::pending(&*db).await.unwrap().is_empty());
+        assert!(migrations::migrate(&*db, None).await.unwrap().is_empty());
+
+        assert_eq!(migrations::rollback(&*db, Some(0)).await.unwrap().first(), Some(&latest));
+        assert!(migrations::applied(&*db).await.unwrap().is_empty());
+        assert!(db.insert("accounts", &json!({})).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn dump() {
+        let source = VolatileDb::default();
+        migrations::migrate(&source, None).await.unwrap();
+        database::setup(&source).await;
+        let account = json!({ "uid": "123", "firstname": "Test", "surname": "User", "email": "test@email.com", "pass_hash": "hash" });
+        source.insert("accounts", &json!({ "uid": "0", "firstname": "Old", "surname": "User", "email": "old@email.com", "pass_hash": "hash" })).await.unwrap();
+        let id = source.insert("accounts", &account).await.unwrap();
+        source.delete("accounts", source.filter().eq("uid", "0".into()).build()).await.unwrap();
+        let dump = dump::export(&source).await.unwrap();
+
+        let db = SqliteDb::init(":memory:").await.unwrap();
+        dump::import(&*db, dump).await.unwrap();
+        assert!(migrations::pending(&*db).await.unwrap().is_empty());
+        // Entries keep their ids, and later entries are given new ones.
+        assert_eq!(db.get_by_id("accounts", id).await.unwrap(), account);
+        // Unique fields are indexed once restored.
+        assert!(db.insert("accounts", &account).await.is_err());
+        assert!(db.insert("accounts", &json!({ "uid": "456", "firstname": "New", "surname": "User", "email": "new@email.com", "pass_hash": "hash" })).await.unwrap() > id);
+
+        // Invalid dumps are rejected without touching the database.
+        let mut dump = dump::export(&*db).await.unwrap();
+        dump.tables.iter_mut().find(|table| table.table == "accounts").unwrap().entries.push(EntryDump { id: 99, entry: json!({ "uid": "789" }) });
+        assert!(matches!(dump::import(&*db, dump).await, Err(DatabaseError::SchemaViolation(_))));
+        let mut dump = dump::export(&*db).await.unwrap();
+        dump.tables.iter_mut().find(|table| table.table == "accounts").unwrap().schema["unique"] = json!([]);
+        assert!(matches!(dump::import(&*db, dump).await, Err(DatabaseError::SchemaViolation(_))));
+        assert_eq!(db.query("accounts", DatabaseQuery::new(db.filter().build())).await.unwrap().total, 2);
+    }
+
+    #[tokio::test]
+    async fn subscribe() {
+        let db = SqliteDb::init(":memory:").await.unwrap();
+        db.create_table("sessions", &Session::schema()).await.unwrap();
+        let changes = db.subscribe("sessions");
+
+        let session = json!({ "session_id": "a", "uid": "1", "created": 0, "valid": true });
+        let id = db.insert("sessions", &session).await.unwrap();
+        db.update("sessions", db.filter().build(), &json!({ "valid": false })).await.unwrap();
+        db.delete("sessions", db.filter().build()).await.unwrap();
+
+        let updated = json!({ "session_id": "a", "uid": "1", "created": 0, "valid": false, "revision": 1 });
+        assert_eq!(changes.recv().await.unwrap(), Change { table_id: "sessions".into(), id, event: ChangeEvent::Insert { new: session.clone() } });
+        assert_eq!(changes.recv().await.unwrap().event, ChangeEvent::Update { old: session, new: updated.clone() });
+        assert_eq!(changes.recv().await.unwrap().event, ChangeEvent::Delete { old: updated });
+
+        let result = db.transaction(Box::new(|db| Box::pin(async move {
+            db.insert("sessions", &json!({ "session_id": "b", "uid": "1", "created": 0, "valid": true })).await?;
+            Err(DatabaseError::InvalidArgument("Cancelled".into()))
+        }))).await;
+        assert!(result.is_err());
+        assert!(changes.is_empty());
+    }
+}
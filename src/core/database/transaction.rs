@@ -0,0 +1,191 @@
+use async_std::{channel::Receiver, sync::Mutex};
+use async_trait::async_trait;
+
+use crate::core::models::ModelSchema;
+
+use super::{aggregate::{AggregateGroup, Aggregation}, changes::{Change, Subscriptions}, error::DatabaseError, Database, DatabaseFilter, DatabaseQuery, EntryId, FilterValue, QueryResult, TableChange, TransactionFn};
+
+/// A write to a single table, made through [`Database::write`] and applied by [`Transactional::apply`].
+#[derive(Clone, Copy, Debug)]
+pub enum Operation<'o> {
+    CreateTable(&'o ModelSchema),
+    AlterTable(&'o TableChange),
+    DropTable,
+    Insert(&'o serde_json::Value),
+    InsertMany(&'o [serde_json::Value]),
+    Restore(&'o [(EntryId, serde_json::Value)]),
+    Update(&'o DatabaseFilter<FilterValue>, &'o serde_json::Value),
+    UpdateMany(&'o DatabaseFilter<FilterValue>, &'o serde_json::Value),
+    Delete(&'o DatabaseFilter<FilterValue>),
+    DeleteMany(&'o DatabaseFilter<FilterValue>)
+}
+
+impl Operation<'_> {
+    /// Copy the operation along with everything it borrows.
+    pub fn into_owned(self) -> OwnedOperation {
+        match self {
+            Operation::CreateTable(schema) => OwnedOperation::CreateTable(schema.clone()),
+            Operation::AlterTable(change) => OwnedOperation::AlterTable(change.clone()),
+            Operation::DropTable => OwnedOperation::DropTable,
+            Operation::Insert(data) => OwnedOperation::Insert(data.clone()),
+            Operation::InsertMany(data) => OwnedOperation::InsertMany(data.to_vec()),
+            Operation::Restore(entries) => OwnedOperation::Restore(entries.to_vec()),
+            Operation::Update(filter, data) => OwnedOperation::Update(filter.clone(), data.clone()),
+            Operation::UpdateMany(filter, data) => OwnedOperation::UpdateMany(filter.clone(), data.clone()),
+            Operation::Delete(filter) => OwnedOperation::Delete(filter.clone()),
+            Operation::DeleteMany(filter) => OwnedOperation::DeleteMany(filter.clone())
+        }
+    }
+}
+
+/// An [`Operation`] which owns what it writes, so it can be moved into a transaction or a blocking task.
+#[derive(Clone, Debug)]
+pub enum OwnedOperation {
+    CreateTable(ModelSchema),
+    AlterTable(TableChange),
+    DropTable,
+    Insert(serde_json::Value),
+    InsertMany(Vec<serde_json::Value>),
+    Restore(Vec<(EntryId, serde_json::Value)>),
+    Update(DatabaseFilter<FilterValue>, serde_json::Value),
+    UpdateMany(DatabaseFilter<FilterValue>, serde_json::Value),
+    Delete(DatabaseFilter<FilterValue>),
+    DeleteMany(DatabaseFilter<FilterValue>)
+}
+
+impl OwnedOperation {
+    pub fn as_operation(&self) -> Operation<'_> {
+        match self {
+            OwnedOperation::CreateTable(schema) => Operation::CreateTable(schema),
+            OwnedOperation::AlterTable(change) => Operation::AlterTable(change),
+            OwnedOperation::DropTable => Operation::DropTable,
+            OwnedOperation::Insert(data) => Operation::Insert(data),
+            OwnedOperation::InsertMany(data) => Operation::InsertMany(data),
+            OwnedOperation::Restore(entries) => Operation::Restore(entries),
+            OwnedOperation::Update(filter, data) => Operation::Update(filter, data),
+            OwnedOperation::UpdateMany(filter, data) => Operation::UpdateMany(filter, data),
+            OwnedOperation::Delete(filter) => Operation::Delete(filter),
+            OwnedOperation::DeleteMany(filter) => Operation::DeleteMany(filter)
+        }
+    }
+}
+
+/// Databases which run [`Database::transaction`] through a [`Transaction`].
+///
+/// A transaction holds [`Transactional::lock`] from start to finish, and every write made outside of one holds it while it runs,
+/// so writes from elsewhere wait for a running transaction to finish rather than being mixed into it.
+#[async_trait]
+pub trait Transactional: Database {
+    /// Whatever is needed to roll back the writes made within a transaction.
+    type Journal: Send;
+
+    fn lock(&self) -> &Mutex<()>;
+
+    fn subscriptions(&self) -> &Subscriptions;
+
+    async fn begin(&self) -> Result<Self::Journal, DatabaseError>;
+
+    /// Apply `operation` to `table_id`, returning the changes it made to entries.
+    /// Within a transaction `journal` is given, and should be kept up to date so every operation can be rolled back.
+    ///
+    /// This is only called with [`Transactional::lock`] held.
+    async fn apply(&self, table_id: &str, operation: Operation<'_>, journal: Option<&mut Self::Journal>) -> Result<Vec<Change>, DatabaseError>;
+
+    async fn commit(&self, journal: Self::Journal) -> Result<(), DatabaseError>;
+
+    async fn rollback(&self, journal: Self::Journal) -> Result<(), DatabaseError>;
+}
+
+/// Apply `operation` outside of a transaction, waiting for any running transaction to finish first.
+pub async fn write<D: Transactional>(db: &D, table_id: &str, operation: Operation<'_>) -> Result<Vec<Change>, DatabaseError> {
+    let _guard = db.lock().lock().await;
+    let changes = db.apply(table_id, operation, None).await?;
+    changes.iter().for_each(|change| db.subscriptions().notify(change.clone()));
+    Ok(changes)
+}
+
+/// Run `operation` within a transaction, for implementations of [`Database::transaction`].
+pub async fn run<D: Transactional>(db: &D, operation: TransactionFn) -> Result<(), DatabaseError> {
+    let _guard = db.lock().lock().await;
+
+    let transaction = Transaction { db, journal: Mutex::new(db.begin().await?), changes: std::sync::Mutex::new(Vec::new()) };
+    let result = operation(&transaction).await;
+    let (journal, changes) = (transaction.journal.into_inner(), transaction.changes.into_inner().unwrap());
+
+    match result {
+        Ok(()) => {
+            db.commit(journal).await?;
+            changes.into_iter().for_each(|change| db.subscriptions().notify(change));
+            Ok(())
+        },
+        Err(e) => {
+            log::debug!("Rolling back transaction: {e}");
+            db.rollback(journal).await?;
+            Err(e)
+        }
+    }
+}
+
+/// Database given to the operation run within a transaction.
+///
+/// Reads go straight to the database, writes are applied without waiting on [`Transactional::lock`], which the transaction holds.
+/// Subscribers are only sent the changes made within the transaction once it has been committed.
+pub struct Transaction<'d, D: Transactional> {
+    db: &'d D,
+    journal: Mutex<D::Journal>,
+    changes: std::sync::Mutex<Vec<Change>>
+}
+
+impl<D: Transactional> Transaction<'_, D> {
+    async fn apply(&self, table_id: &str, operation: Operation<'_>) -> Result<Vec<Change>, DatabaseError> {
+        let mut journal = self.journal.lock().await;
+        let changes = self.db.apply(table_id, operation, Some(&mut journal)).await?;
+        self.changes.lock().unwrap().extend(changes.iter().cloned());
+        Ok(changes)
+    }
+}
+
+#[async_trait]
+impl<D: Transactional> Database for Transaction<'_, D> {
+
+    /// Transactions can't be initialized, they're given to the operation run by [`Database::transaction`].
+    async fn init(_location: &str) -> Result<std::sync::Arc<Self>, DatabaseError> {
+        Err(DatabaseError::InvalidArgument("A transaction can only be started from an existing database.".into()))
+    }
+
+    async fn write(&self, table_id: &str, operation: Operation<'_>) -> Result<Vec<Change>, DatabaseError> {
+        self.apply(table_id, operation).await
+    }
+
+    async fn get(&self, table_id: &str,  filter: DatabaseFilter<FilterValue>) -> Result<serde_json::Value, DatabaseError> {
+        self.db.get(table_id, filter).await
+    }
+
+    async fn find(&self, table_id: &str, filter: DatabaseFilter<FilterValue>) -> Result<EntryId, DatabaseError> {
+        self.db.find(table_id, filter).await
+    }
+
+    async fn find_entries(&self, table_id: &str, filter: DatabaseFilter<FilterValue>) -> Result<Vec<(EntryId, serde_json::Value)>, DatabaseError> {
+        self.db.find_entries(table_id, filter).await
+    }
+
+    async fn get_by_id(&self, table_id: &str, id: EntryId) -> Result<serde_json::Value, DatabaseError> {
+        self.db.get_by_id(table_id, id).await
+    }
+
+    async fn query(&self, table_id: &str, query: DatabaseQuery<FilterValue>) -> Result<QueryResult, DatabaseError> {
+        self.db.query(table_id, query).await
+    }
+
+    async fn aggregate(&self, table_id: &str, aggregation: Aggregation<FilterValue>) -> Result<Vec<AggregateGroup>, DatabaseError> {
+        self.db.aggregate(table_id, aggregation).await
+    }
+
+    fn subscribe(&self, table_id: &str) -> Receiver<Change> {
+        self.db.subscribe(table_id)
+    }
+
+    async fn transaction(&self, _operation: TransactionFn) -> Result<(), DatabaseError> {
+        Err(DatabaseError::InvalidArgument("Transactions can't be nested.".into()))
+    }
+}
@@ -1,130 +1,102 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::{Arc, RwLock}};
+use async_std::{channel::Receiver, sync::Mutex};
 use async_trait::async_trait;
-use once_cell::sync::Lazy;
 
-use crate::core::models::ModelValueType;
+use super::{aggregate::{AggregateGroup, Aggregation}, changes::{Change, Subscriptions}, error::DatabaseError, memory::{self, MemoryTable, Undo}, Database, DatabaseFilter, DatabaseQuery, QueryResult, EntryId, FilterValue, TransactionFn, transaction::{self, Operation, Transactional}};
 
-use super::{Database, DatabaseFilter, PartialFilter, EntryLocation, FilterValue};
+type Volatile = HashMap<String, MemoryTable>;
 
-type Volatile = HashMap<String, VolatileTable>;
-type VolatileTable = Vec<serde_json::Value>;
+/// In-memory database, everything is lost once it is dropped.
+///
+/// Each instance has its own tables.
+#[derive(Default)]
+pub struct VolatileDb {
+    tables: RwLock<Volatile>,
+    /// Held for the duration of a transaction, so only one can run at a time, and by each write made outside of one while it runs.
+    transaction: Mutex<()>,
+    subscriptions: Subscriptions
+}
 
-static mut TABLES: Lazy<Volatile> = Lazy::new(|| {
-    log::debug!("Initializing Volatile tables.");
-    Volatile::new()
-});
+impl VolatileDb {
+    /// Run `f` against the table `table_id`.
+    fn read<R>(&self, table_id: &str, f: impl FnOnce(&MemoryTable) -> Result<R, DatabaseError>) -> Result<R, DatabaseError> {
+        match self.tables.read().unwrap().get(table_id) {
+            Some(table) => f(table),
+            None => Err(DatabaseError::TableNotFound(table_id.into()))
+        }
+    }
+}
 
-#[derive(Clone)]
-pub struct VolatileDb;
+#[async_trait]
+impl Database for VolatileDb {
 
-impl VolatileDb {
-    fn get_tables(&self) -> &mut Volatile {
-        unsafe { &mut TABLES }
+    async fn init(_location: &str) -> Result<Arc<VolatileDb>, DatabaseError> {
+        Ok(Arc::new(VolatileDb::default()))
     }
 
-    fn get_table(&self, table_id: &str) -> Result<&VolatileTable, String> {
-        match self.get_tables().get(table_id) {
-            Some(table) => Ok(table),
-            None => Err(format!("Failed to find table {table_id}"))
-        } 
+    async fn write(&self, table_id: &str, operation: Operation<'_>) -> Result<Vec<Change>, DatabaseError> {
+        transaction::write(self, table_id, operation).await
     }
 
-    fn get_table_mut(&self, table_id: &str) -> Result<&mut VolatileTable, String> {
-        match self.get_tables().get_mut(table_id) {
-            Some(table) => Ok(table),
-            None => Err(format!("Failed to find table {table_id}"))
-        } 
+    async fn get(&self, table_id: &str,  filter: DatabaseFilter<FilterValue>) -> Result<serde_json::Value, DatabaseError> {
+        self.read(table_id, |table| table.get(table_id, &filter))
     }
-}
 
-#[async_trait]
-impl Database for VolatileDb {
+    async fn find(&self, table_id: &str, filter: DatabaseFilter<FilterValue>) -> Result<EntryId, DatabaseError> {
+        self.read(table_id, |table| table.find(table_id, &filter))
+    }
 
-    async fn init(_location: &str) -> Arc<VolatileDb> {
-        Arc::new(VolatileDb { })
+    async fn find_entries(&self, table_id: &str, filter: DatabaseFilter<FilterValue>) -> Result<Vec<(EntryId, serde_json::Value)>, DatabaseError> {
+        self.read(table_id, |table| table.find_entries(table_id, &filter))
     }
 
-    // Model does not need to be used in this case as Volatile won't conform to an explicit structure.
-    async fn create_table(&self, table_id: &str, _model: &Vec<ModelValueType>) -> Result<(), String> {
-        let tables = self.get_tables();
-        tables.insert(table_id.to_string(), VolatileTable::new());
-        Ok(())
+    async fn get_by_id(&self, table_id: &str, id: EntryId) -> Result<serde_json::Value, DatabaseError> {
+        self.read(table_id, |table| table.get_by_id(id))
     }
 
-    async fn insert(&self, table_id: &str, data: &serde_json::Value) -> Result<EntryLocation, String> {
-        let table = self.get_table_mut(table_id)?;
-        table.push(data.clone());
-        Ok(table.len())
+    async fn query(&self, table_id: &str, query: DatabaseQuery<FilterValue>) -> Result<QueryResult, DatabaseError> {
+        self.read(table_id, |table| table.query(table_id, &query))
     }
 
-    async fn update(&self, table_id: &str, filter: DatabaseFilter<FilterValue>, data: &serde_json::Value) -> Result<(), String> {
-        let loc = self.find(table_id, filter).await?;
-        let table = self.get_table_mut(table_id)?;
-        let entry = &mut table[loc];
-        for (key, value) in data.as_object().unwrap() {
-            entry[key] = value.clone();
-        }
-        Ok(())
+    async fn aggregate(&self, table_id: &str, aggregation: Aggregation<FilterValue>) -> Result<Vec<AggregateGroup>, DatabaseError> {
+        self.read(table_id, |table| table.aggregate(table_id, &aggregation))
     }
 
-    async fn delete(&self, table_id: &str, filter: DatabaseFilter<FilterValue>) -> Result<(), String> {
-        let loc = self.find(table_id, filter).await?;
-        let table = self.get_table_mut(table_id)?;
-        table.remove(loc);
-        Ok(())
+    fn subscribe(&self, table_id: &str) -> Receiver<Change> {
+        self.subscriptions.subscribe(table_id)
     }
 
-    async fn get(&self, table_id: &str,  filter: DatabaseFilter<FilterValue>) -> Result<serde_json::Value, String> {
-        let loc = self.find(table_id, filter).await?;
-        self.get_loc(table_id, loc).await
-    }
-
-    async fn find(&self, table_id: &str, filter: DatabaseFilter<FilterValue>) -> Result<EntryLocation, String> {
-        let entries = self.get_table(table_id)?;
-        'entry_loop: for (idx, entry) in entries.iter().enumerate() {
-            for partial in &filter.0 {
-                match partial {
-                    PartialFilter::EQ { key, value } => {
-                        match entry.get(key) {
-                            Some(val) => {
-                                if val != value {
-                                    continue 'entry_loop
-                                }
-                            },
-                            None => continue 'entry_loop
-                        }
-                    },
-                    PartialFilter::NEQ { key, value } => {
-                        match entry.get(key) {
-                            Some(val) => {
-                                if val == value {
-                                    continue 'entry_loop;
-                                }
-                            },
-                            None => continue 'entry_loop
-                        }
-                    },
-                }
-            }
-            return Ok(idx);
-        }
-        Err("Failed to find entry matching the filter.".to_string())
+    async fn transaction(&self, operation: TransactionFn) -> Result<(), DatabaseError> {
+        transaction::run(self, operation).await
     }
+}
 
-    async fn get_loc(&self, table_id: &str, loc: EntryLocation) -> Result<serde_json::Value, String> {
-        let table = self.get_table(table_id)?;
+#[async_trait]
+impl Transactional for VolatileDb {
+    type Journal = Vec<Undo>;
 
-        if let Some(entry) = table.get(loc) {
-            // return match serde_json::from_value::<T>(entry.clone()) {
-            //     Ok(parsed) => Ok(parsed),
-            //     Err(e) => {
-            //         Err(format!("Failed to parse entry: {}, {entry}", e.to_string()))
-            //     }
-            // };
-            return Ok(entry.clone());
-        }
+    fn lock(&self) -> &Mutex<()> {
+        &self.transaction
+    }
 
-        Err(format!("Failed to find entry at location {loc} in table {table_id}"))
+    fn subscriptions(&self) -> &Subscriptions {
+        &self.subscriptions
+    }
+
+    async fn begin(&self) -> Result<Vec<Undo>, DatabaseError> {
+        Ok(Vec::new())
+    }
+
+    async fn apply(&self, table_id: &str, operation: Operation<'_>, journal: Option<&mut Vec<Undo>>) -> Result<Vec<Change>, DatabaseError> {
+        memory::apply(&mut self.tables.write().unwrap(), table_id, operation, journal)
+    }
+
+    async fn commit(&self, _journal: Vec<Undo>) -> Result<(), DatabaseError> {
+        Ok(())
+    }
+
+    async fn rollback(&self, journal: Vec<Undo>) -> Result<(), DatabaseError> {
+        memory::rollback(&mut self.tables.write().unwrap(), journal).map(|_| ())
     }
 }
 
@@ -132,10 +104,16 @@ impl Database for VolatileDb {
 pub mod test {
     use serde::{Deserialize, Serialize};
 
-    use crate::core::database::Database;
+    use serde_json::json;
+
+    use chrono::Utc;
+
+    use crate::core::{database::{self, changes::{Change, ChangeEvent}, error::DatabaseError, expiry, migrations, Database, DatabaseQuery, SortOrder}, models::{ModelSchema, ModelValueType}, sessions::SESSION_DURATION};
 
     use super::VolatileDb;
 
+    database::conformance::conformance!(VolatileDb::init("").await.unwrap());
+
     #[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
     struct User {
         email: String,
@@ -144,25 +122,184 @@ pub mod test {
 
     #[test]
     fn filter() {
-        let db = VolatileDb { };
+        let db = VolatileDb::default();
         let filter = db.filter()
-            .eq("email".into(), "test@email.com".into())
-            .eq("password".into(), "testPassword123".into())
+            .eq("email", "test@email.com".into())
+            .eq("password", "testPassword123".into())
             .build();
 
         assert_eq!(filter.0.len(), 2);
     }
 
+    #[test]
+    fn filter_operators() {
+        let db = VolatileDb::default();
+        let entry = serde_json::json!({ "title": "Flu season", "created": 20, "tags": null });
+
+        let matches = |filter: &mut crate::core::database::DatabaseFilterBuilder<_>| filter.build().matches(&entry);
+
+        assert!(matches(db.filter().gt("created", 10.into()).lte("created", 20.into())));
+        assert!(!matches(db.filter().lt("created", 20.into())));
+        assert!(!matches(db.filter().gt("title", 10.into())));
+        assert!(matches(db.filter().is_in("created", vec![10.into(), 20.into()])));
+        assert!(!matches(db.filter().not_in("created", vec![20.into()])));
+        assert!(matches(db.filter().contains("title", "season".into()).starts_with("title", "Flu".into())));
+        assert!(!matches(db.filter().starts_with("title", "season".into())));
+        assert!(matches(db.filter().exists("title", true).exists("tags", false).exists("missing", false)));
+        assert!(matches(db.filter().or(|f| f.eq("created", 0.into()).and(|f| f.eq("created", 20.into())))));
+        assert!(!matches(db.filter().or(|f| f)));
+        assert!(!matches(db.filter().neq("missing", 0.into())));
+    }
+
+    #[test]
+    fn query_apply() {
+        let db = VolatileDb::default();
+        let entries = [
+            serde_json::json!({ "id": 1, "uid": "a", "created": 30 }),
+            serde_json::json!({ "id": 2, "uid": "b", "created": 10 }),
+            serde_json::json!({ "id": 3, "uid": "a" }),
+            serde_json::json!({ "id": 4, "uid": "a", "created": 20 }),
+        ];
+
+        let query = DatabaseQuery::new(db.filter().eq("uid", "a".into()).build())
+            .order_by("created", SortOrder::Ascending)
+            .offset(1)
+            .limit(5);
+        let result = query.apply(entries.iter());
+        assert_eq!(result.total, 3);
+        assert_eq!(result.entries, vec![entries[3].clone(), entries[0].clone()]);
+    }
+
+    #[tokio::test]
+    async fn instances() {
+        let schema = ModelSchema { fields: vec![ModelValueType::Number { field: "n" }], optional: Vec::new(), unique: vec!["n"], indexed: Vec::new() };
+        let db = VolatileDb::init("").await.unwrap();
+        db.create_table("numbers", &schema).await.unwrap();
+        assert!(VolatileDb::default().find("numbers", db.filter().build()).await.is_err());
+
+        // Writers on separate threads all land.
+        let handles: Vec<_> = (0..8).map(|n| {
+            let db = db.clone();
+            std::thread::spawn(move || async_std::task::block_on(async move {
+                for i in 0..50 {
+                    db.insert("numbers", &serde_json::json!({ "n": n * 50 + i })).await.unwrap();
+                }
+            }))
+        }).collect();
+        handles.into_iter().for_each(|handle| handle.join().unwrap());
+
+        let result = db.query("numbers", DatabaseQuery::new(db.filter().build())).await.unwrap();
+        assert_eq!(result.total, 400);
+        assert!(db.find("numbers", db.filter().eq("n", 399.into()).build()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn subscribe() {
+        let schema = ModelSchema { fields: vec![ModelValueType::Number { field: "n" }], optional: Vec::new(), unique: vec!["n"], indexed: Vec::new() };
+        let db = VolatileDb::default();
+        db.create_table("numbers", &schema).await.unwrap();
+        let changes = db.subscribe("numbers");
+
+        let id = db.insert("numbers", &json!({ "n": 1 })).await.unwrap();
+        db.update("numbers", db.filter().eq("n", 1.into()).build(), &json!({ "n": 2 })).await.unwrap();
+        db.delete("numbers", db.filter().build()).await.unwrap();
+        assert_eq!(changes.recv().await.unwrap(), Change { table_id: "numbers".into(), id, event: ChangeEvent::Insert { new: json!({ "n": 1 }) } });
+        assert_eq!(changes.recv().await.unwrap().event, ChangeEvent::Update { old: json!({ "n": 1 }), new: json!({ "n": 2 }) });
+        assert_eq!(changes.recv().await.unwrap().event, ChangeEvent::Delete { old: json!({ "n": 2 }) });
+
+        // Changes within a transaction are only sent once it is committed.
+        let result = db.transaction(Box::new(|db| Box::pin(async move {
+            db.insert("numbers", &json!({ "n": 3 })).await?;
+            Err(DatabaseError::InvalidArgument("Cancelled".into()))
+        }))).await;
+        assert!(result.is_err());
+        db.transaction(Box::new(|db| Box::pin(async move {
+            db.insert("numbers", &json!({ "n": 4 })).await?;
+            Ok(())
+        }))).await.unwrap();
+        assert_eq!(changes.recv().await.unwrap().event, ChangeEvent::Insert { new: json!({ "n": 4 }) });
+        assert!(changes.is_empty());
+
+        // Failed writes aren't sent.
+        assert!(db.insert("numbers", &json!({ "n": 4 })).await.is_err());
+        assert!(changes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn expiry() {
+        let db = VolatileDb::default();
+        migrations::migrate(&db, None).await.unwrap();
+        database::setup(&db).await;
+
+        let now = Utc::now().timestamp_millis();
+        let expired = now - SESSION_DURATION.num_milliseconds() - 1000;
+        for (session_id, created) in [("a", expired), ("b", now), ("c", expired)] {
+            db.insert("sessions", &json!({ "session_id": session_id, "uid": "1", "created": created, "valid": true })).await.unwrap();
+        }
+
+        assert_eq!(expiry::sweep(&db).await, Ok(2));
+        let result = db.query("sessions", DatabaseQuery::new(db.filter().build())).await.unwrap();
+        assert_eq!(result.entries.iter().map(|entry| entry["session_id"].clone()).collect::<Vec<_>>(), vec![json!("b")]);
+        assert_eq!(expiry::sweep(&db).await, Ok(0));
+    }
+
+    #[tokio::test]
+    async fn bulk() {
+        let schema = ModelSchema { fields: vec![ModelValueType::Number { field: "n" }, ModelValueType::Boolean { field: "even" }], optional: Vec::new(), unique: vec!["n"], indexed: vec!["even"] };
+        let db = VolatileDb::default();
+        db.create_table("numbers", &schema).await.unwrap();
+        let changes = db.subscribe("numbers");
+
+        let numbers: Vec<_> = (0..10).map(|n| json!({ "n": n, "even": n % 2 == 0 })).collect();
+        let ids = db.insert_many("numbers", &numbers).await.unwrap();
+        assert_eq!(ids.len(), 10);
+        assert_eq!(db.get_by_id("numbers", ids[9]).await.unwrap(), numbers[9]);
+        // Nothing is inserted if any entry is invalid.
+        assert!(db.insert_many("numbers", &[json!({ "n": 10, "even": true }), json!({ "n": 0, "even": true })]).await.is_err());
+        assert!(db.insert_many("numbers", &[json!({ "n": 10, "even": true }), json!({ "n": 11 })]).await.is_err());
+
+        let even = db.filter().eq("even", true.into()).build();
+        assert_eq!(db.update_many("numbers", even.clone(), &json!({ "even": false })).await, Ok(5));
+        assert_eq!(db.update_many("numbers", even.clone(), &json!({ "even": false })).await, Ok(0));
+        // Nothing is updated if any entry conflicts.
+        assert!(db.update_many("numbers", db.filter().lt("n", 2.into()).build(), &json!({ "n": 100 })).await.is_err());
+        assert!(db.find("numbers", db.filter().eq("n", 0.into()).build()).await.is_ok());
+
+        assert_eq!(db.delete_many("numbers", db.filter().gte("n", 4.into()).build()).await, Ok(6));
+        assert_eq!(db.delete_many("numbers", db.filter().gte("n", 4.into()).build()).await, Ok(0));
+        let result = db.query("numbers", DatabaseQuery::new(db.filter().build())).await.unwrap();
+        assert_eq!(result.total, 4);
+        assert_eq!(db.get_by_id("numbers", ids[3]).await.unwrap()["n"], 3);
+        assert!(db.find("numbers", db.filter().eq("n", 3.into()).build()).await.is_ok());
+
+        // Every entry changed is sent to subscribers.
+        let mut counts = [0; 3];
+        while let Ok(change) = changes.try_recv() {
+            counts[match change.event { ChangeEvent::Insert { .. } => 0, ChangeEvent::Update { .. } => 1, ChangeEvent::Delete { .. } => 2 }] += 1;
+        }
+        assert_eq!(counts, [10, 5, 6]);
+    }
+
     #[tokio::test]
     async fn get() {
-        let db = VolatileDb { };
+        let db = VolatileDb::default();
+        let schema = ModelSchema {
+            fields: vec![ModelValueType::String { field: "email" }, ModelValueType::String { field: "password" }],
+            optional: Vec::new(),
+            unique: vec!["email"],
+            indexed: Vec::new()
+        };
+        db.create_table("users", &schema).await.unwrap();
+        db.insert("users", &serde_json::json!({ "email": "other@email.com", "password": "testPassword123" })).await.unwrap();
+        db.insert("users", &serde_json::json!({ "email": "test@email.com", "password": "testPassword123" })).await.unwrap();
+
         let filter = db.filter()
-            .eq("email".into(), "test@email.com".into())
-            .eq("password".into(), "testPassword123".into())
+            .eq("email", "test@email.com".into())
+            .eq("password", "testPassword123".into())
             .build();
 
-        let result = db.get("users", filter).await;
-        assert_eq!(result.unwrap(), serde_json::json!({ "email": "test@email.com", "password": "testPassword123" }))
+        let result = db.get("users", filter).await.unwrap();
+        assert_eq!(serde_json::from_value::<User>(result).unwrap(), User { email: "test@email.com".into(), password: "testPassword123".into() })
     }
 
 }
\ No newline at end of file
@@ -2,6 +2,9 @@ use crate::middleware::MiddlewareData;
 
 pub trait TideRequestExt {
     fn make_data(&self, data: serde_json::Value) -> serde_json::Value;
+
+    /// `uid` of the signed in user, set by [`UserSessionMiddleware`](crate::middleware::user_session::UserSessionMiddleware).
+    fn uid(&self) -> Option<String>;
 }
 
 impl<State: Clone + Send + Sync + 'static> TideRequestExt for tide::Request<State> {
@@ -14,4 +17,11 @@ impl<State: Clone + Send + Sync + 'static> TideRequestExt for tide::Request<Stat
         data.as_object_mut().unwrap().extend(ext.as_object().unwrap().clone());
         data
     }
+
+    fn uid(&self) -> Option<String> {
+        self.ext::<MiddlewareData>()
+            .and_then(|ext| ext.get("uid"))
+            .and_then(|uid| uid.as_str())
+            .map(String::from)
+    }
 }
\ No newline at end of file
@@ -1,28 +1,22 @@
 use serde::{Deserialize, Serialize};
 
-use crate::core::{database::DatabaseModel, models::ModelValueType};
+use crate::core::database::DatabaseModel;
 
-#[derive(Deserialize, Serialize, Clone, Debug)]
+/// Deleted accounts are kept so they can be recovered, and keep their `uid` and `email` reserved until then,
+/// so an email address can't be registered again while the account it belonged to can still be recovered.
+#[derive(Deserialize, Serialize, Clone, Debug, DatabaseModel)]
+#[model(table = "accounts", timestamps, soft_delete, revisions)]
 pub struct Account {
+    #[model(unique)]
     pub uid: String,
     pub firstname: String,
     pub surname: String,
+    #[model(unique)]
     pub email: String,
+    #[model(sensitive)]
     pub pass_hash: String
 }
 
-impl DatabaseModel for Account {
-    fn fields() -> Vec<ModelValueType> {
-        vec![
-            ModelValueType::String { field: "uid" },
-            ModelValueType::String { field: "firstname" },
-            ModelValueType::String { field: "surname" },
-            ModelValueType::String { field: "email" },
-            ModelValueType::String { field: "pass_hash" }
-        ]
-    }
-}
-
 impl Account {
     pub fn partial(firstname: String, surname: String, email: String, pass_hash: String) -> Account {
         Account { 
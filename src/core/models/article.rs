@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+use crate::core::database::DatabaseModel;
+
+/// An article shown under the site's Health Advice or News pages, found with [`Search`](crate::core::database::search::Search).
+#[derive(Deserialize, Serialize, Clone, Debug, DatabaseModel)]
+#[model(table = "articles", timestamps, soft_delete)]
+pub struct Article {
+    #[model(indexed, kind = String)]
+    pub section: ArticleSection,
+    #[model(searchable)]
+    pub title: String,
+    #[model(searchable)]
+    pub body: String
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ArticleSection {
+    Advice,
+    News
+}
@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+
+use crate::core::database::{DatabaseModel, EntryId};
+
+/// A single change recorded by [`Audited`](crate::core::database::audit::Audited).
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, DatabaseModel)]
+#[model(table = "audit_log")]
+pub struct AuditEntry {
+    /// Account which made the change, `None` for changes made by the application itself.
+    #[model(indexed)]
+    pub uid: Option<String>,
+    /// Unix timestamp in milliseconds.
+    pub timestamp: i64,
+    pub table_id: String,
+    // `EntryId` is an alias, so isn't recognised as a number.
+    #[model(indexed, kind = Number)]
+    pub entry_id: EntryId,
+    #[model(kind = String)]
+    pub action: AuditAction,
+    /// Every field which changed, mapped to an object holding its `old` and `new` values.
+    pub diff: serde_json::Value
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Insert,
+    Update,
+    Delete
+}
@@ -1,13 +1,279 @@
+use std::{collections::HashSet, sync::Mutex};
+
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 
 pub mod session;
 pub mod account;
+pub mod audit;
+pub mod article;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub enum ModelValueType {
     String  {field: &'static str},
     Number  {field: &'static str},
     Object  {field: &'static str},
     Array   {field: &'static str},
     Boolean {field: &'static str}
-}
\ No newline at end of file
+}
+
+impl ModelValueType {
+    pub fn field(&self) -> &'static str {
+        match self {
+            ModelValueType::String  { field } |
+            ModelValueType::Number  { field } |
+            ModelValueType::Object  { field } |
+            ModelValueType::Array   { field } |
+            ModelValueType::Boolean { field } => field
+        }
+    }
+
+    /// Same type, with a different field name.
+    pub fn renamed(&self, field: &'static str) -> ModelValueType {
+        match self {
+            ModelValueType::String  { .. } => ModelValueType::String  { field },
+            ModelValueType::Number  { .. } => ModelValueType::Number  { field },
+            ModelValueType::Object  { .. } => ModelValueType::Object  { field },
+            ModelValueType::Array   { .. } => ModelValueType::Array   { field },
+            ModelValueType::Boolean { .. } => ModelValueType::Boolean { field }
+        }
+    }
+
+    /// Name of the json type expected for this field.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ModelValueType::String  { .. } => "string",
+            ModelValueType::Number  { .. } => "number",
+            ModelValueType::Object  { .. } => "object",
+            ModelValueType::Array   { .. } => "array",
+            ModelValueType::Boolean { .. } => "boolean"
+        }
+    }
+
+    /// Checks that `value` is of the expected type.
+    pub fn accepts(&self, value: &serde_json::Value) -> bool {
+        match self {
+            ModelValueType::String  { .. } => value.is_string(),
+            ModelValueType::Number  { .. } => value.is_number(),
+            ModelValueType::Object  { .. } => value.is_object(),
+            ModelValueType::Array   { .. } => value.is_array(),
+            ModelValueType::Boolean { .. } => value.is_boolean()
+        }
+    }
+}
+
+/// Describes the table for a [`DatabaseModel`](crate::core::database::DatabaseModel),
+/// entries written to the table are validated against it.
+///
+/// Schemas are stored alongside their tables by the databases which keep them on disk, so can be read back too.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(from = "StoredSchema")]
+pub struct ModelSchema {
+    pub fields: Vec<ModelValueType>,
+    /// Fields which may be missing or null.
+    pub optional: Vec<&'static str>,
+    /// Fields which no two entries may share a value for, missing or null values aren't considered.
+    /// Unique fields are also indexed.
+    pub unique: Vec<&'static str>,
+    /// Fields which are indexed, speeding up lookups which check for equality on them.
+    pub indexed: Vec<&'static str>
+}
+
+impl ModelSchema {
+    pub fn field(&self, field: &str) -> Option<&ModelValueType> {
+        self.fields.iter().find(|value_type| value_type.field() == field)
+    }
+
+    pub fn is_optional(&self, field: &str) -> bool {
+        self.optional.contains(&field)
+    }
+
+    pub fn is_unique(&self, field: &str) -> bool {
+        self.unique.contains(&field)
+    }
+
+    /// Every indexed field, including unique fields.
+    pub fn indexes(&self) -> Vec<&'static str> {
+        let mut indexes = self.unique.clone();
+        indexes.extend(self.indexed.iter().filter(|field| !self.is_unique(field)));
+        indexes
+    }
+
+    /// Checks that `data` is a valid entry.
+    /// If `partial` is true only the fields present in `data` are checked, as is the case for updates.
+    ///
+    /// Errors with a description of the first invalid field.
+    pub fn validate(&self, data: &serde_json::Value, partial: bool) -> Result<(), String> {
+        let Some(entry) = data.as_object() else {
+            return Err(format!("Expected an object, found {}", json_type_name(data)));
+        };
+
+        if let Some(key) = entry.keys().find(|key| self.field(key).is_none()) {
+            return Err(format!("Unknown field `{key}`"));
+        }
+
+        for value_type in &self.fields {
+            let field = value_type.field();
+            match entry.get(field) {
+                None if partial => {},
+                None | Some(serde_json::Value::Null) => {
+                    if !self.is_optional(field) {
+                        return Err(format!("Missing required field `{field}`"));
+                    }
+                },
+                Some(value) => {
+                    if !value_type.accepts(value) {
+                        return Err(format!("Field `{field}` must be {}, found {}", value_type.type_name(), json_type_name(value)));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A [`ModelSchema`] as it's read back from storage, with owned field names.
+#[derive(Deserialize)]
+struct StoredSchema {
+    fields: Vec<StoredValueType>,
+    optional: Vec<String>,
+    unique: Vec<String>,
+    indexed: Vec<String>
+}
+
+#[derive(Deserialize)]
+enum StoredValueType {
+    String  {field: String},
+    Number  {field: String},
+    Object  {field: String},
+    Array   {field: String},
+    Boolean {field: String}
+}
+
+impl From<StoredSchema> for ModelSchema {
+    fn from(stored: StoredSchema) -> ModelSchema {
+        let names = |names: Vec<String>| names.into_iter().map(intern).collect();
+        ModelSchema {
+            fields: stored.fields.into_iter()
+                .map(|value_type| match value_type {
+                    StoredValueType::String  { field } => ModelValueType::String  { field: intern(field) },
+                    StoredValueType::Number  { field } => ModelValueType::Number  { field: intern(field) },
+                    StoredValueType::Object  { field } => ModelValueType::Object  { field: intern(field) },
+                    StoredValueType::Array   { field } => ModelValueType::Array   { field: intern(field) },
+                    StoredValueType::Boolean { field } => ModelValueType::Boolean { field: intern(field) }
+                })
+                .collect(),
+            optional: names(stored.optional),
+            unique: names(stored.unique),
+            indexed: names(stored.indexed)
+        }
+    }
+}
+
+/// Field names are `&'static str` as they're usually declared by models, so names read back from storage are leaked.
+/// Each name is only leaked once, however many times it's read.
+fn intern(name: String) -> &'static str {
+    static NAMES: Lazy<Mutex<HashSet<&'static str>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+    let mut names = NAMES.lock().unwrap();
+    match names.get(name.as_str()) {
+        Some(name) => name,
+        None => {
+            let name: &'static str = Box::leak(name.into_boxed_str());
+            names.insert(name);
+            name
+        }
+    }
+}
+
+fn json_type_name(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "boolean",
+        serde_json::Value::Number(_) => "number",
+        serde_json::Value::String(_) => "string",
+        serde_json::Value::Array(_) => "array",
+        serde_json::Value::Object(_) => "object"
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::collections::HashMap;
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::core::database::{DatabaseModel, CREATED_AT, UPDATED_AT};
+
+    use super::{account::Account, session::Session, ModelSchema, ModelValueType};
+
+    #[derive(Deserialize, Serialize, DatabaseModel)]
+    #[model(table = "profiles", timestamps)]
+    struct Profile {
+        #[model(unique)]
+        uid: String,
+        #[model(searchable)]
+        bio: String,
+        #[model(indexed)]
+        age: Option<u32>,
+        #[model(optional)]
+        verified: bool,
+        tags: Vec<String>,
+        settings: HashMap<String, String>,
+        #[serde(rename = "lang", skip_serializing_if = "Option::is_none")]
+        language: Option<String>,
+        #[model(skip)]
+        #[serde(skip)]
+        _cached: u8,
+        #[serde(skip)]
+        _loaded: bool
+    }
+
+    #[derive(Deserialize, Serialize, DatabaseModel)]
+    #[model(table = "posts")]
+    #[serde(rename_all = "camelCase", deny_unknown_fields)]
+    struct Post {
+        post_id: String,
+        #[serde(rename(serialize = "body", deserialize = "body"))]
+        content: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        tagged_users: Vec<String>
+    }
+
+    #[test]
+    fn derived() {
+        assert_eq!(Profile::table(), "profiles");
+        assert_eq!(Profile::schema(), ModelSchema {
+            fields: vec![
+                ModelValueType::String  { field: "uid" },
+                ModelValueType::String  { field: "bio" },
+                ModelValueType::Number  { field: "age" },
+                ModelValueType::Boolean { field: "verified" },
+                ModelValueType::Array   { field: "tags" },
+                ModelValueType::Object  { field: "settings" },
+                ModelValueType::String  { field: "lang" },
+                ModelValueType::Number  { field: CREATED_AT },
+                ModelValueType::Number  { field: UPDATED_AT }
+            ],
+            optional: vec!["age", "verified", "lang", CREATED_AT, UPDATED_AT],
+            unique: vec!["uid"],
+            indexed: vec!["age"]
+        });
+        assert_eq!(Profile::searchable(), vec!["bio"]);
+        // Schemas read back from storage are the same as the ones written.
+        assert_eq!(serde_json::from_value::<ModelSchema>(serde_json::to_value(Profile::schema()).unwrap()).unwrap(), Profile::schema());
+
+        // Fields are named as they're serialized.
+        assert_eq!(Post::fields(), vec![
+            ModelValueType::String { field: "postId" },
+            ModelValueType::String { field: "body" },
+            ModelValueType::Array  { field: "taggedUsers" }
+        ]);
+        assert_eq!(Post::optional(), vec!["taggedUsers"]);
+
+        assert_eq!(Account::unique(), vec!["uid", "email"]);
+        assert_eq!(Account::sensitive(), vec!["pass_hash"]);
+        assert!(Account::soft_delete());
+        assert_eq!(Session::indexed(), vec!["uid"]);
+        assert!(Session::expiry().is_some() && Session::cache().is_some());
+    }
+}
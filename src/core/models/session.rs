@@ -1,22 +1,25 @@
+use std::time::Duration;
+
 use serde::{Deserialize, Serialize};
 
-use crate::core::{database::DatabaseModel, models::ModelValueType};
+use crate::core::{database::{cache::CachePolicy, expiry::Expiry, DatabaseModel}, sessions::SESSION_DURATION};
 
-#[derive(Deserialize, Serialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug, DatabaseModel)]
+#[model(table = "sessions", timestamps, revisions, expiry = expiry, cache = cache)]
 pub struct Session {
+    #[model(unique)]
     pub session_id: String,
+    #[model(indexed)]
     pub uid: String,
     pub created: i64,
     pub valid: bool
 }
 
-impl DatabaseModel for Session {
-    fn fields() -> Vec<ModelValueType> {
-        vec![
-            ModelValueType::String  { field: "session_id" },
-            ModelValueType::String  { field: "uid" },
-            ModelValueType::Number  { field: "created" },
-            ModelValueType::Boolean { field: "valid" }
-        ]
-    }
+fn expiry() -> Option<Expiry> {
+    Some(Expiry { field: "created", ttl: *SESSION_DURATION })
+}
+
+// Sessions are checked on every request.
+fn cache() -> Option<CachePolicy> {
+    Some(CachePolicy { ttl: Duration::from_secs(60), capacity: 10_000 })
 }
\ No newline at end of file
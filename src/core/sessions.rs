@@ -1,38 +1,39 @@
 use chrono::{Duration, Utc};
 use once_cell::sync::Lazy;
+use serde_json::json;
 
-use super::{database, models::session::Session};
+use super::{database::{Database, error::DatabaseError, repository::Repository}, models::session::Session};
 
 /// If a sessions lifetime exceeds this duration it will be deleted.
-const SESSION_DURATION: Lazy<Duration> = Lazy::new(|| Duration::days(180));
+pub static SESSION_DURATION: Lazy<Duration> = Lazy::new(|| Duration::days(180));
 
 /// Attempts to find an existing session.
 /// Will also ensure the sessions validity.
 /// 
 /// # Arguments
+/// * `db` - [`Database`] to query.
 /// * `session_id` - [`String`] containing the session id to search for.
-pub async fn get(session_id: String) -> Option<Session> {
-    let db = database::get();
+pub async fn get(db: &dyn Database, session_id: String) -> Option<Session> {
+    let sessions = Repository::<Session>::new(db);
 
-    let filter = db.filter()
+    let filter = sessions.filter()
         // Session matching session_id
         .eq("session_id", session_id.clone().into())
         .build();
 
-    match db.get("sessions", filter).await {
+    match sessions.get(filter).await {
         Ok(session) => {
-            // Parse session
-            let session = serde_json::from_value::<Session>(session).unwrap();
             if !is_valid(session.clone()) {
                 // Delete if session is no longer valid
-                let _ = delete(session_id.clone()).await;
+                let _ = delete(db, session_id.clone()).await;
                 return None;
             }
 
             Some(session)
         },
+        Err(DatabaseError::NotFound) => None,
         Err(err) => {
-            log::debug!("Failed to query database for session. {err}");
+            log::error!("Failed to query database for session. {err}");
             None
         }
     }
@@ -41,6 +42,7 @@ pub async fn get(session_id: String) -> Option<Session> {
 /// Attempts to create a new user session.
 /// 
 /// # Arguments
+/// * `db` - [`Database`] to create the session in.
 /// * `session_id` - [`String`] containing a users session identifier.
 /// * `uid` - [`String`] containing the users unique identifier. 
 /// 
@@ -52,21 +54,21 @@ pub async fn get(session_id: String) -> Option<Session> {
 ///     let uid = /* ... */;
 /// 
 ///     let session_id = req.session().id().to_string();
-///     if let Err(err) = sessions::create(session_id, uid).await {
+///     if let Err(err) = sessions::create(&*req.state().db, session_id, uid).await {
 ///         log::error!("Failed to create user session. {err}");
 ///     }
 /// 
 ///     /* ... */
 /// }
 /// ```
-pub async fn create(session_id: String, uid: String) -> Result<Session, String> {
+pub async fn create(db: &dyn Database, session_id: String, uid: String) -> Result<Session, DatabaseError> {
     // Check if session exists
-    if let Some(_) = get(session_id.clone()).await {
+    if get(db, session_id.clone()).await.is_some() {
         log::warn!("Attempted to create session with existing id {session_id}");
-        return Err("Session already exists.".into());
+        return Err(DatabaseError::Conflict { field: "session_id".into() });
     }
 
-    let db = database::get();
+    let sessions = Repository::<Session>::new(db);
     let session = Session {
         session_id,
         uid,
@@ -74,11 +76,9 @@ pub async fn create(session_id: String, uid: String) -> Result<Session, String>
         valid: true
     };
 
-    // Convert to raw json
-    let session_raw = serde_json::to_value(session.clone()).unwrap();
-    if let Err(err) = db.insert("sessions", &session_raw).await {
+    if let Err(err) = sessions.insert(&session).await {
         log::error!("Failed to persist session {:?}: {err}", session);
-        return Err("Failed to create session.".into());
+        return Err(err);
     }
 
     Ok(session)
@@ -94,18 +94,37 @@ pub fn is_valid(session: Session) -> bool {
     Utc::now().timestamp_millis() - session.created < SESSION_DURATION.num_milliseconds()
 }
 
+/// Invalidates every session belonging to `uid`, e.g. to log a user out everywhere.
+/// Returns how many sessions were invalidated.
+///
+/// # Arguments
+/// * `db` - [`Database`] containing the sessions.
+/// * `uid` - [`String`] containing the users unique identifier.
+pub async fn invalidate_all(db: &dyn Database, uid: String) -> Result<usize, DatabaseError> {
+    let sessions = Repository::<Session>::new(db);
+
+    let filter = sessions.filter()
+        .eq("uid", uid.clone().into())
+        .eq("valid", true.into())
+        .build();
+
+    sessions.update_many(filter, json!({ "valid": false })).await
+        .inspect_err(|err| log::error!("Failed to invalidate sessions for UID {uid}: {err}"))
+}
+
 /// Attempts to delete the session matching `session_id`.
 /// 
 /// # Arguments
+/// * `db` - [`Database`] to delete the session from.
 /// * `session_id` - [`String`] containing the session id.
-pub async fn delete(session_id: String) -> Result<(), String> {
-    let db = database::get();
+pub async fn delete(db: &dyn Database, session_id: String) -> Result<(), DatabaseError> {
+    let sessions = Repository::<Session>::new(db);
 
-    let filter = db.filter()
+    let filter = sessions.filter()
         .eq("session_id", session_id.clone().into())
         .build();
 
-    if let Err(err) = db.delete("sessions", filter).await {
+    if let Err(err) = sessions.delete(filter).await {
         log::error!("Failed to delete session {session_id}: {err}");
         return Err(err);
     }
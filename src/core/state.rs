@@ -2,7 +2,11 @@ use std::sync::{Arc, Mutex};
 
 use handlebars::Handlebars;
 
+use super::database::{audit::Audited, search::Search};
+
 #[derive(Clone)]
 pub struct ApplicationState {
-    pub hb: Arc<Mutex<Handlebars<'static>>>
+    pub hb: Arc<Mutex<Handlebars<'static>>>,
+    pub db: Arc<Audited>,
+    pub search: Arc<Search>
 }
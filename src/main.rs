@@ -12,6 +12,15 @@ use tide::sessions::SessionMiddleware;
 
 use crate::cli::CLI;
 use crate::core::database;
+use crate::core::database::audit::Audited;
+use crate::core::database::cache::CachedDb;
+use crate::core::database::dump;
+use crate::core::database::expiry;
+use crate::core::database::migrations;
+use crate::core::database::persistent::PersistentDb;
+use crate::core::database::search::Search;
+use crate::core::database::seed;
+use crate::core::database::sqlite::SqliteDb;
 use crate::core::database::volatile::VolatileDb;
 use crate::core::logger::{Logger, LoggerOptions};
 use crate::core::state::ApplicationState;
@@ -35,24 +44,92 @@ async fn main() -> tide::Result<()> {
     let args = CLI::parse();
 
     // Initialize database depending on the db type passed.
-    match args.db {
+    let db_location = args.db_location.clone().unwrap_or_default();
+    let db = match args.db {
+        cli::ArgDb::Volatile if args.cache => database::init::<CachedDb<VolatileDb>>("").await.unwrap(),
+        cli::ArgDb::Persistent if args.cache => database::init::<CachedDb<PersistentDb>>(&db_location).await.unwrap(),
+        cli::ArgDb::Production if args.cache => database::init::<CachedDb<SqliteDb>>(&db_location).await.unwrap(),
         cli::ArgDb::Volatile => database::init::<VolatileDb>("").await.unwrap(),
-        cli::ArgDb::Persistent => todo!(),
-        cli::ArgDb::Production => todo!(),
+        cli::ArgDb::Persistent => database::init::<PersistentDb>(&db_location).await.unwrap(),
+        cli::ArgDb::Production => database::init::<SqliteDb>(&db_location).await.unwrap(),
     };
 
-    // Create necessary tables
-    if args.setup_db || args.db == cli::ArgDb::Volatile {
-        database::setup().await;
+    match args.command {
+        Some(cli::Command::Migrate { to }) => {
+            match migrations::migrate(&*db, to).await {
+                Ok(applied) => log::info!("Applied {} migration(s).", applied.len()),
+                Err(e) => {
+                    log::error!("{e}");
+                    std::process::exit(1);
+                }
+            }
+            return Ok(());
+        }
+        Some(cli::Command::Rollback { to }) => {
+            match migrations::rollback(&*db, to).await {
+                Ok(rolled_back) => log::info!("Rolled back {} migration(s).", rolled_back.len()),
+                Err(e) => {
+                    log::error!("{e}");
+                    std::process::exit(1);
+                }
+            }
+            return Ok(());
+        }
+        Some(cli::Command::Dump { file }) => {
+            match dump::save(&*db, &file).await {
+                Ok(()) => log::info!("Dumped database to {}", file.display()),
+                Err(e) => {
+                    log::error!("{e}");
+                    std::process::exit(1);
+                }
+            }
+            return Ok(());
+        }
+        Some(cli::Command::Restore { file }) => {
+            match dump::load(&*db, &file).await {
+                Ok(()) => log::info!("Restored database from {}", file.display()),
+                Err(e) => {
+                    log::error!("{e}");
+                    std::process::exit(1);
+                }
+            }
+            return Ok(());
+        }
+        None => {}
     }
 
+    // Volatile databases always start empty, so are brought up to date automatically.
+    if args.db == cli::ArgDb::Volatile {
+        migrations::migrate(&*db, None).await.expect("Failed to migrate volatile database!");
+    }
+
+    let pending = migrations::pending(&*db).await.expect("Failed to read applied migrations!");
+    if !pending.is_empty() {
+        log::error!("Database has {} pending migration(s), apply them with the `migrate` command.", pending.len());
+        std::process::exit(1);
+    }
+
+    // Register table schemas, the tables themselves are created by migrations.
+    database::setup(&*db).await;
+
+    // Index searchable tables, such as articles, each index is kept up to date in the background.
+    let search = Arc::new(Search::init(db.clone()).await.expect("Failed to index searchable tables!"));
+
+    // Every write made by the application from here on is recorded in the audit log.
+    let db = Arc::new(Audited::new(db, None));
+
     // Create dummy data
     if args.dummy_db {
-        database::dummy().await;
+        seed::seed(&*db, &args.fixtures, args.dummy_accounts, args.seed).await.expect("Failed to seed database!");
     }
 
+    // Remove expired entries, such as old sessions, in the background.
+    expiry::spawn_sweeper(db.clone(), expiry::SWEEP_INTERVAL);
+
     let mut app = tide::with_state(ApplicationState {
         hb: Arc::new(Mutex::new(handlebars::Handlebars::new())),
+        db,
+        search,
     });
 
     // Setup middleware
@@ -1,6 +1,6 @@
 use tide::{Middleware, Request, Next, Result};
 
-use crate::core::sessions;
+use crate::core::{sessions, state::ApplicationState};
 use super::MiddlewareData;
 
 pub struct UserSessionMiddleware;
@@ -12,10 +12,11 @@ impl UserSessionMiddleware {
 }
 
 #[tide::utils::async_trait]
-impl<State: Clone + Send + Sync + 'static> Middleware<State> for UserSessionMiddleware {
-    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> Result {
+impl Middleware<ApplicationState> for UserSessionMiddleware {
+    async fn handle(&self, mut req: Request<ApplicationState>, next: Next<'_, ApplicationState>) -> Result {
         let tide_session = req.session().id().to_string();
-        if let Some(session) = sessions::get(tide_session.clone()).await {
+        let db = req.state().db.clone();
+        if let Some(session) = sessions::get(&*db, tide_session.clone()).await {
             log::debug!("Session exists {tide_session}" );
 
             // Retrieve existing middleware data if present, otherwise create new.
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use tide::{Result, prelude::json, Response};
 
-use crate::{routes::Route, core::{state::ApplicationState, accounts, models::account::Account, sessions}};
+use crate::{routes::Route, core::{state::ApplicationState, accounts, ext::tide_request::TideRequestExt, models::account::Account, sessions}};
 
 pub struct AccountAPI;
 
@@ -27,12 +27,15 @@ impl AccountAPI {
         let mut error = String::new();
 
         let pass_hash = accounts::hash_password(account.password);
+        let db = req.state().db.clone();
 
-        match accounts::login(account.email, pass_hash).await {
+        match accounts::login(&*db, account.email, pass_hash).await {
             Ok(uid) => {
                 success = true;
                 let session_id = req.session().id().to_string();
-                if let Err(err) = sessions::create(session_id, uid.clone()).await {
+                // The session is created on behalf of the user who just signed in.
+                let audited = db.as_user(Some(uid.clone()));
+                if let Err(err) = sessions::create(&audited, session_id, uid.clone()).await {
                     log::error!("Failed to create session for UID {uid}: {err}");
                 }
                 else {
@@ -65,7 +68,8 @@ impl AccountAPI {
 
         let user = Account::partial(info.firstname, info.surname, info.email, info.password);
 
-        match accounts::register(user, true, true).await {
+        let audited = req.state().db.as_user(req.uid());
+        match accounts::register(&audited, user, true, true).await {
             Ok(_) => success = true,
             Err(err) => error = err,
         };
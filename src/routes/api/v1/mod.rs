@@ -1,4 +1,5 @@
 pub mod account;
+pub mod search;
 
 use crate::{routes::Route, core::state::ApplicationState};
 
@@ -8,6 +9,7 @@ impl Route for ApiV1 {
     fn register(app: &mut tide::Server<ApplicationState>) {
         let mut api = tide::with_state(app.state().clone());
         account::AccountAPI::register(&mut api);
+        search::SearchAPI::register(&mut api);
         app.at("/_api/v1").nest(api);
     }
 }
\ No newline at end of file
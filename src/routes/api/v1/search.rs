@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+use tide::{Result, prelude::json, Response};
+
+use crate::{routes::Route, core::{state::ApplicationState, database::DatabaseModel, models::article::Article}};
+
+/// Most results returned at once.
+const MAX_LIMIT: usize = 50;
+
+pub struct SearchAPI;
+
+impl Route for SearchAPI {
+    fn register(app: &mut tide::Server<ApplicationState>) {
+        app.at("/articles/search").post(SearchAPI::search_articles);
+    }
+}
+
+impl SearchAPI {
+    async fn search_articles(mut req: tide::Request<ApplicationState>) -> Result {
+        let query = match req.body_json::<SearchQuery>().await {
+            Ok(query) => query,
+            Err(_) => {
+                return Ok(Response::builder(403)
+                    .body("Failed to parse search query.")
+                    .build());
+            }
+        };
+
+        let limit = query.limit.unwrap_or(MAX_LIMIT).min(MAX_LIMIT);
+        match req.state().search.search(Article::table(), &query.q, Some(limit), query.offset.unwrap_or(0)).await {
+            Ok(result) => Ok(
+                json!({
+                    "total": result.total,
+                    "results": result.entries.into_iter()
+                        .map(|hit| json!({ "id": hit.id, "score": hit.score, "article": hit.entry }))
+                        .collect::<Vec<_>>()
+                }).into()
+            ),
+            Err(err) => {
+                log::error!("Failed to search articles: {err}");
+                Ok(Response::builder(500)
+                    .body("Failed to search articles.")
+                    .build())
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct SearchQuery {
+    pub q: String,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>
+}
@@ -10,7 +10,7 @@ impl Route for LoginView {
         app.state().hb.lock().unwrap().register_template_file("login", get_template_path("/pages/login.hbs")).unwrap();
 
         app.at("/login").get(|req: tide::Request<ApplicationState>| async move {
-            if let Some(_) = sessions::get(req.session().id().to_string()).await {
+            if sessions::get(&*req.state().db, req.session().id().to_string()).await.is_some() {
                 log::debug!("Existing session found, redirecting to home...");
                 // Redirect to index if user has an existing session.
                 return Ok(